@@ -1,5 +1,361 @@
 CREATE SCHEMA IF NOT EXISTS api;
 
+CREATE OR REPLACE FUNCTION api.fun_user_add_lead(a_email varchar, a_username varchar, a_title varchar, a_payload varchar, a_tenant varchar DEFAULT NULL)
+RETURNS table (
+    "lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY INSERT INTO tbl.lead (email, username, title, payload, tenant, status, attempts, next_attempt_at, created_at, updated_at)
+    VALUES (a_email, a_username, a_title, a_payload, a_tenant, 'pending', 0,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_claim_due_leads(a_limit int, a_lease_ms bigint)
+RETURNS table (
+    "lead_id" bigint,
+    "payload" varchar,
+    "attempts" int,
+    "pipedrive_lead_id" varchar,
+    "tenant" varchar,
+    "note_created" boolean,
+    "leased_until" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + a_lease_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id IN (
+        SELECT d.pkey_id
+        FROM tbl.lead AS d
+        WHERE d.status = 'pending'
+          AND d.next_attempt_at <= (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+        ORDER BY d.next_attempt_at
+        LIMIT a_limit
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING l.pkey_id, l.payload, l.attempts, l.pipedrive_lead_id, l.tenant, l.note_created, l.next_attempt_at;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_renew_lead_lease(a_lead_id bigint, a_leased_until bigint, a_lease_ms bigint)
+RETURNS table (
+    "leased_until" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + a_lease_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = a_lead_id
+      AND l.status = 'pending'
+      AND l.next_attempt_at = a_leased_until
+    RETURNING l.next_attempt_at;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_set_lead_pipedrive_lead_id(a_lead_id bigint, a_pipedrive_lead_id varchar, a_pipedrive_person_id bigint DEFAULT NULL)
+RETURNS void
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    UPDATE tbl.lead
+    SET pipedrive_lead_id = a_pipedrive_lead_id,
+        pipedrive_person_id = a_pipedrive_person_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = a_lead_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_set_lead_note_created(a_lead_id bigint)
+RETURNS void
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    UPDATE tbl.lead
+    SET note_created = TRUE,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = a_lead_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_mark_lead_delivered(a_lead_id bigint, a_pipedrive_lead_id varchar)
+RETURNS void
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    UPDATE tbl.lead
+    SET status = 'delivered',
+        attempts = attempts + 1,
+        last_error = NULL,
+        pipedrive_lead_id = a_pipedrive_lead_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = a_lead_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_mark_lead_failed(a_lead_id bigint, a_error varchar, a_retry_delay_ms bigint, a_max_attempts int, a_error_info varchar DEFAULT NULL)
+RETURNS table (
+    "status" enum_lead_status
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET attempts = l.attempts + 1,
+        status = CASE WHEN l.attempts + 1 >= a_max_attempts THEN 'dead_letter'::enum_lead_status ELSE l.status END,
+        last_error = a_error,
+        last_error_info = a_error_info,
+        next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + a_retry_delay_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = a_lead_id
+    RETURNING l.status;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_list_dead_letter_leads()
+RETURNS table (
+    "lead_id" bigint,
+    "email" varchar,
+    "username" varchar,
+    "title" varchar,
+    "attempts" int,
+    "error" varchar,
+    "error_info" varchar,
+    "tenant" varchar,
+    "created_at" bigint,
+    "updated_at" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY SELECT l.pkey_id, l.email, l.username, l.title, l.attempts, l.last_error, l.last_error_info, l.tenant, l.created_at, l.updated_at
+    FROM tbl.lead AS l
+    WHERE l.status = 'dead_letter'
+    ORDER BY l.pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_replay_dead_letter_leads(a_lead_id bigint DEFAULT NULL)
+RETURNS table (
+    "lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET status = 'pending',
+        attempts = 0,
+        next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.status = 'dead_letter'
+      AND (a_lead_id IS NULL OR l.pkey_id = a_lead_id)
+    RETURNING l.pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_discard_dead_letter_leads(a_lead_id bigint DEFAULT NULL)
+RETURNS table (
+    "lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET status = 'discarded',
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.status = 'dead_letter'
+      AND (a_lead_id IS NULL OR l.pkey_id = a_lead_id)
+    RETURNING l.pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_get_pipedrive_oauth_token(a_tenant varchar, a_client_id varchar)
+RETURNS table (
+    "access_token" varchar,
+    "refresh_token" varchar,
+    "expires_at" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY SELECT t.access_token, t.refresh_token, t.expires_at
+    FROM tbl.pipedrive_oauth_token AS t
+    WHERE t.tenant = a_tenant
+      AND t.client_id = a_client_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_save_pipedrive_oauth_token(a_tenant varchar, a_client_id varchar, a_access_token varchar, a_refresh_token varchar, a_expires_at bigint)
+RETURNS void
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    INSERT INTO tbl.pipedrive_oauth_token (tenant, client_id, access_token, refresh_token, expires_at, updated_at)
+    VALUES (a_tenant, a_client_id, a_access_token, a_refresh_token, a_expires_at, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    ON CONFLICT (tenant, client_id) DO UPDATE
+    SET access_token = EXCLUDED.access_token,
+        refresh_token = EXCLUDED.refresh_token,
+        expires_at = EXCLUDED.expires_at,
+        updated_at = EXCLUDED.updated_at;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_list_tenants()
+RETURNS table (
+    "name" varchar,
+    "form_key" varchar,
+    "config" varchar
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY SELECT t.name, t.form_key, t.config
+    FROM tbl.tenant AS t
+    ORDER BY t.name;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_add_pipedrive_webhook_event(a_tenant varchar, a_action varchar, a_object varchar, a_payload varchar, a_object_id varchar DEFAULT NULL)
+RETURNS table (
+    "event_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY INSERT INTO tbl.pipedrive_webhook_event (tenant, action, object, object_id, payload, received_at)
+    VALUES (a_tenant, a_action, a_object, a_object_id, a_payload, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_update_lead_from_pipedrive_lead(a_tenant varchar, a_pipedrive_lead_id varchar, a_pipedrive_status enum_pipedrive_status, a_pipedrive_deal_id bigint DEFAULT NULL)
+RETURNS table (
+    "lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    -- leads queued before tenants existed have no tenant and belong to the default one
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_status = a_pipedrive_status,
+        pipedrive_deal_id = a_pipedrive_deal_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pipedrive_lead_id = a_pipedrive_lead_id
+      AND COALESCE(l.tenant, 'default') = a_tenant
+      AND l.pipedrive_deal_id IS NULL
+    RETURNING l.pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_update_lead_from_pipedrive_deal(a_tenant varchar, a_pipedrive_person_id bigint, a_pipedrive_deal_id bigint, a_pipedrive_status enum_pipedrive_status)
+RETURNS table (
+    "lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    -- leads already linked to the deal, by the conversion of the lead or an earlier deal event
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_status = a_pipedrive_status,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pipedrive_deal_id = a_pipedrive_deal_id
+      AND COALESCE(l.tenant, 'default') = a_tenant
+    RETURNING l.pkey_id;
+    IF FOUND THEN
+        RETURN;
+    END IF;
+    -- deals do not reference the lead they were converted from, so a deal nothing is linked to
+    -- yet takes the most recent lead of the person that is not linked to a deal either
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_deal_id = a_pipedrive_deal_id,
+        pipedrive_status = a_pipedrive_status,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = (
+        SELECT o.pkey_id
+        FROM tbl.lead AS o
+        WHERE o.pipedrive_person_id = a_pipedrive_person_id
+          AND COALESCE(o.tenant, 'default') = a_tenant
+          AND o.pipedrive_deal_id IS NULL
+        ORDER BY o.pkey_id DESC
+        LIMIT 1
+    )
+    RETURNING l.pkey_id;
+END
+        
+$$;
+        
+
+CREATE OR REPLACE FUNCTION api.fun_user_quarantine_lead(a_email varchar, a_payload varchar, a_reason varchar, a_client_ip varchar)
+RETURNS table (
+    "quarantined_lead_id" bigint
+)
+LANGUAGE plpgsql
+AS $$
+    
+BEGIN
+    RETURN QUERY INSERT INTO tbl.quarantined_lead (email, payload, reason, client_ip, created_at)
+    VALUES (a_email, a_payload, a_reason, a_client_ip, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        
+$$;
+        
+
 CREATE OR REPLACE FUNCTION api.USER_SERVICE()
 RETURNS table (
     "code" int
@@ -1,3 +1,5 @@
 CREATE TYPE enum_role AS ENUM ('guest', 'user', 'admin', 'owner', 'developer');
 CREATE TYPE enum_recovery_question_category AS ENUM ('childhood', 'education', 'family', 'favorite', 'first', 'personal', 'pet', 'work', 'historical');
+CREATE TYPE enum_lead_status AS ENUM ('pending', 'delivered', 'dead_letter', 'discarded');
+CREATE TYPE enum_pipedrive_status AS ENUM ('lead_open', 'lead_archived', 'lead_deleted', 'deal_open', 'deal_won', 'deal_lost', 'deal_deleted');
 CREATE TYPE enum_service AS ENUM ('user');
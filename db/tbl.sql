@@ -3,3 +3,81 @@
 
 create schema tbl;;
 
+-- tables
+-- Table: lead
+CREATE TABLE tbl.lead (
+    pkey_id bigserial  NOT NULL,
+    email varchar  NOT NULL,
+    username varchar  NOT NULL,
+    title varchar  NOT NULL,
+    payload varchar  NOT NULL,
+    status enum_lead_status  NOT NULL DEFAULT 'pending',
+    attempts int  NOT NULL DEFAULT 0,
+    next_attempt_at bigint  NOT NULL,
+    last_error varchar  NULL,
+    last_error_info varchar  NULL,
+    pipedrive_lead_id varchar  NULL,
+    pipedrive_person_id bigint  NULL,
+    note_created boolean  NOT NULL DEFAULT FALSE,
+    pipedrive_deal_id bigint  NULL,
+    pipedrive_status enum_pipedrive_status  NULL,
+    tenant varchar  NULL,
+    created_at bigint  NOT NULL,
+    updated_at bigint  NOT NULL,
+    CONSTRAINT lead_pk PRIMARY KEY (pkey_id)
+);
+
+CREATE INDEX lead_status_next_attempt_at_idx on tbl.lead (status ASC, next_attempt_at ASC);
+
+CREATE INDEX lead_pipedrive_lead_id_idx on tbl.lead (pipedrive_lead_id ASC);
+
+CREATE INDEX lead_pipedrive_person_id_idx on tbl.lead (pipedrive_person_id ASC);
+
+
+-- Table: tenant
+CREATE TABLE tbl.tenant (
+    pkey_id bigserial  NOT NULL,
+    name varchar  NOT NULL,
+    form_key varchar  NOT NULL,
+    config varchar  NOT NULL,
+    updated_at bigint  NOT NULL,
+    CONSTRAINT tenant_ak_1 UNIQUE (name) NOT DEFERRABLE  INITIALLY IMMEDIATE,
+    CONSTRAINT tenant_ak_2 UNIQUE (form_key) NOT DEFERRABLE  INITIALLY IMMEDIATE,
+    CONSTRAINT tenant_pk PRIMARY KEY (pkey_id)
+);
+
+-- Table: pipedrive_oauth_token
+CREATE TABLE tbl.pipedrive_oauth_token (
+    pkey_id bigserial  NOT NULL,
+    tenant varchar  NOT NULL,
+    client_id varchar  NOT NULL,
+    access_token varchar  NOT NULL,
+    refresh_token varchar  NOT NULL,
+    expires_at bigint  NOT NULL,
+    updated_at bigint  NOT NULL,
+    CONSTRAINT pipedrive_oauth_token_ak_1 UNIQUE (tenant, client_id) NOT DEFERRABLE  INITIALLY IMMEDIATE,
+    CONSTRAINT pipedrive_oauth_token_pk PRIMARY KEY (pkey_id)
+);
+
+-- Table: pipedrive_webhook_event
+CREATE TABLE tbl.pipedrive_webhook_event (
+    pkey_id bigserial  NOT NULL,
+    tenant varchar  NOT NULL,
+    action varchar  NOT NULL,
+    object varchar  NOT NULL,
+    object_id varchar  NULL,
+    payload varchar  NOT NULL,
+    received_at bigint  NOT NULL,
+    CONSTRAINT pipedrive_webhook_event_pk PRIMARY KEY (pkey_id)
+);
+
+-- Table: quarantined_lead
+CREATE TABLE tbl.quarantined_lead (
+    pkey_id bigserial  NOT NULL,
+    email varchar  NOT NULL,
+    payload varchar  NOT NULL,
+    reason varchar  NOT NULL,
+    client_ip varchar  NOT NULL,
+    created_at bigint  NOT NULL,
+    CONSTRAINT quarantined_lead_pk PRIMARY KEY (pkey_id)
+);
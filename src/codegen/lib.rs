@@ -34,9 +34,15 @@ pub struct ErrorMessage {
     #[serde(default)]
     pub symbol: String,
     pub message: String,
+    /// Status of the HTTP response carrying the error
+    #[serde(default = "default_http_status")]
+    pub http_status: u16,
     #[serde(default)]
     pub source: String,
 }
+fn default_http_status() -> u16 {
+    400
+}
 pub fn collect_rust_recursive_types(t: Type) -> Vec<Type> {
     match t {
         Type::Object { ref fields, .. } => {
@@ -50,6 +56,7 @@ pub fn collect_rust_recursive_types(t: Type) -> Vec<Type> {
             collect_rust_recursive_types(Type::object(name, fields))
         }
         Type::Vec(x) => collect_rust_recursive_types(*x),
+        Type::Optional(x) => collect_rust_recursive_types(*x),
         _ => vec![],
     }
 }
@@ -80,7 +87,7 @@ use tokio_postgres::types::*;
 use serde::*;
 use num_derive::FromPrimitive;
 use strum_macros::EnumString;
-use lib::error_code::ErrorCode;
+use lib::error_code::{ErrorCode, ErrorCodeInfo, LocalizedErrorMessages};
     "#
     )?;
 
@@ -109,6 +116,48 @@ use lib::error_code::ErrorCode;
             s.to_rust_decl()
         )?;
     }
+    writeln!(
+        &mut f,
+        "pub const ERROR_CODES: &[ErrorCodeInfo] = &[{}];",
+        errors
+            .codes
+            .iter()
+            .map(|x| format!(
+                "ErrorCodeInfo {{ code: {}, symbol: {:?}, message: {:?}, http_status: {} }}",
+                x.code,
+                x.symbol.to_case(Case::Pascal),
+                x.message,
+                x.http_status
+            ))
+            .join(",\n")
+    )?;
+    writeln!(
+        &mut f,
+        "pub const ERROR_MESSAGES: &[LocalizedErrorMessages] = &[{}];",
+        get_error_catalogs(root)?
+            .iter()
+            .map(|catalog| format!(
+                "LocalizedErrorMessages {{ language: {:?}, messages: &[{}] }}",
+                catalog.language,
+                catalog
+                    .codes
+                    .iter()
+                    .map(|x| format!("({}, {:?})", x.code, x.message))
+                    .join(", ")
+            ))
+            .join(",\n")
+    )?;
+    let http_statuses = errors
+        .codes
+        .iter()
+        .map(|x| {
+            format!(
+                "Self::{} => {},",
+                x.symbol.to_case(Case::Pascal),
+                x.http_status
+            )
+        })
+        .join("\n");
     let enum_ = Type::enum_(
         "ErrorCode",
         errors
@@ -132,7 +181,15 @@ impl Into<ErrorCode> for EnumErrorCode {{
         ErrorCode::new(self as _)
     }}
 }}
-    "#
+impl EnumErrorCode {{
+    pub fn http_status(self) -> u16 {{
+        match self {{
+            {}
+        }}
+    }}
+}}
+    "#,
+        http_statuses
     )?;
 
     for s in services::get_services() {
@@ -413,6 +470,42 @@ pub fn get_error_messages(root: &str) -> Result<ErrorMessages> {
     let definitions: ErrorMessages = serde_json::from_slice(&def_file)?;
     Ok(definitions)
 }
+/// Every `error_codes_<lang>.json`, English first. Translations must define the same codes.
+pub fn get_error_catalogs(root: &str) -> Result<Vec<ErrorMessages>> {
+    let english = get_error_messages(root)?;
+    let codes: Vec<i64> = english.codes.iter().map(|x| x.code).sorted().collect();
+    let mut catalogs = vec![];
+    for entry in std::fs::read_dir(format!("{}/docs/error_codes", root))? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let language = match file_name
+            .strip_prefix("error_codes_")
+            .and_then(|x| x.strip_suffix(".json"))
+        {
+            Some(language) if language != english.language => language.to_owned(),
+            _ => continue,
+        };
+        let catalog: ErrorMessages = serde_json::from_slice(&std::fs::read(&path)?)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if catalog.language != language {
+            bail!("{} declares language {}", file_name, catalog.language);
+        }
+        let translated: Vec<i64> = catalog.codes.iter().map(|x| x.code).sorted().collect();
+        if translated != codes {
+            bail!(
+                "{} does not define the same codes as error_codes_{}.json, missing {:?}, extra {:?}",
+                file_name,
+                english.language,
+                codes.iter().filter(|x| !translated.contains(x)).collect::<Vec<_>>(),
+                translated.iter().filter(|x| !codes.contains(x)).collect::<Vec<_>>()
+            );
+        }
+        catalogs.push(catalog);
+    }
+    catalogs.sort_by(|a, b| a.language.cmp(&b.language));
+    catalogs.insert(0, english);
+    Ok(catalogs)
+}
 pub fn gen_error_message_md(root: &str) -> Result<()> {
     let definitions = get_error_messages(root)?;
     let doc_filename = format!("{}/docs/error_codes/error_codes.md", root);
@@ -421,14 +514,14 @@ pub fn gen_error_message_md(root: &str) -> Result<()> {
         &mut doc_file,
         r#"
 # Error Messages
-|Error Code|Error Symbol|Error Message|Error Source|
-|----------|------------|-------------|------------|"#,
+|Error Code|Error Symbol|Error Message|HTTP Status|Error Source|
+|----------|------------|-------------|-----------|------------|"#,
     )?;
     for item in definitions.codes {
         writeln!(
             &mut doc_file,
-            "|{}|{}|{}|{}|",
-            item.code, item.symbol, item.message, item.source
+            "|{}|{}|{}|{}|{}|",
+            item.code, item.symbol, item.message, item.http_status, item.source
         )?;
     }
     Ok(())
@@ -456,9 +549,7 @@ pub fn main() -> Result<()> {
         root,
         "pipedrive_gw",
         "gw",
-        HashMap::from([
-            ("user".to_owned(), "pipedrive_gw.defi.digital".to_owned()),
-        ]),
+        HashMap::from([("user".to_owned(), "pipedrive_gw.defi.digital".to_owned())]),
     )?;
     gen_error_message_md(root)?;
     Ok(())
@@ -21,3 +21,59 @@ impl UserClient {
         self.client.request(20660, req).await
     }
 }
+impl UserClient {
+    pub async fn list_dead_letter_leads(
+        &mut self,
+        req: &ListDeadLetterLeadsRequest,
+    ) -> Result<ListDeadLetterLeadsResponse> {
+        self.client.request(20661, req).await
+    }
+}
+impl UserClient {
+    pub async fn replay_dead_letter_leads(
+        &mut self,
+        req: &ReplayDeadLetterLeadsRequest,
+    ) -> Result<ReplayDeadLetterLeadsResponse> {
+        self.client.request(20662, req).await
+    }
+}
+impl UserClient {
+    pub async fn discard_dead_letter_leads(
+        &mut self,
+        req: &DiscardDeadLetterLeadsRequest,
+    ) -> Result<DiscardDeadLetterLeadsResponse> {
+        self.client.request(20663, req).await
+    }
+}
+impl UserClient {
+    pub async fn pipedrive_webhook(
+        &mut self,
+        req: &PipedriveWebhookRequest,
+    ) -> Result<PipedriveWebhookResponse> {
+        self.client.request(20664, req).await
+    }
+}
+impl UserClient {
+    pub async fn subscribe_pipedrive_events(
+        &mut self,
+        req: &SubscribePipedriveEventsRequest,
+    ) -> Result<SubscribePipedriveEventsResponse> {
+        self.client.request(20665, req).await
+    }
+}
+impl UserClient {
+    pub async fn issue_form_token(
+        &mut self,
+        req: &IssueFormTokenRequest,
+    ) -> Result<IssueFormTokenResponse> {
+        self.client.request(20666, req).await
+    }
+}
+impl UserClient {
+    pub async fn issue_pow_challenge(
+        &mut self,
+        req: &IssuePowChallengeRequest,
+    ) -> Result<IssuePowChallengeResponse> {
+        self.client.request(20667, req).await
+    }
+}
@@ -17,3 +17,602 @@ impl From<SimpleDbClient> for DbClient {
         Self::new(client)
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddLeadReq {
+    pub email: String,
+    pub username: String,
+    pub title: String,
+    pub payload: String,
+    pub tenant: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddLeadRespRow {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddLeadResp {
+    pub rows: Vec<FunUserAddLeadRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_add_lead(&self, req: FunUserAddLeadReq) -> Result<FunUserAddLeadResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_add_lead(a_email => $1::varchar, a_username => $2::varchar, a_title => $3::varchar, a_payload => $4::varchar, a_tenant => $5::varchar);", &[&req.email, &req.username, &req.title, &req.payload, &req.tenant]).await?;
+        let mut resp = FunUserAddLeadResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserAddLeadRespRow {
+                lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserClaimDueLeadsReq {
+    pub limit: i32,
+    pub lease_ms: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserClaimDueLeadsRespRow {
+    pub lead_id: i64,
+    pub payload: String,
+    pub attempts: i32,
+    pub pipedrive_lead_id: Option<String>,
+    pub tenant: Option<String>,
+    pub note_created: bool,
+    pub leased_until: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserClaimDueLeadsResp {
+    pub rows: Vec<FunUserClaimDueLeadsRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_claim_due_leads(
+        &self,
+        req: FunUserClaimDueLeadsReq,
+    ) -> Result<FunUserClaimDueLeadsResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_claim_due_leads(a_limit => $1::int, a_lease_ms => $2::bigint);", &[&req.limit, &req.lease_ms]).await?;
+        let mut resp = FunUserClaimDueLeadsResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserClaimDueLeadsRespRow {
+                lead_id: row.try_get(0)?,
+                payload: row.try_get(1)?,
+                attempts: row.try_get(2)?,
+                pipedrive_lead_id: row.try_get(3)?,
+                tenant: row.try_get(4)?,
+                note_created: row.try_get(5)?,
+                leased_until: row.try_get(6)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserRenewLeadLeaseReq {
+    pub lead_id: i64,
+    pub leased_until: i64,
+    pub lease_ms: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserRenewLeadLeaseRespRow {
+    pub leased_until: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserRenewLeadLeaseResp {
+    pub rows: Vec<FunUserRenewLeadLeaseRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_renew_lead_lease(
+        &self,
+        req: FunUserRenewLeadLeaseReq,
+    ) -> Result<FunUserRenewLeadLeaseResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_renew_lead_lease(a_lead_id => $1::bigint, a_leased_until => $2::bigint, a_lease_ms => $3::bigint);", &[&req.lead_id, &req.leased_until, &req.lease_ms]).await?;
+        let mut resp = FunUserRenewLeadLeaseResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserRenewLeadLeaseRespRow {
+                leased_until: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadPipedriveLeadIdReq {
+    pub lead_id: i64,
+    pub pipedrive_lead_id: String,
+    pub pipedrive_person_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadPipedriveLeadIdRespRow {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadPipedriveLeadIdResp {
+    pub rows: Vec<FunUserSetLeadPipedriveLeadIdRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_set_lead_pipedrive_lead_id(
+        &self,
+        req: FunUserSetLeadPipedriveLeadIdReq,
+    ) -> Result<FunUserSetLeadPipedriveLeadIdResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_set_lead_pipedrive_lead_id(a_lead_id => $1::bigint, a_pipedrive_lead_id => $2::varchar, a_pipedrive_person_id => $3::bigint);", &[&req.lead_id, &req.pipedrive_lead_id, &req.pipedrive_person_id]).await?;
+        let mut resp = FunUserSetLeadPipedriveLeadIdResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserSetLeadPipedriveLeadIdRespRow {};
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadNoteCreatedReq {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadNoteCreatedRespRow {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSetLeadNoteCreatedResp {
+    pub rows: Vec<FunUserSetLeadNoteCreatedRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_set_lead_note_created(
+        &self,
+        req: FunUserSetLeadNoteCreatedReq,
+    ) -> Result<FunUserSetLeadNoteCreatedResp> {
+        let rows = self
+            .client
+            .query(
+                "SELECT * FROM api.fun_user_set_lead_note_created(a_lead_id => $1::bigint);",
+                &[&req.lead_id],
+            )
+            .await?;
+        let mut resp = FunUserSetLeadNoteCreatedResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserSetLeadNoteCreatedRespRow {};
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadDeliveredReq {
+    pub lead_id: i64,
+    pub pipedrive_lead_id: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadDeliveredRespRow {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadDeliveredResp {
+    pub rows: Vec<FunUserMarkLeadDeliveredRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_mark_lead_delivered(
+        &self,
+        req: FunUserMarkLeadDeliveredReq,
+    ) -> Result<FunUserMarkLeadDeliveredResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_mark_lead_delivered(a_lead_id => $1::bigint, a_pipedrive_lead_id => $2::varchar);", &[&req.lead_id, &req.pipedrive_lead_id]).await?;
+        let mut resp = FunUserMarkLeadDeliveredResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserMarkLeadDeliveredRespRow {};
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadFailedReq {
+    pub lead_id: i64,
+    pub error: String,
+    pub retry_delay_ms: i64,
+    pub max_attempts: i32,
+    pub error_info: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadFailedRespRow {
+    pub status: EnumLeadStatus,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserMarkLeadFailedResp {
+    pub rows: Vec<FunUserMarkLeadFailedRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_mark_lead_failed(
+        &self,
+        req: FunUserMarkLeadFailedReq,
+    ) -> Result<FunUserMarkLeadFailedResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_mark_lead_failed(a_lead_id => $1::bigint, a_error => $2::varchar, a_retry_delay_ms => $3::bigint, a_max_attempts => $4::int, a_error_info => $5::varchar);", &[&req.lead_id, &req.error, &req.retry_delay_ms, &req.max_attempts, &req.error_info]).await?;
+        let mut resp = FunUserMarkLeadFailedResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserMarkLeadFailedRespRow {
+                status: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListDeadLetterLeadsReq {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListDeadLetterLeadsRespRow {
+    pub lead_id: i64,
+    pub email: String,
+    pub username: String,
+    pub title: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub error_info: Option<String>,
+    pub tenant: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListDeadLetterLeadsResp {
+    pub rows: Vec<FunUserListDeadLetterLeadsRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_list_dead_letter_leads(
+        &self,
+        req: FunUserListDeadLetterLeadsReq,
+    ) -> Result<FunUserListDeadLetterLeadsResp> {
+        let rows = self
+            .client
+            .query("SELECT * FROM api.fun_user_list_dead_letter_leads();", &[])
+            .await?;
+        let mut resp = FunUserListDeadLetterLeadsResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserListDeadLetterLeadsRespRow {
+                lead_id: row.try_get(0)?,
+                email: row.try_get(1)?,
+                username: row.try_get(2)?,
+                title: row.try_get(3)?,
+                attempts: row.try_get(4)?,
+                error: row.try_get(5)?,
+                error_info: row.try_get(6)?,
+                tenant: row.try_get(7)?,
+                created_at: row.try_get(8)?,
+                updated_at: row.try_get(9)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserReplayDeadLetterLeadsReq {
+    pub lead_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserReplayDeadLetterLeadsRespRow {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserReplayDeadLetterLeadsResp {
+    pub rows: Vec<FunUserReplayDeadLetterLeadsRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_replay_dead_letter_leads(
+        &self,
+        req: FunUserReplayDeadLetterLeadsReq,
+    ) -> Result<FunUserReplayDeadLetterLeadsResp> {
+        let rows = self
+            .client
+            .query(
+                "SELECT * FROM api.fun_user_replay_dead_letter_leads(a_lead_id => $1::bigint);",
+                &[&req.lead_id],
+            )
+            .await?;
+        let mut resp = FunUserReplayDeadLetterLeadsResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserReplayDeadLetterLeadsRespRow {
+                lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserDiscardDeadLetterLeadsReq {
+    pub lead_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserDiscardDeadLetterLeadsRespRow {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserDiscardDeadLetterLeadsResp {
+    pub rows: Vec<FunUserDiscardDeadLetterLeadsRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_discard_dead_letter_leads(
+        &self,
+        req: FunUserDiscardDeadLetterLeadsReq,
+    ) -> Result<FunUserDiscardDeadLetterLeadsResp> {
+        let rows = self
+            .client
+            .query(
+                "SELECT * FROM api.fun_user_discard_dead_letter_leads(a_lead_id => $1::bigint);",
+                &[&req.lead_id],
+            )
+            .await?;
+        let mut resp = FunUserDiscardDeadLetterLeadsResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserDiscardDeadLetterLeadsRespRow {
+                lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserGetPipedriveOauthTokenReq {
+    pub tenant: String,
+    pub client_id: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserGetPipedriveOauthTokenRespRow {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserGetPipedriveOauthTokenResp {
+    pub rows: Vec<FunUserGetPipedriveOauthTokenRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_get_pipedrive_oauth_token(
+        &self,
+        req: FunUserGetPipedriveOauthTokenReq,
+    ) -> Result<FunUserGetPipedriveOauthTokenResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_get_pipedrive_oauth_token(a_tenant => $1::varchar, a_client_id => $2::varchar);", &[&req.tenant, &req.client_id]).await?;
+        let mut resp = FunUserGetPipedriveOauthTokenResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserGetPipedriveOauthTokenRespRow {
+                access_token: row.try_get(0)?,
+                refresh_token: row.try_get(1)?,
+                expires_at: row.try_get(2)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSavePipedriveOauthTokenReq {
+    pub tenant: String,
+    pub client_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSavePipedriveOauthTokenRespRow {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserSavePipedriveOauthTokenResp {
+    pub rows: Vec<FunUserSavePipedriveOauthTokenRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_save_pipedrive_oauth_token(
+        &self,
+        req: FunUserSavePipedriveOauthTokenReq,
+    ) -> Result<FunUserSavePipedriveOauthTokenResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_save_pipedrive_oauth_token(a_tenant => $1::varchar, a_client_id => $2::varchar, a_access_token => $3::varchar, a_refresh_token => $4::varchar, a_expires_at => $5::bigint);", &[&req.tenant, &req.client_id, &req.access_token, &req.refresh_token, &req.expires_at]).await?;
+        let mut resp = FunUserSavePipedriveOauthTokenResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserSavePipedriveOauthTokenRespRow {};
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListTenantsReq {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListTenantsRespRow {
+    pub name: String,
+    pub form_key: String,
+    pub config: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserListTenantsResp {
+    pub rows: Vec<FunUserListTenantsRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_list_tenants(
+        &self,
+        req: FunUserListTenantsReq,
+    ) -> Result<FunUserListTenantsResp> {
+        let rows = self
+            .client
+            .query("SELECT * FROM api.fun_user_list_tenants();", &[])
+            .await?;
+        let mut resp = FunUserListTenantsResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserListTenantsRespRow {
+                name: row.try_get(0)?,
+                form_key: row.try_get(1)?,
+                config: row.try_get(2)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddPipedriveWebhookEventReq {
+    pub tenant: String,
+    pub action: String,
+    pub object: String,
+    pub payload: String,
+    pub object_id: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddPipedriveWebhookEventRespRow {
+    pub event_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserAddPipedriveWebhookEventResp {
+    pub rows: Vec<FunUserAddPipedriveWebhookEventRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_add_pipedrive_webhook_event(
+        &self,
+        req: FunUserAddPipedriveWebhookEventReq,
+    ) -> Result<FunUserAddPipedriveWebhookEventResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_add_pipedrive_webhook_event(a_tenant => $1::varchar, a_action => $2::varchar, a_object => $3::varchar, a_payload => $4::varchar, a_object_id => $5::varchar);", &[&req.tenant, &req.action, &req.object, &req.payload, &req.object_id]).await?;
+        let mut resp = FunUserAddPipedriveWebhookEventResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserAddPipedriveWebhookEventRespRow {
+                event_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveLeadReq {
+    pub tenant: String,
+    pub pipedrive_lead_id: String,
+    pub pipedrive_status: EnumPipedriveStatus,
+    pub pipedrive_deal_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveLeadRespRow {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveLeadResp {
+    pub rows: Vec<FunUserUpdateLeadFromPipedriveLeadRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_update_lead_from_pipedrive_lead(
+        &self,
+        req: FunUserUpdateLeadFromPipedriveLeadReq,
+    ) -> Result<FunUserUpdateLeadFromPipedriveLeadResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_update_lead_from_pipedrive_lead(a_tenant => $1::varchar, a_pipedrive_lead_id => $2::varchar, a_pipedrive_status => $3::enum_pipedrive_status, a_pipedrive_deal_id => $4::bigint);", &[&req.tenant, &req.pipedrive_lead_id, &req.pipedrive_status, &req.pipedrive_deal_id]).await?;
+        let mut resp = FunUserUpdateLeadFromPipedriveLeadResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserUpdateLeadFromPipedriveLeadRespRow {
+                lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveDealReq {
+    pub tenant: String,
+    pub pipedrive_person_id: i64,
+    pub pipedrive_deal_id: i64,
+    pub pipedrive_status: EnumPipedriveStatus,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveDealRespRow {
+    pub lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserUpdateLeadFromPipedriveDealResp {
+    pub rows: Vec<FunUserUpdateLeadFromPipedriveDealRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_update_lead_from_pipedrive_deal(
+        &self,
+        req: FunUserUpdateLeadFromPipedriveDealReq,
+    ) -> Result<FunUserUpdateLeadFromPipedriveDealResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_update_lead_from_pipedrive_deal(a_tenant => $1::varchar, a_pipedrive_person_id => $2::bigint, a_pipedrive_deal_id => $3::bigint, a_pipedrive_status => $4::enum_pipedrive_status);", &[&req.tenant, &req.pipedrive_person_id, &req.pipedrive_deal_id, &req.pipedrive_status]).await?;
+        let mut resp = FunUserUpdateLeadFromPipedriveDealResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserUpdateLeadFromPipedriveDealRespRow {
+                lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserQuarantineLeadReq {
+    pub email: String,
+    pub payload: String,
+    pub reason: String,
+    pub client_ip: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserQuarantineLeadRespRow {
+    pub quarantined_lead_id: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunUserQuarantineLeadResp {
+    pub rows: Vec<FunUserQuarantineLeadRespRow>,
+}
+impl DbClient {
+    #[allow(unused_variables)]
+    pub async fn fun_user_quarantine_lead(
+        &self,
+        req: FunUserQuarantineLeadReq,
+    ) -> Result<FunUserQuarantineLeadResp> {
+        let rows = self.client.query("SELECT * FROM api.fun_user_quarantine_lead(a_email => $1::varchar, a_payload => $2::varchar, a_reason => $3::varchar, a_client_ip => $4::varchar);", &[&req.email, &req.payload, &req.reason, &req.client_ip]).await?;
+        let mut resp = FunUserQuarantineLeadResp {
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let r = FunUserQuarantineLeadRespRow {
+                quarantined_lead_id: row.try_get(0)?,
+            };
+            resp.rows.push(r);
+        }
+        Ok(resp)
+    }
+}
@@ -1,4 +1,4 @@
-use lib::error_code::ErrorCode;
+use lib::error_code::{ErrorCode, ErrorCodeInfo, LocalizedErrorMessages};
 use num_derive::FromPrimitive;
 use serde::*;
 use strum_macros::EnumString;
@@ -60,6 +60,51 @@ pub enum EnumRecoveryQuestionCategory {
 #[derive(
     Debug, Clone, Copy, ToSql, FromSql, Serialize, Deserialize, FromPrimitive, PartialEq, EnumString,
 )]
+#[postgres(name = "enum_lead_status")]
+pub enum EnumLeadStatus {
+    ///
+    #[postgres(name = "pending")]
+    Pending = 0,
+    ///
+    #[postgres(name = "delivered")]
+    Delivered = 1,
+    ///
+    #[postgres(name = "dead_letter")]
+    DeadLetter = 2,
+    ///
+    #[postgres(name = "discarded")]
+    Discarded = 3,
+}
+#[derive(
+    Debug, Clone, Copy, ToSql, FromSql, Serialize, Deserialize, FromPrimitive, PartialEq, EnumString,
+)]
+#[postgres(name = "enum_pipedrive_status")]
+pub enum EnumPipedriveStatus {
+    ///
+    #[postgres(name = "lead_open")]
+    LeadOpen = 0,
+    ///
+    #[postgres(name = "lead_archived")]
+    LeadArchived = 1,
+    ///
+    #[postgres(name = "lead_deleted")]
+    LeadDeleted = 2,
+    ///
+    #[postgres(name = "deal_open")]
+    DealOpen = 3,
+    ///
+    #[postgres(name = "deal_won")]
+    DealWon = 4,
+    ///
+    #[postgres(name = "deal_lost")]
+    DealLost = 5,
+    ///
+    #[postgres(name = "deal_deleted")]
+    DealDeleted = 6,
+}
+#[derive(
+    Debug, Clone, Copy, ToSql, FromSql, Serialize, Deserialize, FromPrimitive, PartialEq, EnumString,
+)]
 #[postgres(name = "enum_service")]
 pub enum EnumService {
     ///
@@ -77,6 +122,11 @@ pub struct ErrorInternalServerError {}
 pub struct ErrorNotImplemented {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct ErrorOriginNotAllowed {
+    pub origin: String,
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorNotFound {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -89,6 +139,9 @@ pub struct ErrorInvalidService {}
 pub struct ErrorUserForbidden {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct ErrorUnauthorized {}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorUserNotFound {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -98,6 +151,17 @@ pub struct ErrorUserMustAgreeTos {}
 pub struct ErrorUserMustAgreePrivacyPolicy {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct ErrorPipedriveRateLimitExceeded {}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorUnknownFormKey {
+    pub form_key: String,
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorCaptchaFailed {}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorInvalidEnumLevel {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -210,6 +274,514 @@ pub struct ErrorAuthError {}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorInternalError {}
+pub const ERROR_CODES: &[ErrorCodeInfo] = &[
+    ErrorCodeInfo {
+        code: 100400,
+        symbol: "BadRequest",
+        message: "Bad Request",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 100500,
+        symbol: "InternalServerError",
+        message: "Internal Server Error",
+        http_status: 500,
+    },
+    ErrorCodeInfo {
+        code: 100501,
+        symbol: "NotImplemented",
+        message: "Method not implemented",
+        http_status: 501,
+    },
+    ErrorCodeInfo {
+        code: 100403,
+        symbol: "OriginNotAllowed",
+        message: "Origin {origin} is not allowed",
+        http_status: 403,
+    },
+    ErrorCodeInfo {
+        code: 100404,
+        symbol: "NotFound",
+        message: "NotFoundResource",
+        http_status: 404,
+    },
+    ErrorCodeInfo {
+        code: 100601,
+        symbol: "DatabaseError",
+        message: "Database error",
+        http_status: 500,
+    },
+    ErrorCodeInfo {
+        code: 100602,
+        symbol: "InvalidService",
+        message: "Invalid Service",
+        http_status: 404,
+    },
+    ErrorCodeInfo {
+        code: 101403,
+        symbol: "UserForbidden",
+        message: "Insufficient role for user",
+        http_status: 403,
+    },
+    ErrorCodeInfo {
+        code: 101401,
+        symbol: "Unauthorized",
+        message: "Missing or invalid credentials",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 101404,
+        symbol: "UserNotFound",
+        message: "User not found",
+        http_status: 404,
+    },
+    ErrorCodeInfo {
+        code: 101601,
+        symbol: "UserMustAgreeTos",
+        message: "Must agree to the terms of service",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 101602,
+        symbol: "UserMustAgreePrivacyPolicy",
+        message: "Must agree to the privacy policy",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 101429,
+        symbol: "PipedriveRateLimitExceeded",
+        message: "Pipedrive rate limit exceeded, try again later",
+        http_status: 429,
+    },
+    ErrorCodeInfo {
+        code: 101400,
+        symbol: "UnknownFormKey",
+        message: "Unknown form key {form_key}",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 101422,
+        symbol: "CaptchaFailed",
+        message: "CAPTCHA verification failed, please try again",
+        http_status: 422,
+    },
+    ErrorCodeInfo {
+        code: 3484946,
+        symbol: "InvalidEnumLevel",
+        message: "InvalidEnumLevel",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 4349632,
+        symbol: "Error",
+        message: "Error",
+        http_status: 500,
+    },
+    ErrorCodeInfo {
+        code: 45349633,
+        symbol: "InvalidArgument",
+        message: "InvalidArgument",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349634,
+        symbol: "InvalidState",
+        message: "InvalidState",
+        http_status: 409,
+    },
+    ErrorCodeInfo {
+        code: 45349635,
+        symbol: "InvalidSeq",
+        message: "InvalidSeq",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349636,
+        symbol: "InvalidMethod",
+        message: "InvalidMethod",
+        http_status: 404,
+    },
+    ErrorCodeInfo {
+        code: 45349637,
+        symbol: "ProtocolViolation",
+        message: "ProtocolViolation",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349638,
+        symbol: "MalformedRequest",
+        message: "MalformedRequest",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349639,
+        symbol: "UnknownUser",
+        message: "UnknownUser",
+        http_status: 404,
+    },
+    ErrorCodeInfo {
+        code: 45349640,
+        symbol: "BlockedUser",
+        message: "BlockedUser",
+        http_status: 403,
+    },
+    ErrorCodeInfo {
+        code: 45349641,
+        symbol: "InvalidPassword",
+        message: "InvalidPassword",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 45349642,
+        symbol: "InvalidToken",
+        message: "InvalidToken",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 45349643,
+        symbol: "TemporarilyUnavailable",
+        message: "TemporarilyUnavailable",
+        http_status: 503,
+    },
+    ErrorCodeInfo {
+        code: 45349644,
+        symbol: "UnexpectedException",
+        message: "UnexpectedException",
+        http_status: 500,
+    },
+    ErrorCodeInfo {
+        code: 45349645,
+        symbol: "BackPressureIncreased",
+        message: "BackPressureIncreased",
+        http_status: 503,
+    },
+    ErrorCodeInfo {
+        code: 45349646,
+        symbol: "InvalidPublicId",
+        message: "InvalidPublicId",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349647,
+        symbol: "InvalidRange",
+        message: "InvalidRange",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349648,
+        symbol: "BankAccountAlreadyExists",
+        message: "BankAccountAlreadyExists",
+        http_status: 409,
+    },
+    ErrorCodeInfo {
+        code: 45349649,
+        symbol: "InsufficientFunds",
+        message: "InsufficientFunds",
+        http_status: 402,
+    },
+    ErrorCodeInfo {
+        code: 45349654,
+        symbol: "LogicalError",
+        message: "LogicalError",
+        http_status: 500,
+    },
+    ErrorCodeInfo {
+        code: 45349655,
+        symbol: "RestrictedUserPrivileges",
+        message: "RestrictedUserPrivileges",
+        http_status: 403,
+    },
+    ErrorCodeInfo {
+        code: 45349656,
+        symbol: "IdenticalReplacement",
+        message: "IdenticalReplacement",
+        http_status: 409,
+    },
+    ErrorCodeInfo {
+        code: 45349659,
+        symbol: "InvalidRecoveryQuestions",
+        message: "InvalidRecoveryQuestions",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349660,
+        symbol: "InvalidRole",
+        message: "InvalidRole",
+        http_status: 403,
+    },
+    ErrorCodeInfo {
+        code: 45349661,
+        symbol: "WrongRecoveryAnswers",
+        message: "WrongRecoveryAnswers",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349662,
+        symbol: "MessageNotDelivered",
+        message: "MessageNotDelivered",
+        http_status: 502,
+    },
+    ErrorCodeInfo {
+        code: 45349663,
+        symbol: "NoReply",
+        message: "NoReply",
+        http_status: 504,
+    },
+    ErrorCodeInfo {
+        code: 45349664,
+        symbol: "NullAttribute",
+        message: "NullAttribute",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349665,
+        symbol: "ConsentMissing",
+        message: "ConsentMissing",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349666,
+        symbol: "ActiveSubscriptionRequired",
+        message: "ActiveSubscriptionRequired",
+        http_status: 402,
+    },
+    ErrorCodeInfo {
+        code: 45349667,
+        symbol: "UsernameAlreadyRegistered",
+        message: "UsernameAlreadyRegistered",
+        http_status: 409,
+    },
+    ErrorCodeInfo {
+        code: 45349668,
+        symbol: "RecoveryQuestionsNotSet",
+        message: "RecoveryQuestionsNotSet",
+        http_status: 409,
+    },
+    ErrorCodeInfo {
+        code: 45349669,
+        symbol: "MustSubmitAllRecoveryQuestions",
+        message: "MustSubmitAllRecoveryQuestions",
+        http_status: 400,
+    },
+    ErrorCodeInfo {
+        code: 45349670,
+        symbol: "InvalidRecoveryToken",
+        message: "InvalidRecoveryToken",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 45349676,
+        symbol: "RoutingError",
+        message: "RoutingError",
+        http_status: 502,
+    },
+    ErrorCodeInfo {
+        code: 45349677,
+        symbol: "UnauthorizedMessage",
+        message: "UnauthorizedMessage",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 45349679,
+        symbol: "AuthError",
+        message: "AuthError",
+        http_status: 401,
+    },
+    ErrorCodeInfo {
+        code: 45349684,
+        symbol: "InternalError",
+        message: "InternalError",
+        http_status: 500,
+    },
+];
+pub const ERROR_MESSAGES: &[LocalizedErrorMessages] = &[
+    LocalizedErrorMessages {
+        language: "en",
+        messages: &[
+            (100400, "Bad Request"),
+            (100500, "Internal Server Error"),
+            (100501, "Method not implemented"),
+            (100403, "Origin {origin} is not allowed"),
+            (100404, "NotFoundResource"),
+            (100601, "Database error"),
+            (100602, "Invalid Service"),
+            (101403, "Insufficient role for user"),
+            (101401, "Missing or invalid credentials"),
+            (101404, "User not found"),
+            (101601, "Must agree to the terms of service"),
+            (101602, "Must agree to the privacy policy"),
+            (101429, "Pipedrive rate limit exceeded, try again later"),
+            (101400, "Unknown form key {form_key}"),
+            (101422, "CAPTCHA verification failed, please try again"),
+            (3484946, "InvalidEnumLevel"),
+            (4349632, "Error"),
+            (45349633, "InvalidArgument"),
+            (45349634, "InvalidState"),
+            (45349635, "InvalidSeq"),
+            (45349636, "InvalidMethod"),
+            (45349637, "ProtocolViolation"),
+            (45349638, "MalformedRequest"),
+            (45349639, "UnknownUser"),
+            (45349640, "BlockedUser"),
+            (45349641, "InvalidPassword"),
+            (45349642, "InvalidToken"),
+            (45349643, "TemporarilyUnavailable"),
+            (45349644, "UnexpectedException"),
+            (45349645, "BackPressureIncreased"),
+            (45349646, "InvalidPublicId"),
+            (45349647, "InvalidRange"),
+            (45349648, "BankAccountAlreadyExists"),
+            (45349649, "InsufficientFunds"),
+            (45349654, "LogicalError"),
+            (45349655, "RestrictedUserPrivileges"),
+            (45349656, "IdenticalReplacement"),
+            (45349659, "InvalidRecoveryQuestions"),
+            (45349660, "InvalidRole"),
+            (45349661, "WrongRecoveryAnswers"),
+            (45349662, "MessageNotDelivered"),
+            (45349663, "NoReply"),
+            (45349664, "NullAttribute"),
+            (45349665, "ConsentMissing"),
+            (45349666, "ActiveSubscriptionRequired"),
+            (45349667, "UsernameAlreadyRegistered"),
+            (45349668, "RecoveryQuestionsNotSet"),
+            (45349669, "MustSubmitAllRecoveryQuestions"),
+            (45349670, "InvalidRecoveryToken"),
+            (45349676, "RoutingError"),
+            (45349677, "UnauthorizedMessage"),
+            (45349679, "AuthError"),
+            (45349684, "InternalError"),
+        ],
+    },
+    LocalizedErrorMessages {
+        language: "de",
+        messages: &[
+            (100400, "Ungültige Anfrage"),
+            (100500, "Interner Serverfehler"),
+            (100501, "Methode nicht implementiert"),
+            (100403, "Herkunft {origin} ist nicht erlaubt"),
+            (100404, "Ressource nicht gefunden"),
+            (100601, "Datenbankfehler"),
+            (100602, "Ungültiger Dienst"),
+            (101403, "Unzureichende Berechtigungen"),
+            (101401, "Fehlende oder ungültige Zugangsdaten"),
+            (101404, "Benutzer nicht gefunden"),
+            (101601, "Den Nutzungsbedingungen muss zugestimmt werden"),
+            (101602, "Der Datenschutzerklärung muss zugestimmt werden"),
+            (
+                101429,
+                "Pipedrive-Anfragelimit überschritten, bitte später erneut versuchen",
+            ),
+            (101400, "Unbekannter Formularschlüssel {form_key}"),
+            (
+                101422,
+                "CAPTCHA-Prüfung fehlgeschlagen, bitte erneut versuchen",
+            ),
+            (3484946, "Ungültiger Wert"),
+            (4349632, "Fehler"),
+            (45349633, "Ungültiges Argument"),
+            (45349634, "Ungültiger Zustand"),
+            (45349635, "Ungültige Sequenz"),
+            (45349636, "Ungültige Methode"),
+            (45349637, "Protokollverletzung"),
+            (45349638, "Fehlerhafte Anfrage"),
+            (45349639, "Unbekannter Benutzer"),
+            (45349640, "Benutzer gesperrt"),
+            (45349641, "Falsches Passwort"),
+            (45349642, "Ungültiges Token"),
+            (45349643, "Vorübergehend nicht verfügbar"),
+            (45349644, "Unerwartete Ausnahme"),
+            (45349645, "Dienst überlastet, bitte später erneut versuchen"),
+            (45349646, "Ungültige öffentliche ID"),
+            (45349647, "Ungültiger Bereich"),
+            (45349648, "Bankkonto existiert bereits"),
+            (45349649, "Unzureichendes Guthaben"),
+            (45349654, "Logischer Fehler"),
+            (45349655, "Eingeschränkte Benutzerrechte"),
+            (45349656, "Der neue Wert entspricht dem aktuellen"),
+            (45349659, "Ungültige Sicherheitsfragen"),
+            (45349660, "Ungültige Rolle"),
+            (45349661, "Falsche Antworten auf die Sicherheitsfragen"),
+            (45349662, "Nachricht nicht zugestellt"),
+            (45349663, "Keine Antwort"),
+            (45349664, "Ein Pflichtattribut fehlt"),
+            (45349665, "Einwilligung fehlt"),
+            (45349666, "Ein aktives Abonnement ist erforderlich"),
+            (45349667, "Benutzername bereits registriert"),
+            (45349668, "Keine Sicherheitsfragen festgelegt"),
+            (45349669, "Alle Sicherheitsfragen müssen beantwortet werden"),
+            (45349670, "Ungültiges Wiederherstellungstoken"),
+            (45349676, "Routing-Fehler"),
+            (45349677, "Nicht autorisierte Nachricht"),
+            (45349679, "Authentifizierungsfehler"),
+            (45349684, "Interner Fehler"),
+        ],
+    },
+    LocalizedErrorMessages {
+        language: "es",
+        messages: &[
+            (100400, "Solicitud incorrecta"),
+            (100500, "Error interno del servidor"),
+            (100501, "Método no implementado"),
+            (100403, "El origen {origin} no está permitido"),
+            (100404, "Recurso no encontrado"),
+            (100601, "Error de base de datos"),
+            (100602, "Servicio no válido"),
+            (101403, "Permisos insuficientes"),
+            (101401, "Credenciales ausentes o no válidas"),
+            (101404, "Usuario no encontrado"),
+            (101601, "Debe aceptar los términos del servicio"),
+            (101602, "Debe aceptar la política de privacidad"),
+            (
+                101429,
+                "Se superó el límite de solicitudes de Pipedrive, inténtelo más tarde",
+            ),
+            (101400, "Clave de formulario desconocida {form_key}"),
+            (101422, "La verificación CAPTCHA falló, inténtelo de nuevo"),
+            (3484946, "Valor no válido"),
+            (4349632, "Error"),
+            (45349633, "Argumento no válido"),
+            (45349634, "Estado no válido"),
+            (45349635, "Secuencia no válida"),
+            (45349636, "Método no válido"),
+            (45349637, "Violación del protocolo"),
+            (45349638, "Solicitud mal formada"),
+            (45349639, "Usuario desconocido"),
+            (45349640, "Usuario bloqueado"),
+            (45349641, "Contraseña incorrecta"),
+            (45349642, "Token no válido"),
+            (45349643, "No disponible temporalmente"),
+            (45349644, "Excepción inesperada"),
+            (45349645, "Servicio sobrecargado, inténtelo más tarde"),
+            (45349646, "Identificador público no válido"),
+            (45349647, "Rango no válido"),
+            (45349648, "La cuenta bancaria ya existe"),
+            (45349649, "Fondos insuficientes"),
+            (45349654, "Error lógico"),
+            (45349655, "Privilegios de usuario restringidos"),
+            (45349656, "El nuevo valor es idéntico al actual"),
+            (45349659, "Preguntas de recuperación no válidas"),
+            (45349660, "Rol no válido"),
+            (45349661, "Respuestas de recuperación incorrectas"),
+            (45349662, "Mensaje no entregado"),
+            (45349663, "Sin respuesta"),
+            (45349664, "Falta un atributo obligatorio"),
+            (45349665, "Falta el consentimiento"),
+            (45349666, "Se requiere una suscripción activa"),
+            (45349667, "El nombre de usuario ya está registrado"),
+            (45349668, "No se han configurado preguntas de recuperación"),
+            (
+                45349669,
+                "Debe responder todas las preguntas de recuperación",
+            ),
+            (45349670, "Token de recuperación no válido"),
+            (45349676, "Error de enrutamiento"),
+            (45349677, "Mensaje no autorizado"),
+            (45349679, "Error de autenticación"),
+            (45349684, "Error interno"),
+        ],
+    },
+];
 #[derive(
     Debug, Clone, Copy, ToSql, FromSql, Serialize, Deserialize, FromPrimitive, PartialEq, EnumString,
 )]
@@ -224,6 +796,9 @@ pub enum EnumErrorCode {
     /// Custom Method not implemented
     #[postgres(name = "NotImplemented")]
     NotImplemented = 100501,
+    /// Custom Origin {origin} is not allowed
+    #[postgres(name = "OriginNotAllowed")]
+    OriginNotAllowed = 100403,
     /// Custom NotFoundResource
     #[postgres(name = "NotFound")]
     NotFound = 100404,
@@ -236,6 +811,9 @@ pub enum EnumErrorCode {
     /// Custom Insufficient role for user
     #[postgres(name = "UserForbidden")]
     UserForbidden = 101403,
+    /// Custom Missing or invalid credentials
+    #[postgres(name = "Unauthorized")]
+    Unauthorized = 101401,
     /// Custom User not found
     #[postgres(name = "UserNotFound")]
     UserNotFound = 101404,
@@ -245,6 +823,15 @@ pub enum EnumErrorCode {
     /// Custom Must agree to the privacy policy
     #[postgres(name = "UserMustAgreePrivacyPolicy")]
     UserMustAgreePrivacyPolicy = 101602,
+    /// Custom Pipedrive rate limit exceeded, try again later
+    #[postgres(name = "PipedriveRateLimitExceeded")]
+    PipedriveRateLimitExceeded = 101429,
+    /// Custom Unknown form key {form_key}
+    #[postgres(name = "UnknownFormKey")]
+    UnknownFormKey = 101400,
+    /// Custom CAPTCHA verification failed, please try again
+    #[postgres(name = "CaptchaFailed")]
+    CaptchaFailed = 101422,
     /// SQL 22P02 InvalidEnumLevel
     #[postgres(name = "InvalidEnumLevel")]
     InvalidEnumLevel = 3484946,
@@ -366,6 +953,65 @@ impl Into<ErrorCode> for EnumErrorCode {
         ErrorCode::new(self as _)
     }
 }
+impl EnumErrorCode {
+    pub fn http_status(self) -> u16 {
+        match self {
+            Self::BadRequest => 400,
+            Self::InternalServerError => 500,
+            Self::NotImplemented => 501,
+            Self::OriginNotAllowed => 403,
+            Self::NotFound => 404,
+            Self::DatabaseError => 500,
+            Self::InvalidService => 404,
+            Self::UserForbidden => 403,
+            Self::Unauthorized => 401,
+            Self::UserNotFound => 404,
+            Self::UserMustAgreeTos => 400,
+            Self::UserMustAgreePrivacyPolicy => 400,
+            Self::PipedriveRateLimitExceeded => 429,
+            Self::UnknownFormKey => 400,
+            Self::CaptchaFailed => 422,
+            Self::InvalidEnumLevel => 400,
+            Self::Error => 500,
+            Self::InvalidArgument => 400,
+            Self::InvalidState => 409,
+            Self::InvalidSeq => 400,
+            Self::InvalidMethod => 404,
+            Self::ProtocolViolation => 400,
+            Self::MalformedRequest => 400,
+            Self::UnknownUser => 404,
+            Self::BlockedUser => 403,
+            Self::InvalidPassword => 401,
+            Self::InvalidToken => 401,
+            Self::TemporarilyUnavailable => 503,
+            Self::UnexpectedException => 500,
+            Self::BackPressureIncreased => 503,
+            Self::InvalidPublicId => 400,
+            Self::InvalidRange => 400,
+            Self::BankAccountAlreadyExists => 409,
+            Self::InsufficientFunds => 402,
+            Self::LogicalError => 500,
+            Self::RestrictedUserPrivileges => 403,
+            Self::IdenticalReplacement => 409,
+            Self::InvalidRecoveryQuestions => 400,
+            Self::InvalidRole => 403,
+            Self::WrongRecoveryAnswers => 400,
+            Self::MessageNotDelivered => 502,
+            Self::NoReply => 504,
+            Self::NullAttribute => 400,
+            Self::ConsentMissing => 400,
+            Self::ActiveSubscriptionRequired => 402,
+            Self::UsernameAlreadyRegistered => 409,
+            Self::RecoveryQuestionsNotSet => 409,
+            Self::MustSubmitAllRecoveryQuestions => 400,
+            Self::InvalidRecoveryToken => 401,
+            Self::RoutingError => 502,
+            Self::UnauthorizedMessage => 401,
+            Self::AuthError => 401,
+            Self::InternalError => 500,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -374,7 +1020,106 @@ pub struct AddCrmLeadRequest {
     pub username: String,
     pub title: String,
     pub message: String,
+    pub company: Option<String>,
+    pub form: Option<String>,
+    pub form_key: Option<String>,
+    pub form_token: Option<String>,
+    pub website: Option<String>,
+    pub captcha_token: Option<String>,
+    pub pow_challenge: Option<String>,
+    pub pow_solution: Option<String>,
+    pub fields: Option<Vec<CrmLeadField>>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CrmLeadField {
+    pub name: String,
+    pub value: String,
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct AddCrmLeadResponse {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListDeadLetterLeadsRequest {
+    pub admin_token: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListDeadLetterLeadsResponse {
+    pub leads: Vec<DeadLetterLead>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterLead {
+    pub lead_id: i64,
+    pub email: String,
+    pub username: String,
+    pub title: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub error_info: Option<String>,
+    pub tenant: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayDeadLetterLeadsRequest {
+    pub admin_token: String,
+    pub lead_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayDeadLetterLeadsResponse {
+    pub lead_ids: Vec<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscardDeadLetterLeadsRequest {
+    pub admin_token: String,
+    pub lead_id: Option<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscardDeadLetterLeadsResponse {
+    pub lead_ids: Vec<i64>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PipedriveWebhookRequest {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PipedriveWebhookResponse {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribePipedriveEventsRequest {
+    pub admin_token: String,
+    pub limit: Option<i32>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribePipedriveEventsResponse {}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssueFormTokenRequest {
+    pub form_key: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssueFormTokenResponse {
+    pub form_token: String,
+    pub expires_at: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuePowChallengeRequest {
+    pub form_key: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuePowChallengeResponse {
+    pub challenge: String,
+    pub difficulty: i32,
+    pub expires_at: i64,
+}
@@ -1,7 +1,10 @@
 use crate::database::DatabaseConfig;
 use crate::log::LogLevel;
+use crate::shutdown::DEFAULT_SHUTDOWN_GRACE;
 use clap::Parser;
 use eyre::*;
+use ipnet::IpNet;
+use model::endpoint::RateLimit;
 use serde::de::DeserializeOwned;
 use serde::*;
 use serde_json::Value;
@@ -9,6 +12,7 @@ use std::collections::HashMap;
 use std::env::current_dir;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -65,9 +69,92 @@ pub struct AppConfig<App> {
     pub debug: bool,
     #[serde(skip)]
     pub header_only: bool,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Redirects answering HTML form posts, keyed by endpoint name
+    #[serde(default)]
+    pub form_redirects: HashMap<String, FormRedirectConfig>,
+    /// Deadline of HTTP handlers whose endpoint sets no `timeout_secs`, 30 seconds when unset
+    #[serde(default)]
+    pub handler_timeout_secs: Option<u64>,
+    /// How long in-flight requests may take to finish after SIGTERM, 30 seconds when unset
+    #[serde(default)]
+    pub shutdown_grace_secs: Option<u64>,
+    /// Request rate limits keyed by endpoint name, replacing the limit of the endpoint schema.
+    /// A limit of 0 requests per minute turns it off.
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// Expect a PROXY protocol header from the load balancer on every connection
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// Networks like `10.0.0.0/8` of the proxies whose `X-Forwarded-For` and `Forwarded`
+    /// headers are believed. Single addresses need a `/32` or `/128` suffix.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
     #[serde(flatten)]
     pub extra: App,
 }
+
+impl<App> AppConfig<App> {
+    pub fn shutdown_grace(&self) -> Duration {
+        self.shutdown_grace_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_GRACE)
+    }
+}
+
+/// Where a browser is sent with a `303 See Other` after posting an HTML form. Without a URL
+/// the endpoint answers like it does for JSON requests.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FormRedirectConfig {
+    /// Thank-you page shown after a successful submission
+    #[serde(default)]
+    pub success_url: Option<String>,
+    /// Error page, `code` and `log_id` of the failure are appended as query parameters
+    #[serde(default)]
+    pub error_url: Option<String>,
+}
+
+/// Cross-origin access of browsers to `HttpServer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins like `https://www.example.com` whose pages may call the server, `*` allows any.
+    /// Requests carrying any other `Origin` are rejected. Empty disables CORS entirely.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_cors_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response
+    #[serde(default = "default_cors_max_age_secs")]
+    pub max_age_secs: u64,
+    /// Allows cookies and `Authorization` headers on cross-origin requests
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["POST".to_owned(), "OPTIONS".to_owned()]
+}
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec!["Content-Type".to_owned()]
+}
+fn default_cors_max_age_secs() -> u64 {
+    600
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: default_cors_allowed_methods(),
+            allowed_headers: default_cors_allowed_headers(),
+            max_age_secs: default_cors_max_age_secs(),
+            allow_credentials: false,
+        }
+    }
+}
 pub fn load_config<App: DeserializeOwned + Debug + Default>(
     service_name: String,
 ) -> Result<Config<App>> {
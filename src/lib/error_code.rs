@@ -1,4 +1,7 @@
+use convert_case::{Case, Casing};
 use serde::*;
+use serde_json::Value;
+use std::collections::HashMap;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ErrorCode {
@@ -14,3 +17,107 @@ impl ErrorCode {
         self.code
     }
 }
+
+/// Entry of `docs/error_codes`, generated as `gen::model::ERROR_CODES`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ErrorCodeInfo {
+    pub code: u32,
+    pub symbol: &'static str,
+    /// English message
+    pub message: &'static str,
+    pub http_status: u16,
+}
+
+/// Messages of every error code in one language, generated from
+/// `docs/error_codes/error_codes_<lang>.json` as `gen::model::ERROR_MESSAGES`
+#[derive(Copy, Clone, Debug)]
+pub struct LocalizedErrorMessages {
+    pub language: &'static str,
+    pub messages: &'static [(u32, &'static str)],
+}
+
+/// Error codes of a service with their messages in every language it speaks
+#[derive(Clone, Debug, Default)]
+pub struct ErrorCatalog {
+    codes: HashMap<u32, ErrorCodeInfo>,
+    messages: HashMap<&'static str, HashMap<u32, &'static str>>,
+}
+
+impl ErrorCatalog {
+    pub fn add_codes(&mut self, codes: &[ErrorCodeInfo]) {
+        self.codes.extend(codes.iter().map(|x| (x.code, *x)));
+    }
+    pub fn add_messages(&mut self, messages: &[LocalizedErrorMessages]) {
+        for language in messages {
+            self.messages
+                .entry(language.language)
+                .or_default()
+                .extend(language.messages.iter().copied());
+        }
+    }
+    pub fn get(&self, code: u32) -> Option<&ErrorCodeInfo> {
+        self.codes.get(&code)
+    }
+
+    /// Catalog language of a tag such as `de` or `de-AT`
+    pub fn language(&self, tag: &str) -> Option<&'static str> {
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        [tag, primary].into_iter().find_map(|tag| {
+            self.messages
+                .keys()
+                .find(|x| x.eq_ignore_ascii_case(tag))
+                .copied()
+        })
+    }
+    /// Preferred catalog language of an `Accept-Language` header, `None` when the client
+    /// accepts none of them
+    pub fn negotiate(&self, accept_language: &str) -> Option<&'static str> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = match parts.find_map(|x| x.trim().strip_prefix("q=")) {
+                    Some(quality) => quality.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                Some((tag, quality))
+            })
+            .filter(|(tag, quality)| !tag.is_empty() && *quality > 0.0)
+            .collect();
+        // stable, equally weighted languages keep the order of the client
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.into_iter().find_map(|(tag, _)| self.language(tag))
+    }
+
+    /// Message of a code in a language, falling back to English, with each `{placeholder}`
+    /// replaced by the field of the same name in `params`
+    pub fn render(&self, code: u32, language: Option<&str>, params: &Value) -> String {
+        let template = language
+            .and_then(|x| self.messages.get(x))
+            .and_then(|x| x.get(&code).copied())
+            .or_else(|| self.codes.get(&code).map(|x| x.message))
+            .unwrap_or_default();
+        let mut message = String::new();
+        let mut rest = template;
+        while let Some((start, end)) = rest
+            .find('{')
+            .and_then(|start| Some((start, start + rest[start..].find('}')?)))
+        {
+            let name = &rest[start + 1..end];
+            message.push_str(&rest[..start]);
+            // params are serialized in camelCase, placeholders may be written in snake_case
+            match params
+                .get(name)
+                .or_else(|| params.get(name.to_case(Case::Camel).as_str()))
+            {
+                Some(Value::String(value)) => message.push_str(value),
+                Some(value) => message.push_str(&value.to_string()),
+                None => message.push_str(&rest[start..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        message.push_str(rest);
+        message
+    }
+}
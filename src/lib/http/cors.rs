@@ -0,0 +1,98 @@
+use crate::config::CorsConfig;
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, VARY,
+};
+use hyper::{Method, Request, Response, StatusCode};
+
+pub enum CorsDecision {
+    /// Not a cross-origin request or CORS is disabled, answer as usual
+    Pass,
+    /// Cross-origin request from an allowed origin, answer with CORS headers
+    Allow(HeaderValue),
+    /// Cross-origin request from this origin, answer with `OriginNotAllowed`
+    Forbidden(String),
+    /// Answered here, the request must not reach a handler
+    Respond(Response<String>),
+}
+
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Self { config }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.config
+            .allowed_origins
+            .iter()
+            .any(|x| x == "*" || x.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+
+    pub fn check<B>(&self, request: &Request<B>) -> CorsDecision {
+        if self.config.allowed_origins.is_empty() {
+            return CorsDecision::Pass;
+        }
+        let origin = match request.headers().get(hyper::header::ORIGIN) {
+            Some(origin) => origin,
+            // not sent by a browser
+            None => return CorsDecision::Pass,
+        };
+        if !origin.to_str().map(|x| self.allows(x)).unwrap_or_default() {
+            return CorsDecision::Forbidden(
+                String::from_utf8_lossy(origin.as_bytes()).into_owned(),
+            );
+        }
+        let preflight = request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .contains_key(hyper::header::ACCESS_CONTROL_REQUEST_METHOD);
+        if !preflight {
+            return CorsDecision::Allow(origin.clone());
+        }
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(String::new())
+            .unwrap();
+        self.apply(origin.clone(), &mut response);
+        let headers = response.headers_mut();
+        let join = |values: &[String]| HeaderValue::from_str(&values.join(", ")).ok();
+        if let Some(methods) = join(&self.config.allowed_methods) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if let Some(allowed_headers) = join(&self.config.allowed_headers) {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        }
+        headers.insert(
+            ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from(self.config.max_age_secs),
+        );
+        CorsDecision::Respond(response)
+    }
+
+    /// Adds the CORS headers of an allowed cross-origin request to its response
+    pub fn apply<B>(&self, origin: HeaderValue, response: &mut Response<B>) {
+        let headers = response.headers_mut();
+        // browsers refuse `*` on credentialed requests, so the origin is echoed instead
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        if self.config.allow_credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Answers depend on the Origin of the request whenever CORS is enabled, caches must
+    /// keep them apart even when no CORS headers were added
+    pub fn vary<B>(&self, response: &mut Response<B>) {
+        if !self.config.allowed_origins.is_empty() {
+            response
+                .headers_mut()
+                .append(VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+}
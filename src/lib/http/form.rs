@@ -0,0 +1,190 @@
+use convert_case::{Case, Casing};
+use eyre::*;
+use model::endpoint::EndpointSchema;
+use model::types::{Field, Type};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+/// How the body of a request is encoded, taken from its `Content-Type`
+pub enum BodyFormat {
+    Json,
+    UrlEncoded,
+    Multipart { boundary: String },
+}
+
+impl BodyFormat {
+    /// Requests without a `Content-Type` are treated as JSON
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self> {
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            None => return Ok(Self::Json),
+        };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match mime.as_str() {
+            "application/x-www-form-urlencoded" => Ok(Self::UrlEncoded),
+            "multipart/form-data" => Ok(Self::Multipart {
+                boundary: multer::parse_boundary(content_type)?,
+            }),
+            _ => Ok(Self::Json),
+        }
+    }
+    /// Submitted by a plain HTML `<form>`
+    pub fn is_form(&self) -> bool {
+        !matches!(self, Self::Json)
+    }
+
+    /// Parses a body into the same JSON a client of the endpoint would have sent
+    pub async fn parse(&self, body: Vec<u8>, schema: &EndpointSchema) -> Result<Value> {
+        let pairs = match self {
+            Self::Json => return Ok(serde_json::from_slice(&body)?),
+            Self::UrlEncoded => form_urlencoded::parse(&body).into_owned().collect(),
+            Self::Multipart { boundary } => parse_multipart(body, boundary).await?,
+        };
+        form_to_json(&pairs, &schema.parameters)
+    }
+}
+
+async fn parse_multipart(body: Vec<u8>, boundary: &str) -> Result<Vec<(String, String)>> {
+    let stream =
+        futures::stream::once(async move { Ok::<_, Infallible>(bytes::Bytes::from(body)) });
+    let mut multipart = multer::Multipart::new(stream, boundary);
+    let mut pairs = vec![];
+    while let Some(field) = multipart.next_field().await? {
+        // endpoints take no files, uploads are ignored rather than buffered as text
+        if field.file_name().is_some() {
+            continue;
+        }
+        let name = field.name().unwrap_or_default().to_owned();
+        pairs.push((name, field.text().await?));
+    }
+    Ok(pairs)
+}
+
+/// Splits `fields[0][name]` into `fields`, `0`, `name`
+fn split_key(key: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let (head, mut rest) = key
+        .split_once('[')
+        .map_or((key, ""), |(head, rest)| (head, rest));
+    parts.push(head);
+    while let Some((part, tail)) = rest.split_once(']') {
+        parts.push(part);
+        rest = tail.strip_prefix('[').unwrap_or(tail);
+    }
+    parts
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_case(Case::Camel) == b.to_case(Case::Camel)
+}
+
+/// Builds the JSON request from form fields, coercing values to the types of `parameters`.
+/// Lists are sent as repeated `name` or `name[]` fields, tables as `name[row][column]` and
+/// objects as `name[field]`. Fields that are not parameters are ignored.
+pub fn form_to_json(pairs: &[(String, String)], parameters: &[Field]) -> Result<Value> {
+    let pairs: Vec<(Vec<&str>, &str)> = pairs
+        .iter()
+        .map(|(key, value)| (split_key(key), value.as_str()))
+        .collect();
+    let mut result = Map::new();
+    for param in parameters {
+        let values: Vec<(&[&str], &str)> = pairs
+            .iter()
+            .filter(|(path, _)| same_name(path[0], &param.name))
+            .map(|(path, value)| (&path[1..], *value))
+            .collect();
+        if let Some(value) = collect(&param.name, &param.ty, &values)? {
+            result.insert(param.name.to_case(Case::Camel), value);
+        }
+    }
+    Ok(Value::Object(result))
+}
+
+/// Value of one parameter from the fields below it, `None` when it was not submitted
+fn collect(name: &str, ty: &Type, values: &[(&[&str], &str)]) -> Result<Option<Value>> {
+    match ty {
+        Type::Optional(ty) => {
+            // empty inputs of optional scalars count as not filled in
+            let values: Vec<_> = values
+                .iter()
+                .filter(|(path, value)| !path.is_empty() || !value.is_empty())
+                .copied()
+                .collect();
+            collect(name, ty, &values)
+        }
+        Type::Vec(ty) => {
+            let items = values
+                .iter()
+                .filter(|(path, _)| path.is_empty() || path == &[""])
+                .map(|(_, value)| coerce(name, ty, value))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Some(Value::Array(items)))
+        }
+        Type::DataTable { fields, .. } => {
+            let mut rows: BTreeMap<u32, Vec<(&[&str], &str)>> = BTreeMap::new();
+            for (path, value) in values {
+                if let Some((row, rest)) = path.split_first() {
+                    let row = row
+                        .parse()
+                        .with_context(|| format!("Invalid row {} of {}", row, name))?;
+                    rows.entry(row).or_default().push((rest, value));
+                }
+            }
+            let rows = rows
+                .values()
+                .map(|row| collect_object(name, fields, row))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Some(Value::Array(rows)))
+        }
+        Type::Object { fields, .. } if !values.is_empty() => {
+            Ok(Some(collect_object(name, fields, values)?))
+        }
+        // unchecked checkboxes are not submitted at all
+        Type::Boolean if values.is_empty() => Ok(Some(Value::Bool(false))),
+        _ => match values.iter().rev().find(|(path, _)| path.is_empty()) {
+            Some((_, value)) => Ok(Some(coerce(name, ty, value)?)),
+            None => Ok(None),
+        },
+    }
+}
+
+fn collect_object(name: &str, fields: &[Field], values: &[(&[&str], &str)]) -> Result<Value> {
+    let mut object = Map::new();
+    for field in fields {
+        let values: Vec<(&[&str], &str)> = values
+            .iter()
+            .filter(|(path, _)| path.first().map_or(false, |x| same_name(x, &field.name)))
+            .map(|(path, value)| (&path[1..], *value))
+            .collect();
+        let name = format!("{}.{}", name, field.name);
+        if let Some(value) = collect(&name, &field.ty, &values)? {
+            object.insert(field.name.to_case(Case::Camel), value);
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+fn coerce(name: &str, ty: &Type, value: &str) -> Result<Value> {
+    let invalid = || format!("Invalid value {:?} for {}", value, name);
+    Ok(match ty {
+        Type::Int | Type::BigInt | Type::Second | Type::MilliSecond => {
+            Value::from(value.trim().parse::<i64>().with_context(invalid)?)
+        }
+        Type::Numeric => Value::from(value.trim().parse::<f64>().with_context(invalid)?),
+        // checked checkboxes send `on` unless they have a value
+        Type::Boolean => match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "on" | "yes" | "1" => Value::Bool(true),
+            "false" | "off" | "no" | "0" | "" => Value::Bool(false),
+            _ => bail!(invalid()),
+        },
+        Type::Optional(ty) => coerce(name, ty, value)?,
+        Type::Unit => Value::Null,
+        _ => Value::String(value.to_owned()),
+    })
+}
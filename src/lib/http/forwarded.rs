@@ -0,0 +1,72 @@
+use hyper::header::FORWARDED;
+use hyper::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Address of the client that sent a request. `Forwarded` and `X-Forwarded-For` are only
+/// believed when `peer` is one of `trusted_proxies`, and the chain of hops is only followed back
+/// through trusted proxies, so a client cannot choose the address it is seen with.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !trusted(&peer) {
+        return peer;
+    }
+    let hops = if headers.contains_key(FORWARDED) {
+        forwarded_for(headers)
+    } else {
+        x_forwarded_for(headers)
+    };
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = ip;
+                if !trusted(&ip) {
+                    break;
+                }
+            }
+            // obfuscated and unknown hops end the part of the chain that can be followed
+            None => break,
+        }
+    }
+    client
+}
+
+/// `for` of each element of `Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .flat_map(|x| x.to_str().unwrap_or_default().split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for").then_some(value)
+                })
+                .and_then(|node| parse_node(node.trim_matches('"')))
+        })
+        .collect()
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|x| x.to_str().unwrap_or_default().split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Accepts `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::1`, `[2001:db8::1]` and
+/// `[2001:db8::1]:4711`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
@@ -0,0 +1,75 @@
+use crate::database::SimpleDbClient;
+use eyre::*;
+use futures::future::BoxFuture;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Response, StatusCode};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A check failing makes `/readyz` answer 503 until it passes again
+pub type ReadinessProbe = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Checks slower than this count as failed, the orchestrator polls again anyway
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Readiness {
+    probes: Vec<(String, ReadinessProbe)>,
+}
+
+impl Readiness {
+    pub fn add_probe(&mut self, name: impl Into<String>, probe: ReadinessProbe) {
+        self.probes.push((name.into(), probe));
+    }
+
+    /// Runs every database ping and probe concurrently and reports each of them
+    pub async fn check<B: From<String>>(&self, dbs: &[SimpleDbClient]) -> Response<B> {
+        let dbs = dbs.iter().enumerate().map(|(i, db)| {
+            let db = db.clone();
+            let check: BoxFuture<'static, Result<()>> = Box::pin(async move {
+                db.query("SELECT 1", &[]).await?;
+                Ok(())
+            });
+            (format!("database_{}", i), check)
+        });
+        let probes = self
+            .probes
+            .iter()
+            .map(|(name, probe)| (name.clone(), probe()));
+        let results =
+            futures::future::join_all(dbs.chain(probes).map(|(name, check)| async move {
+                let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+                    Ok(result) => result,
+                    Err(_) => Err(eyre!("timed out after {:?}", CHECK_TIMEOUT)),
+                };
+                (name, result)
+            }))
+            .await;
+        let ready = results.iter().all(|(_, result)| result.is_ok());
+        let checks: Map<String, Value> = results
+            .into_iter()
+            .map(|(name, result)| {
+                let status = match result {
+                    Ok(()) => "ok".to_owned(),
+                    Err(err) => format!("{:#}", err),
+                };
+                (name, Value::String(status))
+            })
+            .collect();
+        let status = if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({ "ready": ready, "checks": checks })
+                    .to_string()
+                    .into(),
+            )
+            .unwrap()
+    }
+}
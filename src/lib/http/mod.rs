@@ -1,5 +1,14 @@
 // TODO
 // mod headers;
+mod cors;
+mod form;
+mod forwarded;
+mod health;
 mod server;
 
+pub use cors::*;
+pub use form::*;
+pub use forwarded::*;
+pub use health::*;
+
 pub use server::*;
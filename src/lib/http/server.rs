@@ -1,111 +1,213 @@
 use eyre::*;
 use hyper::body::HttpBody;
-use hyper::server::accept::Accept;
+use hyper::header::{
+    HeaderValue, ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, LOCATION, RETRY_AFTER,
+    WWW_AUTHENTICATE,
+};
+use hyper::server::conn::Http;
 use hyper::service::service_fn;
-use hyper::{Body, Request, Response, StatusCode};
-use serde_json::Value;
-use std::collections::HashMap;
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::convert::Infallible;
 use std::future::poll_fn;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::pin::Pin;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
-use std::task::Poll;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::*;
 
-use crate::config::AppConfig;
+use crate::config::{AppConfig, FormRedirectConfig};
 use crate::database::SimpleDbClient;
+use crate::error_code::{ErrorCatalog, ErrorCode, ErrorCodeInfo, LocalizedErrorMessages};
 use crate::handler::*;
-use crate::listener::{ConnectionListener, TcpListener, TlsListener};
+use crate::http::{
+    client_ip, form_to_json, BodyFormat, Cors, CorsDecision, Readiness, ReadinessProbe,
+};
+use crate::listener::{ConnectionListener, ProxyProtocolListener, TcpListener, TlsListener};
+use crate::metrics::Metrics;
+use crate::rate_limit::{FormKeyFilter, RequestLimiter};
+use crate::shutdown::{shutdown_signal, TaskTracker};
 use crate::toolbox::{RequestContext, Toolbox};
 use crate::utils::{get_conn_id, get_log_id};
 use crate::ws::Connection;
 use crate::ws::WsResponse;
-use crate::ws::{check_handler, WsEndpoint};
+use crate::ws::{is_websocket_upgrade, AuthController, WebsocketServer, WebsocketStates};
+use crate::ws::{request_error_to_resp, ConnectionId};
+use crate::ws::{EndpointRegistry, WsEndpoint};
+use dashmap::DashMap;
 use model::endpoint::EndpointSchema;
 
+const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Websocket server answering the upgrade requests, with the connections it serves
+type WebsocketUpgrades<App> = (Arc<WebsocketServer<App>>, Arc<WebsocketStates<Upgraded>>);
+
 pub struct HttpServer<App> {
-    pub handlers: HashMap<String, WsEndpoint>,
+    pub handlers: EndpointRegistry,
     pub toolbox: Toolbox,
     pub config: AppConfig<App>,
+    cors: Cors,
+    errors: ErrorCatalog,
+    readiness: Readiness,
+    /// Built from the endpoints and `rate_limits` once the server listens
+    rate_limiter: Arc<RequestLimiter>,
+    /// Form keys that get a rate limit bucket of their own
+    form_keys: Option<FormKeyFilter>,
+    /// Serves `Upgrade: websocket` requests when enabled
+    websocket: Option<WebsocketServer<App>>,
+    /// Open event streams, so messages published to their connection reach them
+    streams: Arc<DashMap<ConnectionId, kanal::AsyncSender<WsResponse>>>,
+}
+
+/// Body of every error response
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: u32,
+    symbol: &'a str,
+    message: &'a str,
+    params: Value,
+    log_id: String,
 }
 
 impl<App: Sync + Send + 'static> HttpServer<App> {
     pub fn new(config: AppConfig<App>) -> Self {
-
+        let streams: Arc<DashMap<ConnectionId, kanal::AsyncSender<WsResponse>>> =
+            Default::default();
+        let mut toolbox = Toolbox::new();
+        let routes = Arc::clone(&streams);
+        // stream channels are unbounded, so handlers on the runtime never wait for a client
+        toolbox.send_msg = Arc::new(move |conn_id, resp| match routes.get(&conn_id) {
+            Some(tx) => tx.try_send(resp).unwrap_or_default(),
+            None => false,
+        });
         Self {
             handlers: Default::default(),
-            toolbox: Toolbox::new(),
+            toolbox,
+            streams,
+            cors: Cors::new(config.cors.clone()),
+            errors: Default::default(),
+            readiness: Default::default(),
+            rate_limiter: Default::default(),
+            form_keys: None,
+            websocket: None,
             config,
         }
     }
     pub fn add_database(&mut self, db: SimpleDbClient) {
         self.toolbox.add_db(db);
     }
+    /// Catalog giving each error code its HTTP status and message
+    pub fn add_error_codes(&mut self, codes: &[ErrorCodeInfo]) {
+        self.errors.add_codes(codes);
+    }
+    /// Translations of the error messages, picked by the `lang` query parameter or `Accept-Language`
+    pub fn add_error_messages(&mut self, messages: &[LocalizedErrorMessages]) {
+        self.errors.add_messages(messages);
+    }
+
+    /// Extra check of `/readyz` besides the databases, e.g. reaching an upstream API
+    pub fn add_readiness_probe(&mut self, name: impl Into<String>, probe: ReadinessProbe) {
+        self.readiness.add_probe(name, probe);
+    }
+
+    /// Gives the known form keys of a client separate rate limit buckets
+    pub fn set_form_key_filter(&mut self, form_keys: FormKeyFilter) {
+        self.form_keys = Some(form_keys);
+    }
 
     pub fn add_handler<T: RequestHandler + 'static>(&mut self, schema: EndpointSchema, handler: T) {
-        check_handler::<T>(&schema).expect("Invalid handler");
-        self.add_handler_erased(schema, Arc::new(handler))
+        self.handlers.add_handler(schema, handler)
     }
     pub fn add_handler_erased(
         &mut self,
         schema: EndpointSchema,
         handler: Arc<dyn RequestHandlerErased>,
     ) {
-        let old = self
-            .handlers
-            .insert(schema.name.clone(), WsEndpoint { schema, handler });
-        if let Some(old) = old {
-            panic!(
-                "Overwriting handler for endpoint {} {}",
-                old.schema.code, old.schema.name
-            );
-        }
+        self.handlers.add_handler_erased(schema, handler)
     }
     async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         self: Arc<Self>,
         addr: SocketAddr,
         stream: S,
+        websocket: Option<WebsocketUpgrades<App>>,
     ) {
-        let conn = Arc::new(Connection {
-            connection_id: get_conn_id(),
-            user_id: Default::default(),
-            role: AtomicU32::new(0),
-            address: addr,
-            log_id: get_log_id(),
-        });
+        let connection_id = get_conn_id();
+        let log_id = get_log_id();
+        let tasks = self.toolbox.tasks.clone();
         let mut seq = 0;
-        let handler = move |req| {
+        let handler = move |req: Request<Body>| {
             let this = Arc::clone(&self);
             seq += 1;
-            let conn = Arc::clone(&conn);
-            let log_id = conn.log_id;
+            // requests on a kept-alive connection may carry different headers
+            let conn = Arc::new(Connection {
+                connection_id,
+                user_id: Default::default(),
+                role: AtomicU32::new(0),
+                address: addr,
+                client_ip: client_ip(addr.ip(), req.headers(), &this.config.trusted_proxies),
+                log_id,
+                headers: req.headers().clone(),
+            });
+            let websocket = websocket.clone().filter(|_| is_websocket_upgrade(&req));
             async move {
-                match this.handle_request(conn, req, seq).await {
-                    Ok(ok) => Ok::<_, Infallible>(ok),
+                // disallowed origins and preflights never reach a handler
+                let origin = match this.cors.check(&req) {
+                    CorsDecision::Pass => None,
+                    CorsDecision::Allow(origin) => Some(origin),
+                    CorsDecision::Forbidden(origin) => {
+                        let language = this.language(&req);
+                        let mut resp = this.error_response(
+                            100403, // Origin Not Allowed
+                            json!({ "origin": origin }),
+                            log_id,
+                            language,
+                        );
+                        this.cors.vary(&mut resp);
+                        return Ok::<_, Infallible>(resp);
+                    }
+                    CorsDecision::Respond(resp) => {
+                        let mut resp = resp.map(Body::from);
+                        this.cors.vary(&mut resp);
+                        return Ok(resp);
+                    }
+                };
+                if let Some((websocket, states)) = websocket {
+                    let mut resp = websocket.upgrade(addr, states, req);
+                    this.cors.vary(&mut resp);
+                    return Ok(resp);
+                }
+                let mut resp = match Arc::clone(&this).handle_request(conn, req, seq).await {
+                    Ok(ok) => ok,
                     Err(err) => {
                         error!("Error handling request: {:?} log_id={}", err, log_id);
-                        Ok(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(format!("Internal Server Error: log_id={}", log_id).into())
-                            .unwrap())
+                        this.error_response(100500, Value::Null, log_id, None) // Internal Server Error
                     }
+                };
+                if let Some(origin) = origin {
+                    this.cors.apply(origin, &mut resp);
                 }
+                this.cors.vary(&mut resp);
+                Ok(resp)
+            }
+        };
+        let conn = Http::new()
+            .serve_connection(stream, service_fn(handler))
+            .with_upgrades();
+        tokio::pin!(conn);
+        let ret = tokio::select! {
+            ret = &mut conn => ret,
+            _ = tasks.closed() => {
+                // idle keep-alive connections close now, others after answering their request
+                conn.as_mut().graceful_shutdown();
+                conn.await
             }
         };
-        let service = hyper::service::make_service_fn(move |_| {
-            let handler = handler.clone();
-            futures::future::ready(Ok::<_, Infallible>(service_fn(handler)))
-        });
-
-        let s = hyper::server::Server::builder(ImmediateAcceptor {
-            listener: Some(stream),
-        })
-            .serve(service);
 
-        if let Err(e) = s.await {
+        if let Err(e) = ret {
             warn!("Error serving connection: {:?}", e);
         }
     }
@@ -115,17 +217,70 @@ impl<App: Sync + Send + 'static> HttpServer<App> {
         conn: Arc<Connection>,
         request: Request<Body>,
         seq: u32,
-    ) -> Result<Response<String>> {
+    ) -> Result<Response<Body>> {
         let url = request.uri().path().trim_start_matches("/");
+        let language = self.language(&request);
 
-        let endpoint = match self.handlers.get(url) {
+        // probes of the orchestrator and the scraper are not endpoints and bypass the handlers
+        match url {
+            "healthz" => {
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .body("ok".into())?);
+            }
+            "readyz" => return Ok(self.readiness.check(self.toolbox.dbs()).await),
+            "metrics" => {
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Metrics::global().render().into())?);
+            }
+            _ => {}
+        }
+        let endpoint = match self.handlers.get_by_name(url) {
             Some(endpoint) => endpoint,
             None => {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(format!("Endpoint {} not found", url))?);
+                return Ok(self.error_response(
+                    100404, // Not Found
+                    format!("Endpoint {} not found", url).into(),
+                    conn.log_id,
+                    language,
+                ));
             }
         };
+        let name = endpoint.schema.name.clone();
+        let started = Instant::now();
+        let resp = self
+            .handle_endpoint(endpoint, conn, request, seq, language)
+            .await;
+        let status = match &resp {
+            Ok(resp) => resp.status().as_u16().to_string(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.as_u16().to_string(),
+        };
+        let metrics = Metrics::global();
+        metrics.increment(
+            "http_requests_total",
+            "Requests by endpoint and HTTP status",
+            &[("endpoint", &name), ("status", &status)],
+        );
+        // event streams are measured until their headers are sent
+        metrics.observe(
+            "http_request_duration_seconds",
+            "Time to answer requests by endpoint",
+            &[("endpoint", &name)],
+            started.elapsed(),
+        );
+        resp
+    }
+
+    async fn handle_endpoint(
+        self: &Arc<Self>,
+        endpoint: &WsEndpoint,
+        conn: Arc<Connection>,
+        request: Request<Body>,
+        seq: u32,
+        language: Option<&'static str>,
+    ) -> Result<Response<Body>> {
         let context = RequestContext {
             connection_id: conn.connection_id,
             user_id: conn.get_user_id(),
@@ -133,45 +288,318 @@ impl<App: Sync + Send + 'static> HttpServer<App> {
             method: endpoint.schema.code,
             log_id: conn.log_id,
         };
-        let mut body = vec![];
-        let mut b = request.into_body();
-        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut b).poll_data(cx)).await {
-            let chunk = chunk?;
-            body.extend_from_slice(chunk.as_ref());
-        }
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok());
+        let format = match BodyFormat::from_content_type(content_type) {
+            Ok(format) => format,
+            Err(err) => {
+                return Ok(self.error_response(
+                    100400, // Bad Request
+                    err.to_string().into(),
+                    conn.log_id,
+                    language,
+                ));
+            }
+        };
+        // JSON clients get JSON back, browsers posting a form may be redirected instead
+        let redirect = self
+            .config
+            .form_redirects
+            .get(&endpoint.schema.name)
+            .filter(|_| format.is_form());
+        let parsed = if request.method() == Method::GET {
+            if !endpoint.schema.allow_get {
+                return Ok(self.error_response(
+                    100400, // Bad Request
+                    format!("Endpoint {} takes POST requests", endpoint.schema.name).into(),
+                    conn.log_id,
+                    language,
+                ));
+            }
+            let query = request.uri().query().unwrap_or_default();
+            let pairs: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect();
+            form_to_json(&pairs, &endpoint.schema.parameters)
+        } else {
+            let mut body = vec![];
+            let mut b = request.into_body();
+            while let Some(chunk) = poll_fn(|cx| Pin::new(&mut b).poll_data(cx)).await {
+                let chunk = chunk?;
+                body.extend_from_slice(chunk.as_ref());
+            }
+            format.parse(body, &endpoint.schema).await
+        };
 
-        let req: Value = match serde_json::from_slice(&body) {
+        let req: Value = match parsed {
             Ok(req) => req,
             Err(err) => {
-                return Ok(Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(err.to_string())?);
+                if let Some(url) = redirect.and_then(|x| x.error_url.as_ref()) {
+                    return self.redirect_to_error(url, 100400, conn.log_id);
+                }
+                return Ok(self.error_response(
+                    100400, // Bad Request
+                    err.to_string().into(),
+                    conn.log_id,
+                    language,
+                ));
             }
         };
+        if let Err(retry_after) =
+            self.rate_limiter
+                .check(endpoint.schema.code, conn.client_ip, &req)
+        {
+            let retry_after_secs = (retry_after.as_secs_f64().ceil() as u64).max(1);
+            let mut resp = match redirect.and_then(|x| x.error_url.as_ref()) {
+                Some(url) => self.redirect_to_error(url, 45349645, conn.log_id)?, // BackPressureIncreased
+                None => self.error_response(
+                    45349645, // BackPressureIncreased
+                    json!({ "retry_after_secs": retry_after_secs }),
+                    conn.log_id,
+                    language,
+                ),
+            };
+            resp.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+            return Ok(resp);
+        }
+        let streaming = !endpoint.schema.stream_response.is_empty();
+        // over plain HTTP the streamed messages would be dropped while the request never ends,
+        // websocket clients get them on their connection instead
+        if streaming
+            && !conn
+                .header("accept")
+                .map_or(false, |x| x.contains("text/event-stream"))
+        {
+            return Ok(self.error_response(
+                100400, // Bad Request
+                format!(
+                    "Endpoint {} streams its response, send Accept: text/event-stream",
+                    endpoint.schema.name
+                )
+                .into(),
+                conn.log_id,
+                language,
+            ));
+        }
+        let timeout = match endpoint.schema.timeout_secs {
+            Some(timeout_secs) => Some(Duration::from_secs(timeout_secs)),
+            // a stream lasts as long as its handler keeps sending
+            None if streaming => None,
+            None => self.config.handler_timeout_secs.map(Duration::from_secs),
+        };
         let (tx, rx) = kanal::unbounded_async();
         let mut toolbox = self.toolbox.clone();
-        toolbox.send_msg =
-            Arc::new(move |_conn, resp| {
-                futures::executor::block_on(tx.send(resp)).unwrap();
-                true
-            });
+        toolbox.timeout = timeout;
+        if streaming {
+            self.streams.insert(conn.connection_id, tx.clone());
+        }
+        // the receiver is gone once the request timed out or the client left the stream
+        toolbox.send_msg = Arc::new(move |_conn, resp| tx.try_send(resp).unwrap_or_default());
         endpoint
             .handler
             .handle(&toolbox, context, Arc::clone(&conn), req);
-        let resp = rx.recv().await?;
-        info!("Response: {:?}", resp);
+        if streaming {
+            return Ok(Arc::clone(self).event_stream(conn.connection_id, rx, language));
+        }
+        let timeout = timeout.unwrap_or(DEFAULT_HANDLER_TIMEOUT);
+        let response = async {
+            loop {
+                match rx.recv().await {
+                    Ok(resp @ (WsResponse::Immediate(_) | WsResponse::Error(_))) => break Ok(resp),
+                    // progress is only reported to event streams
+                    Ok(resp) => debug!("Dropping {:?} of a request without event stream", resp),
+                    Err(err) => break Err(err),
+                }
+            }
+        };
+        // also covers handlers that never answer or do not go through `spawn_ws_response`
+        let resp = match tokio::time::timeout(timeout, response).await {
+            Ok(resp) => resp?,
+            Err(_) => request_error_to_resp(
+                &context,
+                ErrorCode::new(45349643), // TemporarilyUnavailable
+                format!("Request timed out after {:?}", timeout),
+            ),
+        };
+        info!(client_ip = ?conn.client_ip, "Response: {:?}", resp);
+        match (resp, redirect) {
+            (
+                WsResponse::Immediate(_),
+                Some(FormRedirectConfig {
+                    success_url: Some(url),
+                    ..
+                }),
+            ) => see_other(url),
+            (
+                WsResponse::Error(err),
+                Some(FormRedirectConfig {
+                    error_url: Some(url),
+                    ..
+                }),
+            ) => self.redirect_to_error(url, err.code, conn.log_id),
+            (resp, _) => self.response(resp, language),
+        }
+    }
+
+    fn response<B: From<String>>(
+        &self,
+        resp: WsResponse,
+        language: Option<&str>,
+    ) -> Result<Response<B>> {
         match resp {
             WsResponse::Immediate(x) => Ok(Response::builder()
                 .status(StatusCode::OK)
-                .body(serde_json::to_string(&x.params)?)?),
-            WsResponse::Error(err) => Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(serde_json::to_string(&err)?)?),
+                .body(serde_json::to_string(&x.params)?.into())?),
+            WsResponse::Error(err) => {
+                let log_id = err.log_id.parse().unwrap_or_default();
+                Ok(self.error_response(err.code, err.params, log_id, language))
+            }
+            resp => bail!("Unexpected final response {:?}", resp),
+        }
+    }
 
-            _ => {
-                todo!()
+    /// Sends the messages of a streaming request as server-sent events, ending the body after
+    /// the final response
+    fn event_stream(
+        self: Arc<Self>,
+        connection_id: ConnectionId,
+        rx: kanal::AsyncReceiver<WsResponse>,
+        language: Option<&'static str>,
+    ) -> Response<Body> {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            while let Ok(resp) = rx.recv().await {
+                let last = matches!(
+                    resp,
+                    WsResponse::Immediate(_) | WsResponse::Error(_) | WsResponse::Close
+                );
+                if let Some(event) = self.event(resp, language) {
+                    if sender.send_data(event.into()).await.is_err() {
+                        debug!("Client of event stream {} went away", connection_id);
+                        break;
+                    }
+                }
+                if last {
+                    break;
+                }
+            }
+            self.streams.remove(&connection_id);
+        });
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap()
+    }
+
+    fn event(&self, resp: WsResponse, language: Option<&str>) -> Option<String> {
+        let (name, id, data) = match resp {
+            WsResponse::Stream(x) => ("stream", Some(x.stream_seq), serde_json::to_string(&x)),
+            WsResponse::Log(x) => ("log", None, serde_json::to_string(&x)),
+            WsResponse::Immediate(x) => ("response", None, serde_json::to_string(&x.params)),
+            WsResponse::Error(err) => {
+                let log_id = err.log_id.parse().unwrap_or_default();
+                let (_, body) = self.error_body(err.code, err.params, log_id, language);
+                ("error", None, Ok(body))
             }
+            WsResponse::Forwarded(_) | WsResponse::Close => return None,
+        };
+        let mut event = String::new();
+        if let Some(id) = id {
+            event.push_str(&format!("id: {}\n", id));
+        }
+        // serialized JSON has no raw newlines, so it fits on one data line
+        event.push_str(&format!("event: {}\ndata: {}\n\n", name, data.ok()?));
+        Some(event)
+    }
+
+    /// Catalog language asked for by the `lang` query parameter, else by `Accept-Language`
+    fn language<B>(&self, request: &Request<B>) -> Option<&'static str> {
+        let requested = request.uri().query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(name, _)| name == "lang")
+                .map(|(_, lang)| lang.into_owned())
+        });
+        requested
+            .and_then(|lang| self.errors.language(&lang))
+            .or_else(|| {
+                let accept_language = request.headers().get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+                self.errors.negotiate(accept_language)
+            })
+    }
+
+    fn error_response<B: From<String>>(
+        &self,
+        code: u32,
+        params: Value,
+        log_id: u64,
+        language: Option<&str>,
+    ) -> Response<B> {
+        let (status, body) = self.error_body(code, params, log_id, language);
+        let mut resp = Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .unwrap();
+        // credentials over HTTP come as basic auth, e.g. from the Pipedrive webhooks
+        if status == StatusCode::UNAUTHORIZED {
+            resp.headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
         }
+        resp
+    }
+
+    fn error_body(
+        &self,
+        code: u32,
+        params: Value,
+        log_id: u64,
+        language: Option<&str>,
+    ) -> (StatusCode, String) {
+        self.count_error(code);
+        let info = self.errors.get(code);
+        let message = self.errors.render(code, language, &params);
+        // internal errors carry no params, request errors do
+        let status = match info {
+            Some(info) => StatusCode::from_u16(info.http_status).unwrap_or(StatusCode::BAD_REQUEST),
+            None if params.is_null() => StatusCode::INTERNAL_SERVER_ERROR,
+            None => StatusCode::BAD_REQUEST,
+        };
+        let body = ErrorBody {
+            code,
+            symbol: info.map_or("", |x| x.symbol),
+            message: &message,
+            params,
+            log_id: log_id.to_string(),
+        };
+        (status, serde_json::to_string(&body).unwrap())
+    }
+
+    fn redirect_to_error<B: From<String>>(
+        &self,
+        url: &str,
+        code: u32,
+        log_id: u64,
+    ) -> Result<Response<B>> {
+        self.count_error(code);
+        let separator = if url.contains('?') { '&' } else { '?' };
+        see_other(&format!(
+            "{}{}code={}&log_id={}",
+            url, separator, code, log_id
+        ))
+    }
+
+    fn count_error(&self, code: u32) {
+        let symbol = self.errors.get(code).map_or("", |x| x.symbol);
+        Metrics::global().increment(
+            "http_errors_total",
+            "Errors returned to clients by error code",
+            &[("code", &code.to_string()), ("symbol", symbol)],
+        );
     }
 
     pub async fn listen(self) -> Result<()> {
@@ -181,16 +609,17 @@ impl<App: Sync + Send + 'static> HttpServer<App> {
             .context("Failed to resolve address")?;
         if self.config.pub_certs.is_none() && self.config.priv_cert.is_none() {
             let listener = TcpListener::bind(addr).await?;
+            let listener = ProxyProtocolListener::new(listener, self.config.proxy_protocol);
             self.listen_impl(Arc::new(listener), addr).await
         } else if !self.config.pub_certs.is_none() && !self.config.priv_cert.is_none() {
             let listener = TcpListener::bind(addr).await?;
-
+            let listener = ProxyProtocolListener::new(listener, self.config.proxy_protocol);
             let listener = TlsListener::bind(
                 listener,
                 self.config.pub_certs.clone().unwrap(),
                 self.config.priv_cert.clone().unwrap(),
             )
-                .await?;
+            .await?;
             self.listen_impl(Arc::new(listener), addr).await
         } else {
             bail!("pub_cert and priv_cert should be both set or unset")
@@ -198,57 +627,108 @@ impl<App: Sync + Send + 'static> HttpServer<App> {
     }
 
     async fn listen_impl<T: ConnectionListener + 'static>(
-        self,
+        mut self,
         listener: Arc<T>,
         listen_addr: SocketAddr,
     ) -> Result<()> {
         info!("{} listening on {}", self.config.name, listen_addr);
 
+        // endpoints are registered once and served over both protocols, with the same budget
+        self.rate_limiter = Arc::new(RequestLimiter::new(
+            &self.handlers,
+            &self.config.rate_limits,
+            self.form_keys.take(),
+        ));
+        let websocket = self.websocket.take().map(|mut websocket| {
+            websocket.handlers = self.handlers.clone();
+            websocket.toolbox = self.toolbox.clone();
+            websocket.errors = self.errors.clone();
+            websocket.rate_limiter = Arc::clone(&self.rate_limiter);
+            websocket.start::<Upgraded>()
+        });
         let this = Arc::new(self);
+        let connections = TaskTracker::default();
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
         loop {
             let ret = async {
                 let (stream, addr) = listener.accept().await?;
                 let listener2 = Arc::clone(&listener);
                 let this = Arc::clone(&this);
-                tokio::spawn(async move {
+                let websocket = websocket.clone();
+                connections.spawn(async move {
                     let ret: Result<()> = async {
-                        let stream = listener2.handshake(stream).await?;
+                        let (stream, addr) = listener2.handshake(stream, addr).await?;
                         info!("Accepted stream from {}", addr);
 
-                        this.handle_connection(addr, stream).await;
+                        this.handle_connection(addr, stream, websocket).await;
                         Ok(())
                     }
-                        .await;
+                    .await;
                     if let Err(err) = ret {
                         error!("Error while handshaking stream: {:?}", err);
                     }
                 });
                 Ok::<_, Error>(())
-            }
-                .await;
+            };
+            let ret = tokio::select! {
+                ret = ret => ret,
+                _ = &mut shutdown => break,
+            };
             if let Err(err) = ret {
                 error!("Error while accepting stream: {:?}", err);
             }
         }
+        this.drain(connections, websocket).await;
+        Ok(())
     }
-}
 
-struct ImmediateAcceptor<T> {
-    listener: Option<T>,
+    /// Lets the requests in progress finish once no more connections are accepted, then closes
+    /// the websocket connections. Gives up after the grace period.
+    async fn drain(&self, connections: TaskTracker, websocket: Option<WebsocketUpgrades<App>>) {
+        let grace = self.config.shutdown_grace();
+        info!(
+            "Shutting down, draining {} connections for up to {:?}",
+            connections.running(),
+            grace
+        );
+        let drain = async {
+            self.toolbox.tasks.close();
+            // event streams end as if their request was answered
+            let streams: Vec<_> = self.streams.iter().map(|x| x.value().clone()).collect();
+            for stream in streams {
+                let _ = stream.send(WsResponse::Close).await;
+            }
+            self.toolbox.tasks.wait().await;
+            connections.wait().await;
+            if let Some((websocket, states)) = websocket {
+                websocket.close_connections(&states).await;
+            }
+        };
+        if tokio::time::timeout(grace, drain).await.is_err() {
+            warn!(
+                "Shutdown grace period of {:?} expired with {} handlers running",
+                grace,
+                self.toolbox.tasks.running()
+            );
+        }
+    }
 }
 
-impl<T: Unpin> Accept for ImmediateAcceptor<T> {
-    type Conn = T;
-    type Error = Infallible;
-
-    fn poll_accept(
-        mut self: Pin<&mut Self>,
-        _cx: &mut std::task::Context<'_>,
-    ) -> Poll<Option<std::result::Result<Self::Conn, Self::Error>>> {
-        let this = &mut *self;
-        match this.listener.take() {
-            Some(x) => Poll::Ready(Some(Ok(x))),
-            None => Poll::Ready(None),
-        }
+impl<App: Clone + Sync + Send + 'static> HttpServer<App> {
+    /// Also serves websocket clients on the same port, handing `Upgrade: websocket` requests to
+    /// the handshake and `auth_controller` of `WebsocketServer`. Their messages reach the
+    /// handlers of this server by endpoint code.
+    pub fn enable_websocket(&mut self, auth_controller: impl AuthController + 'static) {
+        let mut websocket = WebsocketServer::new(self.config.clone());
+        websocket.add_auth_controller(auth_controller);
+        self.websocket = Some(websocket);
     }
 }
+
+fn see_other<B: From<String>>(url: &str) -> Result<Response<B>> {
+    Ok(Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(LOCATION, url)
+        .body(String::new().into())?)
+}
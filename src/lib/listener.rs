@@ -1,9 +1,11 @@
 use eyre::*;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWrite;
 use tokio::net::TcpStream;
 use tokio_rustls::{server::TlsStream, TlsAcceptor};
@@ -14,11 +16,13 @@ pub trait ConnectionListener: Send + Sync + Unpin {
     type Channel1Future<'a>: Future<Output = Result<(Self::Channel1, SocketAddr)>> + Send + 'a
     where
         Self: 'a;
-    type Channel2Future<'a>: Future<Output = Result<Self::Channel2>> + Send + 'a
+    /// Resolves to the channel and the address of the client, which is not the peer of the
+    /// TCP connection when a load balancer forwards it
+    type Channel2Future<'a>: Future<Output = Result<(Self::Channel2, SocketAddr)>> + Send + 'a
     where
         Self: 'a;
     fn accept(&self) -> Self::Channel1Future<'_>;
-    fn handshake(&self, channel: Self::Channel1) -> Self::Channel2Future<'_>;
+    fn handshake(&self, channel: Self::Channel1, addr: SocketAddr) -> Self::Channel2Future<'_>;
 }
 
 pub struct TcpListener {
@@ -34,15 +38,115 @@ impl ConnectionListener for TcpListener {
     type Channel1 = TcpStream;
     type Channel2 = TcpStream;
     type Channel1Future<'a> = impl Future<Output = Result<(Self::Channel1, SocketAddr)>> + 'a;
-    type Channel2Future<'a> = impl Future<Output = Result<Self::Channel2>> + 'a;
+    type Channel2Future<'a> = impl Future<Output = Result<(Self::Channel2, SocketAddr)>> + 'a;
     fn accept(&self) -> Self::Channel1Future<'_> {
         async {
             let (stream, addr) = self.listener.accept().await?;
             Ok((stream, addr))
         }
     }
-    fn handshake(&self, channel: Self::Channel1) -> Self::Channel2Future<'_> {
-        async move { Ok(channel) }
+    fn handshake(&self, channel: Self::Channel1, addr: SocketAddr) -> Self::Channel2Future<'_> {
+        async move { Ok((channel, addr)) }
+    }
+}
+
+/// Signature starting a PROXY protocol v2 header
+const PROXY_V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest PROXY protocol v1 header, including the line break
+const PROXY_V1_MAX_LEN: usize = 107;
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decodes the PROXY protocol v1 or v2 header a load balancer sends ahead of each connection,
+/// so the address of the client replaces the one of the load balancer. When enabled every
+/// connection must start with a header, the port must only be reachable through the load
+/// balancer.
+pub struct ProxyProtocolListener<T> {
+    inner: T,
+    enabled: bool,
+}
+impl<T: ConnectionListener> ProxyProtocolListener<T> {
+    pub fn new(inner: T, enabled: bool) -> Self {
+        Self { inner, enabled }
+    }
+}
+impl<T: ConnectionListener + 'static> ConnectionListener for ProxyProtocolListener<T> {
+    type Channel1 = T::Channel1;
+    type Channel2 = T::Channel2;
+    type Channel1Future<'a> = impl Future<Output = Result<(Self::Channel1, SocketAddr)>> + 'a;
+    type Channel2Future<'a> = impl Future<Output = Result<(Self::Channel2, SocketAddr)>> + 'a;
+    fn accept(&self) -> Self::Channel1Future<'_> {
+        self.inner.accept()
+    }
+    fn handshake(&self, channel: Self::Channel1, addr: SocketAddr) -> Self::Channel2Future<'_> {
+        async move {
+            let (mut channel, addr) = self.inner.handshake(channel, addr).await?;
+            if !self.enabled {
+                return Ok((channel, addr));
+            }
+            let source =
+                tokio::time::timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut channel))
+                    .await
+                    .with_context(|| format!("No PROXY protocol header from {}", addr))??;
+            Ok((channel, source.unwrap_or(addr)))
+        }
+    }
+}
+
+/// Source address of a PROXY protocol header, `None` for connections the load balancer opened
+/// itself or that came from a non-IP socket. Reads exactly the header off the stream.
+async fn read_proxy_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    // the shortest v1 header `PROXY UNKNOWN\r\n` is longer than the v2 signature
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == PROXY_V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let [version_command, family, len_high, len_low] = header;
+        ensure!(
+            version_command >> 4 == 2,
+            "Unsupported PROXY protocol version {}",
+            version_command >> 4
+        );
+        let mut addresses = vec![0u8; u16::from_be_bytes([len_high, len_low]) as usize];
+        stream.read_exact(&mut addresses).await?;
+        // LOCAL, e.g. health checks of the load balancer
+        if version_command & 0x0f == 0 {
+            return Ok(None);
+        }
+        let source = match family >> 4 {
+            // AF_INET: source, destination, source port, destination port
+            1 if addresses.len() >= 12 => {
+                let ip: [u8; 4] = addresses[..4].try_into()?;
+                Some((IpAddr::from(ip), [addresses[8], addresses[9]]))
+            }
+            // AF_INET6
+            2 if addresses.len() >= 36 => {
+                let ip: [u8; 16] = addresses[..16].try_into()?;
+                Some((IpAddr::from(ip), [addresses[32], addresses[33]]))
+            }
+            _ => None,
+        };
+        return Ok(source.map(|(ip, port)| SocketAddr::new(ip, u16::from_be_bytes(port))));
+    }
+    ensure!(
+        start.starts_with(b"PROXY "),
+        "Connection does not start with a PROXY protocol header"
+    );
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        ensure!(
+            line.len() < PROXY_V1_MAX_LEN,
+            "PROXY protocol header too long"
+        );
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line)?.trim_end();
+    match line.split(' ').collect::<Vec<_>>().as_slice() {
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+            Ok(Some(SocketAddr::new(source.parse()?, port.parse()?)))
+        }
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        _ => bail!("Invalid PROXY protocol header {:?}", line),
     }
 }
 
@@ -84,15 +188,15 @@ impl<T: ConnectionListener + 'static> ConnectionListener for TlsListener<T> {
     type Channel1 = T::Channel1;
     type Channel2 = TlsStream<T::Channel2>;
     type Channel1Future<'a> = impl Future<Output = Result<(Self::Channel1, SocketAddr)>> + 'a;
-    type Channel2Future<'a> = impl Future<Output = Result<Self::Channel2>> + 'a;
+    type Channel2Future<'a> = impl Future<Output = Result<(Self::Channel2, SocketAddr)>> + 'a;
     fn accept(&self) -> Self::Channel1Future<'_> {
         self.tcp.accept()
     }
-    fn handshake(&self, channel: Self::Channel1) -> Self::Channel2Future<'_> {
-        async {
-            let channel = self.tcp.handshake(channel).await?;
+    fn handshake(&self, channel: Self::Channel1, addr: SocketAddr) -> Self::Channel2Future<'_> {
+        async move {
+            let (channel, addr) = self.tcp.handshake(channel, addr).await?;
             let tls_stream = self.acceptor.accept(channel).await?;
-            Ok(tls_stream)
+            Ok((tls_stream, addr))
         }
     }
 }
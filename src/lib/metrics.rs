@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+enum Series {
+    Counter(u64),
+    Histogram {
+        buckets: [u64; LATENCY_BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    /// Keyed by the rendered labels, e.g. `endpoint="AddCrmLead",status="200"`
+    series: BTreeMap<String, Series>,
+}
+
+/// Counters and latency histograms of the process, served by `HttpServer` on `/metrics` in
+/// the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Metrics {
+    /// Registry shared by the servers and the clients of the process
+    pub fn global() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(Metrics::default)
+    }
+
+    fn update(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: &'static str,
+        labels: &[(&str, &str)],
+        update: impl FnOnce(&mut Series),
+        new: impl FnOnce() -> Series,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind,
+            series: BTreeMap::new(),
+        });
+        update(family.series.entry(labels).or_insert_with(new));
+    }
+
+    pub fn increment(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        self.update(
+            name,
+            help,
+            "counter",
+            labels,
+            |series| {
+                if let Series::Counter(count) = series {
+                    *count += 1;
+                }
+            },
+            || Series::Counter(0),
+        );
+    }
+
+    pub fn observe(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        elapsed: Duration,
+    ) {
+        let seconds = elapsed.as_secs_f64();
+        self.update(
+            name,
+            help,
+            "histogram",
+            labels,
+            |series| {
+                if let Series::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } = series
+                {
+                    for (bucket, bound) in buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                        if seconds <= bound {
+                            *bucket += 1;
+                        }
+                    }
+                    *sum += seconds;
+                    *count += 1;
+                }
+            },
+            || Series::Histogram {
+                buckets: [0; LATENCY_BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            },
+        );
+    }
+
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, family.kind);
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Counter(count) => {
+                        let _ = writeln!(out, "{}{} {}", name, braces(labels), count);
+                    }
+                    Series::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let separator = if labels.is_empty() { "" } else { "," };
+                        for (bucket, bound) in buckets.iter().zip(LATENCY_BUCKETS) {
+                            let _ = writeln!(
+                                out,
+                                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                                name, labels, separator, bound, bucket
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+                            name, labels, separator, count
+                        );
+                        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
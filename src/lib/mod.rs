@@ -8,7 +8,10 @@ pub mod handler;
 pub mod http;
 mod listener;
 pub mod log;
+pub mod metrics;
+pub mod rate_limit;
 pub mod scheduler;
+pub mod shutdown;
 pub mod toolbox;
 pub mod utils;
 pub mod ws;
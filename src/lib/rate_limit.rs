@@ -0,0 +1,84 @@
+use crate::ws::EndpointRegistry;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota};
+use model::endpoint::RateLimit;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Buckets of clients that were not seen for a while are dropped once this many are tracked
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+/// Tells whether a `form_key` parameter belongs to a known form
+pub type FormKeyFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    ip: IpAddr,
+    form_key: Option<String>,
+}
+
+/// Throttles the requests of each client IP to the endpoints with a rate limit. A `form_key`
+/// accepted by the `FormKeyFilter` gets a bucket of its own per client, any other key draws
+/// from the bucket of the IP, so made up keys cannot buy a client more requests.
+/// Shared by the HTTP and websocket servers of a process.
+#[derive(Default)]
+pub struct RequestLimiter {
+    /// Keyed by endpoint code
+    limiters: HashMap<u32, DefaultKeyedRateLimiter<ClientKey>>,
+    form_keys: Option<FormKeyFilter>,
+    clock: DefaultClock,
+}
+
+impl RequestLimiter {
+    /// Limits from `overrides`, keyed by endpoint name, take precedence over the schemas.
+    /// Without `form_keys` every request is only limited by its client IP.
+    pub fn new(
+        handlers: &EndpointRegistry,
+        overrides: &HashMap<String, RateLimit>,
+        form_keys: Option<FormKeyFilter>,
+    ) -> Self {
+        let limiters = handlers
+            .iter()
+            .filter_map(|endpoint| {
+                let schema = &endpoint.schema;
+                let limit = overrides.get(&schema.name).or(schema.rate_limit.as_ref())?;
+                let rate = NonZeroU32::new(limit.requests_per_minute)?;
+                let burst = NonZeroU32::new(limit.burst).unwrap_or(rate);
+                let quota = Quota::per_minute(rate).allow_burst(burst);
+                Some((schema.code, DefaultKeyedRateLimiter::keyed(quota)))
+            })
+            .collect();
+        Self {
+            limiters,
+            form_keys,
+            clock: DefaultClock::default(),
+        }
+    }
+
+    /// Takes a request from the bucket of the client, or tells how long it has to wait
+    pub fn check(&self, code: u32, ip: IpAddr, params: &Value) -> Result<(), Duration> {
+        let limiter = match self.limiters.get(&code) {
+            Some(limiter) => limiter,
+            None => return Ok(()),
+        };
+        if limiter.len() > MAX_TRACKED_CLIENTS {
+            limiter.retain_recent();
+        }
+        // parameters arrive in camel case like every request
+        let form_key = params
+            .get("formKey")
+            .and_then(|x| x.as_str())
+            .filter(|x| self.form_keys.as_ref().map_or(false, |known| known(x)));
+        let key = ClientKey {
+            ip,
+            form_key: form_key.map(|x| x.to_owned()),
+        };
+        limiter
+            .check_key(&key)
+            .map_err(|not_until| not_until.wait_time_from(self.clock.now()))
+    }
+}
@@ -5,6 +5,9 @@ use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::Duration;
 use tokio_cron_scheduler::{Job, JobScheduler};
+use tracing::*;
+
+use crate::shutdown::TaskTracker;
 
 pub struct AdaptiveJob {
     duration: Arc<RwLock<Duration>>,
@@ -29,13 +32,21 @@ impl AdaptiveJob {
             duration: self.duration.clone(),
         }
     }
-    pub async fn run(self) {
+    /// Runs the task every `duration` until `jobs` is closed, then once more after the runs in
+    /// progress finished, so work queued right before a shutdown is not left behind
+    pub async fn run(self, jobs: TaskTracker) {
+        let runs = TaskTracker::default();
         loop {
             let duration = *self.duration.read().unwrap();
-            tokio::time::sleep(duration).await;
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {}
+                _ = jobs.closed() => break,
+            }
             let task = (self.task)();
-            tokio::spawn(task);
+            runs.spawn(task);
         }
+        runs.wait().await;
+        (self.task)().await;
     }
 }
 #[derive(Clone)]
@@ -93,10 +104,27 @@ impl Scheduler {
         self.pending_jobs.push(job);
         Ok(trigger)
     }
-    pub async fn spawn(mut self) {
+    pub async fn spawn(mut self) -> SchedulerHandle {
+        let jobs = TaskTracker::default();
         for job in self.pending_jobs.drain(..) {
-            tokio::task::spawn(job.run());
+            jobs.spawn(job.run(jobs.clone()));
         }
         self.scheduler.start();
+        SchedulerHandle { jobs }
+    }
+}
+
+/// Adaptive jobs of a spawned `Scheduler`, jobs added with `add_job` stop with the process
+pub struct SchedulerHandle {
+    jobs: TaskTracker,
+}
+
+impl SchedulerHandle {
+    /// Stops scheduling and runs every adaptive job a last time, waiting up to `grace` for them
+    pub async fn shutdown(self, grace: Duration) {
+        self.jobs.close();
+        if tokio::time::timeout(grace, self.jobs.wait()).await.is_err() {
+            warn!("Scheduled jobs still running after {:?}", grace);
+        }
     }
 }
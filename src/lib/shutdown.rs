@@ -0,0 +1,101 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::*;
+
+/// How long servers drain when `shutdown_grace_secs` is not configured
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Resolves on SIGTERM, sent by systemd on stop and restart, or on Ctrl-C
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => info!("Received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+                }
+                return;
+            }
+            Err(err) => error!("Failed to listen for SIGTERM: {:?}", err),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Received SIGINT");
+}
+
+/// Counts spawned tasks that are still running, so shutdown can wait for them to finish.
+/// Closing it tells long running tasks to wrap up. Clones track the same tasks.
+#[derive(Clone, Default)]
+pub struct TaskTracker {
+    inner: Arc<TaskTrackerInner>,
+}
+
+#[derive(Default)]
+struct TaskTrackerInner {
+    running: AtomicUsize,
+    idle: Notify,
+    closed: AtomicBool,
+    closing: Notify,
+}
+
+struct TaskGuard(Arc<TaskTrackerInner>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl TaskTracker {
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.inner.running.fetch_add(1, Ordering::AcqRel);
+        let guard = TaskGuard(Arc::clone(&self.inner));
+        tokio::spawn(async move {
+            // also released when the task panics
+            let _guard = guard;
+            f.await
+        });
+    }
+    pub fn running(&self) -> usize {
+        self.inner.running.load(Ordering::Acquire)
+    }
+    /// Asks the tracked tasks to finish, tasks can still be spawned
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.closing.notify_waiters();
+    }
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+    /// Resolves once `close` was called, for tasks that would otherwise wait forever
+    pub async fn closed(&self) {
+        loop {
+            let closing = self.inner.closing.notified();
+            if self.is_closed() {
+                return;
+            }
+            closing.await;
+        }
+    }
+    /// Resolves once no tracked task is running
+    pub async fn wait(&self) {
+        loop {
+            let idle = self.inner.idle.notified();
+            if self.running() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::database::SimpleDbClient;
 use crate::error_code::ErrorCode;
 use crate::log::LogLevel;
+use crate::shutdown::TaskTracker;
 use crate::ws::*;
 use crossbeam::queue::SegQueue;
 use dashmap::DashMap;
@@ -10,6 +11,7 @@ use serde_json::Value;
 use std::fmt::{Debug, Display, Formatter};
 use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::*;
 
@@ -72,22 +74,36 @@ pub struct RequestContext {
 pub struct Toolbox {
     db: Vec<SimpleDbClient>,
     pub send_msg: Arc<dyn Fn(ConnectionId, WsResponse) -> bool + Send + Sync>,
+    /// Handlers spawned with `spawn_ws_response` are cancelled when they run longer
+    pub timeout: Option<Duration>,
+    /// Handlers spawned with `spawn_ws_response`, awaited on shutdown
+    pub tasks: TaskTracker,
 }
 
 impl Toolbox {
     pub fn new() -> Self {
         Self {
             db: vec![],
-            send_msg: Arc::new(|_conn_id, _msg| { false }),
+            send_msg: Arc::new(|_conn_id, _msg| false),
+            timeout: None,
+            tasks: TaskTracker::default(),
         }
     }
 
-    pub fn set_ws_states(&mut self, states: Arc<DashMap<ConnectionId, Arc<WsStreamState>>>, trigger: mpsc::Sender<ConnectionId>, oneshot: bool) {
+    /// Messages to connections not in `states` still go where they went before, so a server
+    /// answering both HTTP and websocket reaches the clients of either
+    pub fn set_ws_states(
+        &mut self,
+        states: Arc<DashMap<ConnectionId, Arc<WsStreamState>>>,
+        trigger: mpsc::Sender<ConnectionId>,
+        oneshot: bool,
+    ) {
+        let fallback = Arc::clone(&self.send_msg);
         self.send_msg = Arc::new(move |conn_id, msg| {
             let state = if let Some(state) = states.get(&conn_id) {
                 state
             } else {
-                return false;
+                return fallback(conn_id, msg);
             };
             Self::send_ws_msg(&state.message_queue, &trigger, conn_id, msg, oneshot);
             true
@@ -103,6 +119,9 @@ impl Toolbox {
     pub fn get_nth_db<T: From<SimpleDbClient>>(&self, index: usize) -> T {
         T::from(self.db.get(index).expect("Db not Initialized").clone())
     }
+    pub fn dbs(&self) -> &[SimpleDbClient] {
+        &self.db
+    }
 
     pub fn send_ws_msg(
         sender: &SegQueue<WsResponse>,
@@ -152,10 +171,10 @@ impl Toolbox {
     pub fn spawn_ws_response<Resp: Send + Serialize>(
         &self,
         ctx: RequestContext,
-        f: impl Future<Output=Result<Resp>> + Send + 'static,
+        f: impl Future<Output = Result<Resp>> + Send + 'static,
     ) {
         #[allow(unused_variables)]
-            let RequestContext {
+        let RequestContext {
             connection_id,
             user_id,
             seq,
@@ -163,8 +182,19 @@ impl Toolbox {
             log_id,
         } = ctx;
         let send_msg = self.send_msg.clone();
-        tokio::spawn(async move {
-            let resp = f.await;
+        let timeout = self.timeout;
+        self.tasks.spawn(async move {
+            let resp = match timeout {
+                // dropping the future on expiry cancels whatever it was waiting for
+                Some(timeout) => tokio::time::timeout(timeout, f).await.unwrap_or_else(|_| {
+                    Err(CustomError::new(
+                        ErrorCode::new(45349643), // TemporarilyUnavailable
+                        format!("Request timed out after {:?}", timeout),
+                    )
+                    .into())
+                }),
+                None => f.await,
+            };
             let resp = match resp {
                 Ok(ok) => WsResponse::Immediate(WsSuccessResponse {
                     method,
@@ -211,7 +241,7 @@ impl Toolbox {
     pub fn spawn_response<Resp: Send + Serialize>(
         &self,
         ctx: RequestContext,
-        f: impl Future<Output=Result<Resp>> + Send + 'static,
+        f: impl Future<Output = Result<Resp>> + Send + 'static,
     ) {
         self.spawn_ws_response(ctx, f);
     }
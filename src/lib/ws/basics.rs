@@ -3,11 +3,12 @@ use crate::handler::RequestHandlerErased;
 use crate::log::LogLevel;
 use crate::toolbox::RequestContext;
 use eyre::*;
+use hyper::HeaderMap;
 use model::endpoint::EndpointSchema;
 use serde::*;
 use serde_json::Value;
 use std::fmt::Debug;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicI64, AtomicU32};
 use std::sync::Arc;
 use tracing::*;
@@ -28,6 +29,9 @@ pub struct WsResponseError {
     pub seq: u32,
     pub log_id: String,
     pub params: Value,
+    /// Message of the code in the language of the client, rendered by the server sending it
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message: String,
 }
 
 #[derive(Debug)]
@@ -35,13 +39,24 @@ pub struct Connection {
     pub connection_id: ConnectionId,
     pub user_id: AtomicI64,
     pub role: AtomicU32,
+    /// Peer of the connection, or the client a load balancer announced with the PROXY protocol
     pub address: SocketAddr,
+    /// Client behind the trusted proxies `address` belongs to, else the IP of `address`
+    pub client_ip: IpAddr,
     pub log_id: u64,
+    /// Headers of the HTTP request being handled, or of the handshake of a websocket connection
+    pub headers: HeaderMap,
 }
 impl Connection {
     pub fn get_user_id(&self) -> i64 {
         self.user_id.load(std::sync::atomic::Ordering::Relaxed)
     }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|x| x.to_str().ok())
+    }
+    pub fn origin(&self) -> Option<&str> {
+        self.header("origin")
+    }
 }
 
 pub type WsSuccessResponse = WsSuccessResponseGeneric<serde_json::Value>;
@@ -100,6 +115,7 @@ pub fn internal_error_to_resp(ctx: &RequestContext, code: ErrorCode, err0: Error
         seq: ctx.seq,
         log_id,
         params: Value::Null,
+        message: String::new(),
     };
     error!("Internal error: {:?} {:?}", err, err0);
     WsResponse::Error(err)
@@ -118,6 +134,7 @@ pub fn request_error_to_resp(
         seq: ctx.seq,
         log_id,
         params,
+        message: String::new(),
     };
     warn!("Request error: {:?}", err);
     WsResponse::Error(err)
@@ -24,6 +24,9 @@ impl<S> WebsocketStates<S> {
         self.connection.remove(&connection_id);
         self.states.remove(&connection_id);
     }
+    pub fn connection_ids(&self) -> Vec<u32> {
+        self.states.iter().map(|x| *x.key()).collect()
+    }
     pub fn get_connection(&self, connection_id: u32) -> Option<Arc<WsStreamSink<S>>> {
         self.connection
             .get(&connection_id)
@@ -20,7 +20,8 @@ use tracing::*;
 
 pub struct VerifyProtocol {
     pub addr: SocketAddr,
-    pub tx: tokio::sync::mpsc::Sender<String>,
+    /// Receives the `Sec-WebSocket-Protocol` and the headers of the handshake
+    pub tx: tokio::sync::mpsc::Sender<(String, hyper::HeaderMap)>,
 }
 
 impl Callback for VerifyProtocol {
@@ -44,7 +45,9 @@ impl Callback for VerifyProtocol {
                 .to_string(),
             None => "".to_string(),
         };
-        self.tx.try_send(protocol_str.clone()).unwrap();
+        self.tx
+            .try_send((protocol_str.clone(), request.headers().clone()))
+            .unwrap();
         response
             .headers_mut()
             .append("Date", Utc::now().to_rfc2822().parse().unwrap());
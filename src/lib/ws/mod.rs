@@ -3,6 +3,7 @@ mod client;
 mod conn;
 mod headers;
 mod push;
+mod registry;
 mod server;
 
 pub use basics::*;
@@ -10,4 +11,5 @@ pub use client::*;
 pub use conn::*;
 pub use headers::*;
 pub use push::*;
+pub use registry::*;
 pub use server::*;
@@ -0,0 +1,50 @@
+use crate::handler::{RequestHandler, RequestHandlerErased};
+use crate::ws::{check_handler, WsEndpoint};
+use model::endpoint::EndpointSchema;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Endpoints of a server, looked up by name for HTTP requests and by code for websocket
+/// messages. Clones share the handlers, so one registry can serve both protocols.
+#[derive(Clone, Default)]
+pub struct EndpointRegistry {
+    by_name: HashMap<String, Arc<WsEndpoint>>,
+    by_code: HashMap<u32, Arc<WsEndpoint>>,
+}
+
+impl EndpointRegistry {
+    pub fn add_handler<T: RequestHandler + 'static>(&mut self, schema: EndpointSchema, handler: T) {
+        check_handler::<T>(&schema).expect("Invalid handler");
+        self.add_handler_erased(schema, Arc::new(handler))
+    }
+    pub fn add_handler_erased(
+        &mut self,
+        schema: EndpointSchema,
+        handler: Arc<dyn RequestHandlerErased>,
+    ) {
+        let endpoint = Arc::new(WsEndpoint { schema, handler });
+        let old = self
+            .by_name
+            .get(&endpoint.schema.name)
+            .or_else(|| self.by_code.get(&endpoint.schema.code));
+        if let Some(old) = old {
+            panic!(
+                "Overwriting handler for endpoint {} {}",
+                old.schema.code, old.schema.name
+            );
+        }
+        self.by_name
+            .insert(endpoint.schema.name.clone(), Arc::clone(&endpoint));
+        self.by_code.insert(endpoint.schema.code, endpoint);
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&WsEndpoint> {
+        self.by_name.get(name).map(|x| x.as_ref())
+    }
+    pub fn get_by_code(&self, code: u32) -> Option<&WsEndpoint> {
+        self.by_code.get(&code).map(|x| x.as_ref())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &WsEndpoint> {
+        self.by_code.values().map(|x| x.as_ref())
+    }
+}
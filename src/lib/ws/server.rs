@@ -2,8 +2,10 @@ use eyre::*;
 use futures::stream::SplitStream;
 use futures::SinkExt;
 use futures::StreamExt;
-use serde_json::Value;
-use std::collections::HashMap;
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use serde_json::{json, Value};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
@@ -11,6 +13,9 @@ use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::handshake::server::Callback;
+use tokio_tungstenite::tungstenite::protocol::Role;
 use tokio_tungstenite::tungstenite::Error as WsError;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
@@ -18,28 +23,31 @@ use tracing::*;
 
 use crate::config::AppConfig;
 use crate::database::SimpleDbClient;
-use crate::error_code::ErrorCode;
+use crate::error_code::{ErrorCatalog, ErrorCode, ErrorCodeInfo, LocalizedErrorMessages};
 use crate::handler::*;
-use crate::listener::{ConnectionListener, TcpListener, TlsListener};
+use crate::http::client_ip;
+use crate::listener::{ConnectionListener, ProxyProtocolListener, TcpListener, TlsListener};
+use crate::rate_limit::RequestLimiter;
+use crate::shutdown::shutdown_signal;
 use crate::toolbox::{RequestContext, Toolbox};
 use crate::utils::{get_conn_id, get_log_id};
 use crate::ws::basics::{Connection, WsRequest};
 use crate::ws::SimpleAuthContoller;
 use crate::ws::VerifyProtocol;
 use crate::ws::WebsocketStates;
-use crate::ws::WsEndpoint;
 use crate::ws::WsResponse;
 use crate::ws::WsStreamSink;
 use crate::ws::{request_error_to_resp, WsStreamState};
-use crate::ws::{AuthController, ConnectionId};
+use crate::ws::{AuthController, ConnectionId, EndpointRegistry};
 use model::endpoint::EndpointSchema;
 
 pub struct WebsocketServer<App> {
     pub auth_controller: Arc<dyn AuthController>,
-    pub handlers: HashMap<u32, WsEndpoint>,
-    pub message_receiver: Option<mpsc::Receiver<ConnectionId>>,
+    pub handlers: EndpointRegistry,
     pub toolbox: Toolbox,
     pub config: AppConfig<App>,
+    pub(crate) errors: ErrorCatalog,
+    pub(crate) rate_limiter: Arc<RequestLimiter>,
 }
 
 impl<App: Sync + Send + 'static> WebsocketServer<App> {
@@ -47,9 +55,10 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
         Self {
             auth_controller: Arc::new(SimpleAuthContoller),
             handlers: Default::default(),
-            message_receiver: None,
             toolbox: Toolbox::new(),
             config,
+            errors: Default::default(),
+            rate_limiter: Default::default(),
         }
     }
     pub fn add_auth_controller(&mut self, controller: impl AuthController + 'static) {
@@ -58,26 +67,39 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
     pub fn add_database(&mut self, db: SimpleDbClient) {
         self.toolbox.add_db(db);
     }
+    pub fn add_error_codes(&mut self, codes: &[ErrorCodeInfo]) {
+        self.errors.add_codes(codes);
+    }
+    /// Translations of the error messages, picked by the `Accept-Language` of the handshake
+    pub fn add_error_messages(&mut self, messages: &[LocalizedErrorMessages]) {
+        self.errors.add_messages(messages);
+    }
 
     pub fn add_handler<T: RequestHandler + 'static>(&mut self, schema: EndpointSchema, handler: T) {
-        check_handler::<T>(&schema).expect("Invalid handler");
-        self.add_handler_erased(schema, Arc::new(handler))
+        self.handlers.add_handler(schema, handler)
     }
     pub fn add_handler_erased(
         &mut self,
         schema: EndpointSchema,
         handler: Arc<dyn RequestHandlerErased>,
     ) {
-        let old = self
-            .handlers
-            .insert(schema.code, WsEndpoint { schema, handler });
-        if let Some(old) = old {
-            panic!(
-                "Overwriting handler for endpoint {} {}",
-                old.schema.code, old.schema.name
-            );
-        }
+        self.handlers.add_handler_erased(schema, handler)
+    }
+
+    /// Routes messages sent through the toolbox to the connections in the returned states and
+    /// starts flushing them. Messages for other connections still go to the previous route.
+    pub fn start<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        mut self,
+    ) -> (Arc<Self>, Arc<WebsocketStates<S>>) {
+        let states = Arc::new(WebsocketStates::new());
+        let (tx, rx) = mpsc::channel(100);
+        self.toolbox
+            .set_ws_states(states.clone_states(), tx, self.config.header_only);
+        let this = Arc::new(self);
+        tokio::spawn(Arc::clone(&this).send_msg(Arc::clone(&states), rx));
+        (this, states)
     }
+
     async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         self: Arc<Self>,
         addr: SocketAddr,
@@ -88,44 +110,12 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
             let (tx, mut rx) = mpsc::channel(1);
             let hs = tokio_tungstenite::accept_hdr_async(stream, VerifyProtocol { addr, tx }).await;
             let stream = wrap_ws_error(hs)?;
-            let conn = Arc::new(Connection {
-                connection_id: get_conn_id(),
-                user_id: Default::default(),
-                role: AtomicU32::new(0),
-                address: addr,
-                log_id: get_log_id(),
-            });
-            debug!(?addr, "New connection handshaken {:?}", conn);
-            let headers = rx
+            let (headers, http_headers) = rx
                 .recv()
                 .await
                 .ok_or_else(|| eyre!("Failed to receive ws headers"))?;
-            let (ws_sink, ws_stream) = stream.split();
-
-            let conn = Arc::clone(&conn);
-            states.insert(conn.connection_id, ws_sink, conn.clone());
-
-            let auth_result = Arc::clone(&self.auth_controller)
-                .auth(&self.toolbox, headers, Arc::clone(&conn))
+            self.serve(addr, states, stream, headers, http_headers)
                 .await;
-            let raw_ctx = RequestContext {
-                connection_id: conn.connection_id,
-                user_id: 0,
-                seq: 0,
-                method: 0,
-                log_id: conn.log_id.clone(),
-            };
-            if let Err(err) = auth_result {
-                self.toolbox.send_request_error(
-                    &raw_ctx,
-                    ErrorCode::new(100400), // BadRequest
-                    err.to_string(),
-                );
-            }
-            if !self.config.header_only {
-                debug!(?addr, "Starting ws recv_msg loop");
-                self.recv_msg(conn, states, ws_stream).await;
-            }
             Ok(())
         }
         .await;
@@ -134,6 +124,102 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
         }
     }
 
+    /// Answers the `Upgrade: websocket` request of a client connected to an HTTP server, the
+    /// connection is served as websocket once hyper hands it over
+    pub fn upgrade(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        states: Arc<WebsocketStates<Upgraded>>,
+        mut request: Request<Body>,
+    ) -> Response<Body> {
+        let key = match request.headers().get(SEC_WEBSOCKET_KEY) {
+            Some(key) => derive_accept_key(key.as_bytes()),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("Missing Sec-WebSocket-Key".into())
+                    .unwrap();
+            }
+        };
+        let on_upgrade = hyper::upgrade::on(&mut request);
+        let (parts, _) = request.into_parts();
+        let request = Request::from_parts(parts, ());
+        let response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_ACCEPT, key)
+            .body(())
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let response = match (VerifyProtocol { addr, tx }).on_request(&request, response) {
+            Ok(response) => response,
+            Err(response) => return response.map(|x| x.unwrap_or_default().into()),
+        };
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    error!(?addr, "Error while upgrading to websocket {:?}", err);
+                    return;
+                }
+            };
+            let stream = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            if let Some((headers, http_headers)) = rx.recv().await {
+                self.serve(addr, states, stream, headers, http_headers)
+                    .await;
+            }
+        });
+        response.map(|()| Body::empty())
+    }
+
+    /// Authenticates a handshaken connection and handles its requests until it closes
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        states: Arc<WebsocketStates<S>>,
+        stream: WebSocketStream<S>,
+        headers: String,
+        http_headers: HeaderMap,
+    ) {
+        let conn = Arc::new(Connection {
+            connection_id: get_conn_id(),
+            user_id: Default::default(),
+            role: AtomicU32::new(0),
+            address: addr,
+            client_ip: client_ip(addr.ip(), &http_headers, &self.config.trusted_proxies),
+            log_id: get_log_id(),
+            headers: http_headers,
+        });
+        debug!(?addr, "New connection handshaken {:?}", conn);
+        let (ws_sink, ws_stream) = stream.split();
+
+        let conn = Arc::clone(&conn);
+        states.insert(conn.connection_id, ws_sink, conn.clone());
+
+        let auth_result = Arc::clone(&self.auth_controller)
+            .auth(&self.toolbox, headers, Arc::clone(&conn))
+            .await;
+        let raw_ctx = RequestContext {
+            connection_id: conn.connection_id,
+            user_id: 0,
+            seq: 0,
+            method: 0,
+            log_id: conn.log_id.clone(),
+        };
+        if let Err(err) = auth_result {
+            self.toolbox.send_request_error(
+                &raw_ctx,
+                ErrorCode::new(100400), // BadRequest
+                err.to_string(),
+            );
+        }
+        if !self.config.header_only {
+            debug!(?addr, "Starting ws recv_msg loop");
+            self.recv_msg(conn, states, ws_stream).await;
+        }
+    }
+
     pub async fn recv_msg<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         self: Arc<Self>,
         conn: Arc<Connection>,
@@ -195,7 +281,7 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
                         method: req.method,
                         ..context
                     };
-                    let handler = self.handlers.get(&req.method);
+                    let handler = self.handlers.get_by_code(req.method);
                     let handler = match handler {
                         Some(handler) => handler,
                         None => {
@@ -210,6 +296,21 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
                             continue;
                         }
                     };
+                    let limited = self
+                        .rate_limiter
+                        .check(req.method, conn.client_ip, &req.params);
+                    if let Err(retry_after) = limited {
+                        let retry_after_secs = (retry_after.as_secs_f64().ceil() as u64).max(1);
+                        self.toolbox.send(
+                            context.connection_id,
+                            request_error_to_resp(
+                                &context,
+                                ErrorCode::new(45349645), // BackPressureIncreased
+                                json!({ "retry_after_secs": retry_after_secs }),
+                            ),
+                        );
+                        continue;
+                    }
                     handler
                         .handler
                         .handle(&self.toolbox, context, Arc::clone(&conn), req.params);
@@ -235,7 +336,14 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
     ) {
         let addr = state.conn.address;
         let mut sink = conn.ws_sink.lock().await;
-        while let Some(msg) = state.message_queue.pop() {
+        let language = state
+            .conn
+            .header("accept-language")
+            .and_then(|x| self.errors.negotiate(x));
+        while let Some(mut msg) = state.message_queue.pop() {
+            if let WsResponse::Error(err) = &mut msg {
+                err.message = self.errors.render(err.code, language, &err.params);
+            }
             let timeout_operation = async {
                 match &msg {
                     WsResponse::Close => {
@@ -295,10 +403,11 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
             .context("Failed to resolve address")?;
         if self.config.pub_certs.is_none() && self.config.priv_cert.is_none() {
             let listener = TcpListener::bind(addr).await?;
+            let listener = ProxyProtocolListener::new(listener, self.config.proxy_protocol);
             self.listen_impl(Arc::new(listener), addr).await
         } else if !self.config.pub_certs.is_none() && !self.config.priv_cert.is_none() {
             let listener = TcpListener::bind(addr).await?;
-
+            let listener = ProxyProtocolListener::new(listener, self.config.proxy_protocol);
             let listener = TlsListener::bind(
                 listener,
                 self.config.pub_certs.clone().unwrap(),
@@ -318,13 +427,14 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
     ) -> Result<()> {
         info!("{} listening on {}", self.config.name, listen_addr);
 
-        let message_receiver = self.message_receiver.take().unwrap();
-        let states = Arc::new(WebsocketStates::new());
-        let (tx, rx) = mpsc::channel(100);
-        self.message_receiver = Some(rx);
-        self.toolbox.set_ws_states(states.clone_states(), tx, self.config.header_only);
-        let this = Arc::new(self);
-        tokio::spawn(Arc::clone(&this).send_msg(Arc::clone(&states), message_receiver));
+        self.rate_limiter = Arc::new(RequestLimiter::new(
+            &self.handlers,
+            &self.config.rate_limits,
+            None,
+        ));
+        let (this, states) = self.start::<T::Channel2>();
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
         loop {
             let ret = async {
                 let (stream, addr) = listener.accept().await?;
@@ -333,7 +443,7 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
                 let states = Arc::clone(&states);
                 tokio::spawn(async move {
                     let ret: Result<()> = async {
-                        let stream = listener2.handshake(stream).await?;
+                        let (stream, addr) = listener2.handshake(stream, addr).await?;
                         info!("Accepted stream from {}", addr);
 
                         this.handle_connection(addr, states, stream).await;
@@ -345,15 +455,52 @@ impl<App: Sync + Send + 'static> WebsocketServer<App> {
                     }
                 });
                 Ok::<_, Error>(())
-            }
-            .await;
+            };
+            let ret = tokio::select! {
+                ret = ret => ret,
+                _ = &mut shutdown => break,
+            };
             if let Err(err) = ret {
                 error!("Error while accepting stream: {:?}", err);
             }
         }
+        let grace = this.config.shutdown_grace();
+        info!("Shutting down, draining connections for up to {:?}", grace);
+        let drain = async {
+            this.toolbox.tasks.close();
+            this.toolbox.tasks.wait().await;
+            this.close_connections(&states).await;
+        };
+        if tokio::time::timeout(grace, drain).await.is_err() {
+            warn!("Shutdown grace period of {:?} expired", grace);
+        }
+        Ok(())
+    }
+
+    /// Sends `Close` to every client after the messages already queued for it and waits until
+    /// the connections are gone
+    pub async fn close_connections<S>(&self, states: &WebsocketStates<S>) {
+        for connection_id in states.connection_ids() {
+            self.toolbox.send(connection_id, WsResponse::Close);
+        }
+        while !states.connection_ids().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
     }
 }
 
+/// Whether an HTTP request asks to switch the connection to websocket
+pub fn is_websocket_upgrade<B>(request: &Request<B>) -> bool {
+    let has = |name, value: &str| {
+        request.headers().get_all(name).iter().any(|x| {
+            x.to_str().map_or(false, |x| {
+                x.split(',').any(|x| x.trim().eq_ignore_ascii_case(value))
+            })
+        })
+    };
+    has(UPGRADE, "websocket") && has(CONNECTION, "upgrade")
+}
+
 pub fn wrap_ws_error<T>(err: Result<T, WsError>) -> Result<T> {
     err.map_err(|x| eyre!(x))
 }
@@ -9,6 +9,23 @@ pub struct EndpointSchema {
     pub stream_response: Vec<Field>,
     pub description: String,
     pub json_schema: serde_json::Value,
+    /// Overrides `handler_timeout_secs` of the server
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Requests a single client may send, the `rate_limits` of the server take precedence
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Also served to HTTP `GET` requests, which pass the parameters in the query string
+    #[serde(default)]
+    pub allow_get: bool,
+}
+
+/// Token bucket applied per client IP of an endpoint, and per known `form_key` of a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub requests_per_minute: u32,
+    /// Requests that may be sent at once before the sustained rate applies
+    pub burst: u32,
 }
 
 impl EndpointSchema {
@@ -26,10 +43,28 @@ impl EndpointSchema {
             stream_response: vec![],
             description: "".to_string(),
             json_schema: Default::default(),
+            timeout_secs: None,
+            rate_limit: None,
+            allow_get: false,
         }
     }
     pub fn with_stream_response(mut self, stream_response: Vec<Field>) -> Self {
         self.stream_response = stream_response;
         self
     }
+    pub fn with_timeout_secs(mut self, timeout_secs: u64) -> Self {
+        self.timeout_secs = Some(timeout_secs);
+        self
+    }
+    pub fn with_allow_get(mut self) -> Self {
+        self.allow_get = true;
+        self
+    }
+    pub fn with_rate_limit(mut self, requests_per_minute: u32, burst: u32) -> Self {
+        self.rate_limit = Some(RateLimit {
+            requests_per_minute,
+            burst,
+        });
+        self
+    }
 }
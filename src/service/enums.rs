@@ -36,6 +36,27 @@ pub fn get_enums() -> Vec<Type> {
                 EnumVariant::new("historical", 8),
             ],
         ),
+        Type::enum_(
+            "lead_status".to_owned(),
+            vec![
+                EnumVariant::new("pending", 0),
+                EnumVariant::new("delivered", 1),
+                EnumVariant::new("dead_letter", 2),
+                EnumVariant::new("discarded", 3),
+            ],
+        ),
+        Type::enum_(
+            "pipedrive_status".to_owned(),
+            vec![
+                EnumVariant::new("lead_open", 0),
+                EnumVariant::new("lead_archived", 1),
+                EnumVariant::new("lead_deleted", 2),
+                EnumVariant::new("deal_open", 3),
+                EnumVariant::new("deal_won", 4),
+                EnumVariant::new("deal_lost", 5),
+                EnumVariant::new("deal_deleted", 6),
+            ],
+        ),
         get_service_enum(),
     ]
 }
@@ -0,0 +1,123 @@
+use eyre::*;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Recaptcha,
+    Turnstile,
+    /// Accepts exactly `secret` as token without calling anyone, for tests and development
+    Local,
+}
+
+impl CaptchaProvider {
+    fn siteverify_url(&self) -> &'static str {
+        match self {
+            Self::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            Self::Recaptcha => "https://www.google.com/recaptcha/api/siteverify",
+            Self::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            Self::Local => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptchaConfig {
+    pub provider: CaptchaProvider,
+    /// Secret key of the site at the provider
+    pub secret: String,
+    /// Overrides the siteverify URL of the provider
+    #[serde(default)]
+    pub siteverify_url: Option<String>,
+}
+
+impl CaptchaConfig {
+    pub fn verifier(&self) -> Arc<dyn CaptchaVerifier> {
+        match self.provider {
+            CaptchaProvider::Local => Arc::new(LocalCaptchaVerifier {
+                token: self.secret.clone(),
+            }),
+            provider => Arc::new(SiteverifyCaptchaVerifier {
+                client: reqwest::Client::new(),
+                url: self
+                    .siteverify_url
+                    .clone()
+                    .unwrap_or_else(|| provider.siteverify_url().to_owned()),
+                secret: self.secret.clone(),
+            }),
+        }
+    }
+}
+
+/// Checks the token a CAPTCHA widget handed to the browser. Resolves to `false` when the
+/// provider rejects it and fails when the provider cannot be asked.
+pub trait CaptchaVerifier: Send + Sync {
+    fn verify(
+        self: Arc<Self>,
+        token: String,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'static, Result<bool>>;
+}
+
+/// hCaptcha, reCAPTCHA and Turnstile share the siteverify API
+pub struct SiteverifyCaptchaVerifier {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteverifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+impl CaptchaVerifier for SiteverifyCaptchaVerifier {
+    fn verify(
+        self: Arc<Self>,
+        token: String,
+        client_ip: IpAddr,
+    ) -> BoxFuture<'static, Result<bool>> {
+        async move {
+            let resp: SiteverifyResponse = self
+                .client
+                .post(&self.url)
+                .form(&[
+                    ("secret", self.secret.as_str()),
+                    ("response", token.as_str()),
+                    ("remoteip", client_ip.to_string().as_str()),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if !resp.success {
+                debug!("CAPTCHA of {} rejected: {:?}", client_ip, resp.error_codes);
+            }
+            Ok(resp.success)
+        }
+        .boxed()
+    }
+}
+
+pub struct LocalCaptchaVerifier {
+    token: String,
+}
+
+impl CaptchaVerifier for LocalCaptchaVerifier {
+    fn verify(
+        self: Arc<Self>,
+        token: String,
+        _client_ip: IpAddr,
+    ) -> BoxFuture<'static, Result<bool>> {
+        async move { Ok(!self.token.is_empty() && token == self.token) }.boxed()
+    }
+}
@@ -0,0 +1,195 @@
+use crate::pipedrive::{CustomFieldValues, FieldDefinition, LeadCustomFields, PipeDriveSdk};
+use eyre::*;
+use gen::model::{CrmLeadField, EnumErrorCode};
+use lib::toolbox::CustomError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::*;
+
+/// Form used when a request does not name one
+pub const DEFAULT_FORM: &str = "default";
+
+/// Maps request field names of one form to Pipedrive field keys
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FormConfig {
+    #[serde(default)]
+    pub person_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub organization_fields: HashMap<String, String>,
+    /// Leads use the custom fields defined for deals
+    #[serde(default)]
+    pub lead_fields: HashMap<String, String>,
+}
+
+struct ResolvedField {
+    key: String,
+    field_type: String,
+    /// lowercase option label -> option id, for `enum` and `set` fields
+    options: HashMap<String, i64>,
+}
+
+#[derive(Default)]
+struct ResolvedForm {
+    person: HashMap<String, ResolvedField>,
+    organization: HashMap<String, ResolvedField>,
+    lead: HashMap<String, ResolvedField>,
+}
+
+/// Form field mapping checked against the field definitions of the Pipedrive account
+#[derive(Default)]
+pub struct CustomFieldMapper {
+    forms: HashMap<String, ResolvedForm>,
+}
+
+impl CustomFieldMapper {
+    pub async fn load(sdk: &PipeDriveSdk, forms: &HashMap<String, FormConfig>) -> Result<Self> {
+        if forms.is_empty() {
+            return Ok(Self::default());
+        }
+        let person_fields = sdk.get_person_fields().await?;
+        let organization_fields = sdk.get_organization_fields().await?;
+        let deal_fields = sdk.get_deal_fields().await?;
+        Self::new(forms, &person_fields, &organization_fields, &deal_fields)
+    }
+
+    pub fn new(
+        forms: &HashMap<String, FormConfig>,
+        person_fields: &[FieldDefinition],
+        organization_fields: &[FieldDefinition],
+        deal_fields: &[FieldDefinition],
+    ) -> Result<Self> {
+        let mut errors = vec![];
+        let mut resolved = HashMap::new();
+        for (form_name, form) in forms {
+            let mut resolve = |entity: &str,
+                               mapping: &HashMap<String, String>,
+                               definitions: &[FieldDefinition]| {
+                let mut fields = HashMap::new();
+                for (name, key) in mapping {
+                    match resolve_field(key, definitions) {
+                        Ok(field) => {
+                            fields.insert(name.clone(), field);
+                        }
+                        Err(err) => errors.push(format!(
+                            "form {} {} field {}: {}",
+                            form_name, entity, name, err
+                        )),
+                    }
+                }
+                fields
+            };
+            let form = ResolvedForm {
+                person: resolve("person", &form.person_fields, person_fields),
+                organization: resolve(
+                    "organization",
+                    &form.organization_fields,
+                    organization_fields,
+                ),
+                lead: resolve("lead", &form.lead_fields, deal_fields),
+            };
+            resolved.insert(form_name.clone(), form);
+        }
+        if !errors.is_empty() {
+            bail!("Invalid custom field mapping: {}", errors.join("; "));
+        }
+        info!("Loaded custom field mapping for forms {:?}", forms.keys());
+        Ok(Self { forms: resolved })
+    }
+
+    /// Converts the submitted form fields into Pipedrive custom field values.
+    /// Fields that are not mapped for the form are ignored.
+    pub fn map(&self, form: Option<&str>, fields: &[CrmLeadField]) -> Result<LeadCustomFields> {
+        let resolved = match (form, self.forms.get(form.unwrap_or(DEFAULT_FORM))) {
+            (_, Some(resolved)) => resolved,
+            (None, None) => return Ok(LeadCustomFields::default()),
+            (Some(form), None) => bail!(CustomError::new(
+                EnumErrorCode::InvalidArgument,
+                format!("Unknown form {}", form)
+            )),
+        };
+        let mut result = LeadCustomFields::default();
+        for field in fields {
+            let value = field.value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            for (mapping, values) in [
+                (&resolved.person, &mut result.person),
+                (&resolved.organization, &mut result.organization),
+                (&resolved.lead, &mut result.lead),
+            ] {
+                if let Some(target) = mapping.get(&field.name) {
+                    insert_value(values, &field.name, target, value)?;
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn resolve_field(key: &str, definitions: &[FieldDefinition]) -> Result<ResolvedField> {
+    let definition = definitions
+        .iter()
+        .find(|x| x.key == key)
+        .with_context(|| format!("unknown Pipedrive field key {}", key))?;
+    match definition.field_type.as_str() {
+        "user" | "org" | "people" => bail!(
+            "field {} of type {} cannot be filled from a form",
+            definition.name,
+            definition.field_type
+        ),
+        _ => {}
+    }
+    Ok(ResolvedField {
+        key: definition.key.clone(),
+        field_type: definition.field_type.clone(),
+        options: definition
+            .options
+            .iter()
+            .flatten()
+            .map(|x| (x.label.trim().to_lowercase(), x.id))
+            .collect(),
+    })
+}
+
+fn insert_value(
+    values: &mut CustomFieldValues,
+    name: &str,
+    field: &ResolvedField,
+    value: &str,
+) -> Result<()> {
+    let invalid = || {
+        CustomError::new(
+            EnumErrorCode::InvalidArgument,
+            format!("Invalid value {} for field {}", value, name),
+        )
+    };
+    let option_id = |label: &str| {
+        field
+            .options
+            .get(&label.trim().to_lowercase())
+            .copied()
+            .ok_or_else(invalid)
+    };
+    let value = match field.field_type.as_str() {
+        "enum" => Value::from(option_id(value)?),
+        "set" => {
+            let ids = value
+                .split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| option_id(x).map(|id| id.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::from(ids.join(","))
+        }
+        "double" | "monetary" => {
+            let number: f64 = value.parse().map_err(|_| invalid())?;
+            serde_json::Number::from_f64(number)
+                .map(Value::Number)
+                .ok_or_else(invalid)?
+        }
+        _ => Value::from(value),
+    };
+    values.insert(field.key.clone(), value);
+    Ok(())
+}
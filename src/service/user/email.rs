@@ -0,0 +1,197 @@
+use eyre::*;
+use gen::model::EnumErrorCode;
+use lib::toolbox::CustomError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tracing::*;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("../../../etc/disposable_domains.txt");
+
+/// Local parts of shared mailboxes, compared without a `+tag`
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "billing",
+    "careers",
+    "contact",
+    "help",
+    "hello",
+    "hostmaster",
+    "hr",
+    "info",
+    "jobs",
+    "marketing",
+    "noreply",
+    "no-reply",
+    "office",
+    "postmaster",
+    "root",
+    "sales",
+    "security",
+    "support",
+    "team",
+    "webmaster",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailValidationConfig {
+    /// Reject addresses of throwaway mailbox services
+    #[serde(default = "default_block_disposable")]
+    pub block_disposable: bool,
+    /// More disposable domains, one per line, on top of `etc/disposable_domains.txt`
+    #[serde(default)]
+    pub disposable_domains_file: Option<PathBuf>,
+    /// Reject shared mailboxes like `info@` and `sales@`, which rarely belong to the person
+    #[serde(default)]
+    pub reject_role_accounts: bool,
+}
+
+fn default_block_disposable() -> bool {
+    true
+}
+
+impl Default for EmailValidationConfig {
+    fn default() -> Self {
+        Self {
+            block_disposable: default_block_disposable(),
+            disposable_domains_file: None,
+            reject_role_accounts: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailError {
+    InvalidSyntax,
+    InvalidDomain,
+    DisposableDomain,
+    RoleAccount,
+}
+
+/// Why a field of a request was rejected
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub reason: EmailError,
+}
+
+/// Params of the `InvalidArgument` errors of rejected fields
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldErrors {
+    pub errors: Vec<FieldError>,
+}
+
+/// Checks the addresses of leads before anything is sent to Pipedrive
+pub struct EmailValidator {
+    config: EmailValidationConfig,
+    disposable_domains: HashSet<String>,
+}
+
+fn parse_domain_list(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(|x| x.to_lowercase())
+}
+
+impl EmailValidator {
+    pub fn new(config: EmailValidationConfig) -> Result<Self> {
+        let mut disposable_domains: HashSet<String> =
+            parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS).collect();
+        if let Some(path) = &config.disposable_domains_file {
+            let list = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            disposable_domains.extend(parse_domain_list(&list));
+        }
+        info!("Loaded {} disposable domains", disposable_domains.len());
+        Ok(Self {
+            config,
+            disposable_domains,
+        })
+    }
+
+    fn is_disposable(&self, domain: &str) -> bool {
+        // subdomains of a disposable domain are just as disposable
+        let mut domain = domain;
+        loop {
+            if self.disposable_domains.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Lower case address with the domain in ASCII, or the reason it is rejected
+    pub fn normalize(&self, email: &str) -> Result<String, EmailError> {
+        let email = email.trim();
+        let (local, domain) = email.rsplit_once('@').ok_or(EmailError::InvalidSyntax)?;
+        if !is_valid_local_part(local) {
+            return Err(EmailError::InvalidSyntax);
+        }
+        // punycode of international domains, the way Pipedrive and mail servers compare them
+        let domain = idna::domain_to_ascii(domain).map_err(|_| EmailError::InvalidDomain)?;
+        if !is_valid_domain(&domain) {
+            return Err(EmailError::InvalidDomain);
+        }
+        let local = local.to_lowercase();
+        let email = format!("{}@{}", local, domain);
+        if email.len() > 254 {
+            return Err(EmailError::InvalidSyntax);
+        }
+        if self.config.block_disposable && self.is_disposable(&domain) {
+            return Err(EmailError::DisposableDomain);
+        }
+        let mailbox = local.split('+').next().unwrap_or_default();
+        if self.config.reject_role_accounts && ROLE_ACCOUNTS.contains(&mailbox) {
+            return Err(EmailError::RoleAccount);
+        }
+        Ok(email)
+    }
+
+    /// `normalize` with the rejection as an `InvalidArgument` error of `field`
+    pub fn validate(&self, field: &'static str, email: &str) -> Result<String> {
+        self.normalize(email).map_err(|reason| {
+            CustomError::new(
+                EnumErrorCode::InvalidArgument,
+                FieldErrors {
+                    errors: vec![FieldError { field, reason }],
+                },
+            )
+            .into()
+        })
+    }
+}
+
+/// Dot-atom of RFC 5322, with UTF-8 as RFC 6531 allows. Quoted local parts are not accepted,
+/// hardly any form user has one and Pipedrive does not take them.
+fn is_valid_local_part(local: &str) -> bool {
+    const SPECIALS: &str = "!#$%&'*+/=?^_`{|}~-";
+    !local.is_empty()
+        && local.len() <= 64
+        && local.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || SPECIALS.contains(c))
+        })
+}
+
+/// Host name with a top level domain, domain literals like `[127.0.0.1]` are not accepted
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && !labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
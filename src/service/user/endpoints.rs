@@ -1,6 +1,13 @@
 use model::endpoint::*;
 use model::types::{Field, Type};
 
+/// Posted by the public website forms. Each client IP may send 10 leads a minute, after a
+/// burst of 5, to each tenant's `form_key`; leads without a known key share the budget of
+/// the IP, so bots cannot flood the Pipedrive account.
+/// `website` is a honeypot: forms hide the input, so only bots fill it in. `form_token` comes
+/// from `IssueFormToken` and is required once `spam_protection.form_token_secret` is set,
+/// `captcha_token` once `spam_protection.captcha` is, and `pow_challenge` from
+/// `IssuePowChallenge` with its `pow_solution` once `spam_protection.proof_of_work` is.
 pub fn endpoint_user_add_crm_lead() -> EndpointSchema {
     EndpointSchema::new(
         "AddCrmLead",
@@ -10,13 +17,151 @@ pub fn endpoint_user_add_crm_lead() -> EndpointSchema {
             Field::new("username", Type::String),
             Field::new("title", Type::String),
             Field::new("message", Type::String),
+            Field::new("company", Type::optional(Type::String)),
+            Field::new("form", Type::optional(Type::String)),
+            Field::new("form_key", Type::optional(Type::String)),
+            Field::new("form_token", Type::optional(Type::String)),
+            Field::new("website", Type::optional(Type::String)),
+            Field::new("captcha_token", Type::optional(Type::String)),
+            Field::new("pow_challenge", Type::optional(Type::String)),
+            Field::new("pow_solution", Type::optional(Type::String)),
+            Field::new(
+                "fields",
+                Type::optional(Type::data_table(
+                    "CrmLeadField",
+                    vec![
+                        Field::new("name", Type::String),
+                        Field::new("value", Type::String),
+                    ],
+                )),
+            ),
         ],
         vec![],
     )
+    .with_rate_limit(10, 5)
+}
+
+pub fn endpoint_user_list_dead_letter_leads() -> EndpointSchema {
+    EndpointSchema::new(
+        "ListDeadLetterLeads",
+        20661,
+        vec![Field::new("admin_token", Type::String)],
+        vec![Field::new(
+            "leads",
+            Type::data_table(
+                "DeadLetterLead",
+                vec![
+                    Field::new("lead_id", Type::BigInt),
+                    Field::new("email", Type::String),
+                    Field::new("username", Type::String),
+                    Field::new("title", Type::String),
+                    Field::new("attempts", Type::Int),
+                    Field::new("error", Type::optional(Type::String)),
+                    Field::new("error_info", Type::optional(Type::String)),
+                    Field::new("tenant", Type::optional(Type::String)),
+                    Field::new("created_at", Type::BigInt),
+                    Field::new("updated_at", Type::BigInt),
+                ],
+            ),
+        )],
+    )
+}
+
+pub fn endpoint_user_replay_dead_letter_leads() -> EndpointSchema {
+    EndpointSchema::new(
+        "ReplayDeadLetterLeads",
+        20662,
+        vec![
+            Field::new("admin_token", Type::String),
+            Field::new("lead_id", Type::optional(Type::BigInt)),
+        ],
+        vec![Field::new("lead_ids", Type::vec(Type::BigInt))],
+    )
+}
+
+pub fn endpoint_user_discard_dead_letter_leads() -> EndpointSchema {
+    EndpointSchema::new(
+        "DiscardDeadLetterLeads",
+        20663,
+        vec![
+            Field::new("admin_token", Type::String),
+            Field::new("lead_id", Type::optional(Type::BigInt)),
+        ],
+        vec![Field::new("lead_ids", Type::vec(Type::BigInt))],
+    )
+}
+
+/// Target of the webhooks set up in Pipedrive. The body is the Pipedrive payload itself, the
+/// tenant is identified by the basic-auth credentials of the webhook. Pipedrive retries
+/// deliveries that fail, so a slow one is better answered with an error early.
+pub fn endpoint_user_pipedrive_webhook() -> EndpointSchema {
+    EndpointSchema::new("PipedriveWebhook", 20664, vec![], vec![]).with_timeout_secs(10)
+}
+
+/// Pipedrive webhook events of every tenant as they arrive. Served as server-sent events to
+/// clients accepting `text/event-stream`, the stream ends after `limit` events.
+pub fn endpoint_user_subscribe_pipedrive_events() -> EndpointSchema {
+    EndpointSchema::new(
+        "SubscribePipedriveEvents",
+        20665,
+        vec![
+            Field::new("admin_token", Type::String),
+            Field::new("limit", Type::optional(Type::Int)),
+        ],
+        vec![],
+    )
+    .with_stream_response(vec![
+        Field::new("event_id", Type::BigInt),
+        Field::new("tenant", Type::String),
+        Field::new("action", Type::String),
+        Field::new("object", Type::String),
+        Field::new("object_id", Type::optional(Type::String)),
+        Field::new("lead_ids", Type::vec(Type::BigInt)),
+    ])
+}
+
+/// Signed token a form fetches when it is shown and submits with `AddCrmLead`. Submissions
+/// sent sooner than `min_fill_secs` after the token was issued, or reusing a token, are
+/// treated as bots.
+pub fn endpoint_user_issue_form_token() -> EndpointSchema {
+    EndpointSchema::new(
+        "IssueFormToken",
+        20666,
+        vec![Field::new("form_key", Type::optional(Type::String))],
+        vec![
+            Field::new("form_token", Type::String),
+            Field::new("expires_at", Type::BigInt),
+        ],
+    )
+    .with_allow_get()
+}
+
+/// Challenge a form solves before submitting, by finding a `pow_solution` whose SHA-256 of
+/// `{challenge}:{pow_solution}` starts with `difficulty` zero bits. The difficulty grows
+/// with the recent submissions of the client IP and of the form.
+pub fn endpoint_user_issue_pow_challenge() -> EndpointSchema {
+    EndpointSchema::new(
+        "IssuePowChallenge",
+        20667,
+        vec![Field::new("form_key", Type::optional(Type::String))],
+        vec![
+            Field::new("challenge", Type::String),
+            Field::new("difficulty", Type::Int),
+            Field::new("expires_at", Type::BigInt),
+        ],
+    )
+    .with_allow_get()
 }
 
 pub fn get_user_endpoints() -> Vec<EndpointSchema> {
     vec![
         endpoint_user_add_crm_lead(),
+        endpoint_user_list_dead_letter_leads(),
+        endpoint_user_replay_dead_letter_leads(),
+        endpoint_user_discard_dead_letter_leads(),
+        endpoint_user_pipedrive_webhook(),
+        endpoint_user_subscribe_pipedrive_events(),
+        endpoint_user_issue_form_token(),
+        endpoint_user_issue_pow_challenge(),
     ]
 }
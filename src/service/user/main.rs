@@ -2,21 +2,119 @@ mod method;
 
 use crate::endpoints::*;
 use crate::method::*;
+use custom_fields::FormConfig;
+use email::{EmailValidationConfig, EmailValidator};
 use eyre::*;
+use gen::database::DbClient;
+use gen::model::{ERROR_CODES, ERROR_MESSAGES};
 use lib::config::{load_config, Config};
+use lib::database::connect_to_database;
+use lib::http::HttpServer;
 use lib::log::setup_logs;
-use pipedrive::PipeDriveSdk;
+use lib::scheduler::Scheduler;
+use lib::ws::SimpleAuthContoller;
+use oauth::OAuthConfig;
+use outbox::{LeadDeliveryConfig, LeadOutbox};
+use rate_limit::RateLimitConfig;
 use serde::{Deserialize, Serialize};
+use spam::{IssueFormTokenHandler, IssuePowChallengeHandler, SpamFilter, SpamProtectionConfig};
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use lib::http::HttpServer;
+use std::sync::Arc;
+use std::time::Duration;
+use tenant::{TenantConfig, TenantDefaults, TenantRegistry, DEFAULT_TENANT};
+use tracing::*;
+use webhook::{
+    PipedriveWebhookHandler, SubscribePipedriveEventsHandler, WebhookConfig, WebhookReceiver,
+};
 
+pub mod captcha;
+pub mod custom_fields;
+pub mod email;
 pub mod endpoints;
+pub mod oauth;
+pub mod outbox;
 pub mod pipedrive;
+pub mod pow;
+pub mod rate_limit;
+pub mod spam;
+pub mod tenant;
+pub mod webhook;
 
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct UserConfig {
+    /// Pipedrive account of the `default` tenant, used by requests without a form key.
+    /// Leave empty when every form belongs to one of `tenants`.
+    #[serde(default)]
     pipedrive_company: String,
+    #[serde(default)]
     pipedrive_api_token: String,
+    /// Overrides `https://{pipedrive_company}.pipedrive.com/v1/`, e.g. to point at a mock server
+    #[serde(default)]
+    pipedrive_base_url: Option<String>,
+    #[serde(default)]
+    pipedrive_rate_limit: RateLimitConfig,
+    /// Authenticate as an OAuth app instead of with `pipedrive_api_token` when set
+    #[serde(default)]
+    pipedrive_oauth: Option<OAuthConfig>,
+    /// Basic-auth credentials of the Pipedrive webhooks of the `default` tenant
+    #[serde(default)]
+    pipedrive_webhook: Option<WebhookConfig>,
+    #[serde(default)]
+    lead_delivery: LeadDeliveryConfig,
+    /// Honeypot, form token and fill time checks of `AddCrmLead`
+    #[serde(default)]
+    spam_protection: SpamProtectionConfig,
+    /// Syntax, disposable domain and role account checks of the addresses of leads
+    #[serde(default)]
+    email_validation: EmailValidationConfig,
+    #[serde(default)]
+    admin_token: String,
+    /// Custom field mapping per form, keyed by the `form` of `AddCrmLead`
+    #[serde(default)]
+    forms: HashMap<String, FormConfig>,
+    /// Client companies keyed by tenant name, more can be added to `tbl.tenant`
+    #[serde(default)]
+    tenants: HashMap<String, TenantConfig>,
+    /// How often `tbl.tenant` is checked for added or changed tenants
+    #[serde(default = "default_tenant_reload_secs")]
+    tenant_reload_secs: u64,
+    /// Also require Pipedrive to answer for `/readyz` to report ready
+    #[serde(default)]
+    pipedrive_readiness_probe: bool,
+}
+
+fn default_tenant_reload_secs() -> u64 {
+    30
+}
+
+impl UserConfig {
+    /// Tenants from the config, including the `default` tenant of the top level settings
+    fn tenants(&self) -> Result<HashMap<String, TenantConfig>> {
+        let mut tenants = self.tenants.clone();
+        let legacy = !self.pipedrive_company.is_empty() || self.pipedrive_base_url.is_some();
+        if legacy {
+            let default = TenantConfig {
+                form_key: String::new(),
+                pipedrive_company: self.pipedrive_company.clone(),
+                pipedrive_api_token: self.pipedrive_api_token.clone(),
+                pipedrive_base_url: self.pipedrive_base_url.clone(),
+                pipedrive_oauth: self.pipedrive_oauth.clone(),
+                pipedrive_rate_limit: None,
+                pipedrive_webhook: self.pipedrive_webhook.clone(),
+                allowed_origins: vec![],
+                defaults: TenantDefaults::default(),
+                forms: self.forms.clone(),
+            };
+            if tenants.insert(DEFAULT_TENANT.to_owned(), default).is_some() {
+                bail!(
+                    "Tenant {} is defined by the top level pipedrive settings",
+                    DEFAULT_TENANT
+                );
+            }
+        }
+        Ok(tenants)
+    }
 }
 
 impl Debug for UserConfig {
@@ -46,17 +144,130 @@ async fn main() -> Result<()> {
     let config: Config<UserConfig> = load_config("user".to_owned())?;
     setup_logs(config.app.log_level)?;
 
-    let pipedrive_sdk = PipeDriveSdk::new(
-        &config.app.extra.pipedrive_api_token,
-        &config.app.extra.pipedrive_company,
+    let db = connect_to_database(config.app_db.clone()).await?;
+    let tenants = Arc::new(
+        TenantRegistry::new(
+            DbClient::from(db.clone()),
+            config.app.extra.tenants()?,
+            config.app.extra.pipedrive_rate_limit.clone(),
+            Duration::from_secs(config.app.extra.tenant_reload_secs),
+        )
+        .await?,
     );
+    let outbox = Arc::new(LeadOutbox::new(
+        DbClient::from(db.clone()),
+        tenants.clone(),
+        config.app.extra.lead_delivery.clone(),
+    ));
+    let emails = Arc::new(EmailValidator::new(
+        config.app.extra.email_validation.clone(),
+    )?);
+    let spam = Arc::new(SpamFilter::new(
+        DbClient::from(db.clone()),
+        config.app.extra.spam_protection.clone(),
+    ));
+    let webhooks = Arc::new(WebhookReceiver::new(
+        DbClient::from(db.clone()),
+        tenants.clone(),
+    ));
+
+    let mut scheduler = Scheduler::new();
+    {
+        let outbox = outbox.clone();
+        scheduler.add_adaptive_job(outbox.interval(), move || {
+            let outbox = outbox.clone();
+            async move { outbox.deliver_due_leads().await }
+        })?;
+    }
+    {
+        let tenants = tenants.clone();
+        scheduler.add_adaptive_job(tenants.interval(), move || {
+            let tenants = tenants.clone();
+            async move {
+                if let Err(err) = tenants.reload().await {
+                    error!("Failed to reload tenants: {:?}", err);
+                }
+            }
+        })?;
+    }
+    let scheduler = scheduler.spawn().await;
+
     let mut server = HttpServer::new(config.app.clone());
+    server.add_database(db);
+    server.add_error_codes(ERROR_CODES);
+    server.add_error_messages(ERROR_MESSAGES);
+    // websocket clients call the same endpoints by code on the same port
+    server.enable_websocket(SimpleAuthContoller);
+    {
+        let tenants = tenants.clone();
+        server.set_form_key_filter(Arc::new(move |form_key| {
+            tenants.by_form_key(form_key).is_some()
+        }));
+    }
+    if config.app.extra.pipedrive_readiness_probe {
+        let tenants = tenants.clone();
+        server.add_readiness_probe(
+            "pipedrive",
+            Arc::new(move || {
+                let tenants = tenants.clone();
+                Box::pin(async move { tenants.ping().await })
+            }),
+        );
+    }
 
     server.add_handler(
         endpoint_user_add_crm_lead(),
-        AddCrmLeadHandler { pipedrive_sdk },
+        AddCrmLeadHandler {
+            outbox,
+            spam: spam.clone(),
+            emails,
+        },
+    );
+    server.add_handler(
+        endpoint_user_issue_form_token(),
+        IssueFormTokenHandler { spam: spam.clone() },
+    );
+    server.add_handler(
+        endpoint_user_issue_pow_challenge(),
+        IssuePowChallengeHandler { spam },
+    );
+    server.add_handler_erased(
+        endpoint_user_pipedrive_webhook(),
+        Arc::new(PipedriveWebhookHandler {
+            receiver: webhooks.clone(),
+        }),
+    );
+    server.add_handler(
+        endpoint_user_subscribe_pipedrive_events(),
+        SubscribePipedriveEventsHandler {
+            admin_token: config.app.extra.admin_token.clone(),
+            receiver: webhooks,
+        },
+    );
+    server.add_handler(
+        endpoint_user_list_dead_letter_leads(),
+        ListDeadLetterLeadsHandler {
+            admin_token: config.app.extra.admin_token.clone(),
+        },
+    );
+    server.add_handler(
+        endpoint_user_replay_dead_letter_leads(),
+        ReplayDeadLetterLeadsHandler {
+            admin_token: config.app.extra.admin_token.clone(),
+        },
+    );
+    server.add_handler(
+        endpoint_user_discard_dead_letter_leads(),
+        DiscardDeadLetterLeadsHandler {
+            admin_token: config.app.extra.admin_token.clone(),
+        },
     );
 
+    let grace = config.app.shutdown_grace();
+    // returns once SIGTERM was received and the requests in progress finished
     server.listen().await?;
+    // leads accepted right before the shutdown are delivered before exiting
+    scheduler.shutdown(grace).await;
+    info!("Shut down");
     Ok(())
 }
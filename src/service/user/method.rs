@@ -1,16 +1,148 @@
-use std::sync::Arc;
-use gen::model::{AddCrmLeadRequest, AddCrmLeadResponse};
+use crate::email::EmailValidator;
+use crate::outbox::LeadOutbox;
+use crate::spam::SpamFilter;
+use eyre::*;
+use gen::database::*;
+use gen::model::*;
 use lib::handler::RequestHandler;
-use lib::toolbox::{RequestContext, Toolbox};
+use lib::toolbox::{CustomError, RequestContext, Toolbox};
 use lib::ws::Connection;
-use crate::pipedrive::PipeDriveSdk;
+use std::sync::Arc;
 
 pub struct AddCrmLeadHandler {
-    pub pipedrive_sdk: PipeDriveSdk,
+    pub outbox: Arc<LeadOutbox>,
+    pub spam: Arc<SpamFilter>,
+    pub emails: Arc<EmailValidator>,
 }
 impl RequestHandler for AddCrmLeadHandler {
     type Request = AddCrmLeadRequest;
     type Response = AddCrmLeadResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        conn: Arc<Connection>,
+        mut req: Self::Request,
+    ) {
+        let outbox = self.outbox.clone();
+        let spam = self.spam.clone();
+        let emails = self.emails.clone();
+        toolbox.spawn_response(ctx, async move {
+            // unknown form keys are turned away before they cost any spam bookkeeping
+            let tenant = outbox.resolve_tenant(&req)?;
+            if let Some(reason) = spam.check(&req, conn.client_ip) {
+                // bots get the answer people get, so they cannot tell what gave them away
+                spam.reject(&req, reason, conn.client_ip).await?;
+                return Ok(AddCrmLeadResponse {});
+            }
+            req.email = emails.validate("email", &req.email)?;
+            spam.verify_captcha(req.captcha_token.as_deref(), conn.client_ip)
+                .await?;
+            outbox.enqueue(&tenant, &req, conn.origin()).await?;
+            Ok(AddCrmLeadResponse {})
+        })
+    }
+}
+
+/// Compares secrets in constant time
+pub fn secure_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Admin endpoints are disabled unless `admin_token` is set in the config
+pub fn ensure_admin_token(expected: &str, actual: &str) -> Result<()> {
+    if expected.is_empty() || !secure_eq(expected, actual) {
+        bail!(CustomError::new(
+            EnumErrorCode::UserForbidden,
+            "Invalid admin token"
+        ));
+    }
+    Ok(())
+}
+
+pub struct ListDeadLetterLeadsHandler {
+    pub admin_token: String,
+}
+impl RequestHandler for ListDeadLetterLeadsHandler {
+    type Request = ListDeadLetterLeadsRequest;
+    type Response = ListDeadLetterLeadsResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        _conn: Arc<Connection>,
+        req: Self::Request,
+    ) {
+        let db: DbClient = toolbox.get_db();
+        let admin_token = self.admin_token.clone();
+        toolbox.spawn_response(ctx, async move {
+            ensure_admin_token(&admin_token, &req.admin_token)?;
+            let resp = db
+                .fun_user_list_dead_letter_leads(FunUserListDeadLetterLeadsReq {})
+                .await?;
+            Ok(ListDeadLetterLeadsResponse {
+                leads: resp
+                    .rows
+                    .into_iter()
+                    .map(|x| DeadLetterLead {
+                        lead_id: x.lead_id,
+                        email: x.email,
+                        username: x.username,
+                        title: x.title,
+                        attempts: x.attempts,
+                        error: x.error,
+                        error_info: x.error_info,
+                        tenant: x.tenant,
+                        created_at: x.created_at,
+                        updated_at: x.updated_at,
+                    })
+                    .collect(),
+            })
+        })
+    }
+}
+
+pub struct ReplayDeadLetterLeadsHandler {
+    pub admin_token: String,
+}
+impl RequestHandler for ReplayDeadLetterLeadsHandler {
+    type Request = ReplayDeadLetterLeadsRequest;
+    type Response = ReplayDeadLetterLeadsResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        _conn: Arc<Connection>,
+        req: Self::Request,
+    ) {
+        let db: DbClient = toolbox.get_db();
+        let admin_token = self.admin_token.clone();
+        toolbox.spawn_response(ctx, async move {
+            ensure_admin_token(&admin_token, &req.admin_token)?;
+            // replayed leads go back to the outbox, so delivery uses the same PipeDriveSdk path
+            let resp = db
+                .fun_user_replay_dead_letter_leads(FunUserReplayDeadLetterLeadsReq {
+                    lead_id: req.lead_id,
+                })
+                .await?;
+            Ok(ReplayDeadLetterLeadsResponse {
+                lead_ids: resp.rows.into_iter().map(|x| x.lead_id).collect(),
+            })
+        })
+    }
+}
+
+pub struct DiscardDeadLetterLeadsHandler {
+    pub admin_token: String,
+}
+impl RequestHandler for DiscardDeadLetterLeadsHandler {
+    type Request = DiscardDeadLetterLeadsRequest;
+    type Response = DiscardDeadLetterLeadsResponse;
     fn handle(
         &self,
         toolbox: &Toolbox,
@@ -18,12 +150,18 @@ impl RequestHandler for AddCrmLeadHandler {
         _conn: Arc<Connection>,
         req: Self::Request,
     ) {
-        // let db: DbClient = toolbox.get_db();
-        let sdk = self.pipedrive_sdk.clone();
+        let db: DbClient = toolbox.get_db();
+        let admin_token = self.admin_token.clone();
         toolbox.spawn_response(ctx, async move {
-            let deal = sdk.create_deal(&req.email, &req.username, &req.title, &req.message)
+            ensure_admin_token(&admin_token, &req.admin_token)?;
+            let resp = db
+                .fun_user_discard_dead_letter_leads(FunUserDiscardDeadLetterLeadsReq {
+                    lead_id: req.lead_id,
+                })
                 .await?;
-            Ok(deal)
+            Ok(DiscardDeadLetterLeadsResponse {
+                lead_ids: resp.rows.into_iter().map(|x| x.lead_id).collect(),
+            })
         })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,183 @@
+use eyre::*;
+use gen::database::*;
+use lib::utils::get_time_milliseconds;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::*;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Refresh token from the app installation, only used until a token is stored in Postgres
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+    /// Access tokens expiring within this window are refreshed before use
+    #[serde(default = "default_refresh_before_secs")]
+    pub refresh_before_secs: u64,
+}
+
+fn default_token_url() -> String {
+    "https://oauth.pipedrive.com/oauth/token".to_owned()
+}
+fn default_refresh_before_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    expires_in: u64,
+}
+
+struct OAuthToken {
+    access_token: String,
+    refresh_token: String,
+    expires_at: i64,
+}
+
+/// Keeps the OAuth access token of a Pipedrive app installation fresh. Tokens live in
+/// `tbl.pipedrive_oauth_token`, so restarts and other instances reuse them. The same app may be
+/// installed in the Pipedrive companies of several tenants, so tokens are stored per tenant.
+pub struct OAuthTokenManager {
+    db: DbClient,
+    tenant: String,
+    config: OAuthConfig,
+    client: reqwest::Client,
+    token: tokio::sync::Mutex<Option<OAuthToken>>,
+}
+
+impl OAuthTokenManager {
+    pub async fn new(db: DbClient, tenant: String, config: OAuthConfig) -> Result<Self> {
+        let this = Self {
+            db,
+            tenant,
+            config,
+            client: reqwest::Client::new(),
+            token: tokio::sync::Mutex::new(None),
+        };
+        let stored = this.load().await?;
+        match (stored, &this.config.refresh_token) {
+            (Some(token), _) => *this.token.lock().await = Some(token),
+            (None, Some(refresh_token)) => {
+                info!(
+                    "No stored Pipedrive OAuth token for tenant {} client {}, using configured refresh token",
+                    this.tenant, this.config.client_id
+                );
+                let token = this.refresh(refresh_token).await?;
+                *this.token.lock().await = Some(token);
+            }
+            (None, None) => bail!(
+                "No Pipedrive OAuth token stored for tenant {} client {} and no refresh_token configured",
+                this.tenant,
+                this.config.client_id
+            ),
+        }
+        Ok(this)
+    }
+
+    /// Returns a valid access token, refreshing it when it is about to expire
+    pub async fn access_token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        let refresh_before =
+            Duration::from_secs(self.config.refresh_before_secs).as_millis() as i64;
+        let fresh = |x: &OAuthToken| x.expires_at - refresh_before > get_time_milliseconds();
+        if let Some(current) = token.as_ref().filter(|x| fresh(x)) {
+            return Ok(current.access_token.clone());
+        }
+        // another instance may have refreshed already, its refresh token replaced ours
+        let newer = |x: &OAuthToken| {
+            token
+                .as_ref()
+                .map(|current| current.access_token != x.access_token)
+                .unwrap_or(true)
+        };
+        if let Some(stored) = self.load().await?.filter(|x| fresh(x) && newer(x)) {
+            let access_token = stored.access_token.clone();
+            *token = Some(stored);
+            return Ok(access_token);
+        }
+        let refresh_token = match token.as_ref() {
+            Some(current) => current.refresh_token.clone(),
+            None => self
+                .load()
+                .await?
+                .map(|x| x.refresh_token)
+                .context("No Pipedrive OAuth refresh token")?,
+        };
+        let refreshed = self.refresh(&refresh_token).await?;
+        let access_token = refreshed.access_token.clone();
+        *token = Some(refreshed);
+        Ok(access_token)
+    }
+
+    /// Forgets an access token Pipedrive rejected, so the next call refreshes it
+    pub async fn invalidate(&self, access_token: &str) {
+        let mut token = self.token.lock().await;
+        if let Some(current) = token.as_mut() {
+            if current.access_token == access_token {
+                current.expires_at = 0;
+            }
+        }
+    }
+
+    async fn load(&self) -> Result<Option<OAuthToken>> {
+        let resp = self
+            .db
+            .fun_user_get_pipedrive_oauth_token(FunUserGetPipedriveOauthTokenReq {
+                tenant: self.tenant.clone(),
+                client_id: self.config.client_id.clone(),
+            })
+            .await?;
+        Ok(resp.rows.into_iter().next().map(|x| OAuthToken {
+            access_token: x.access_token,
+            refresh_token: x.refresh_token,
+            expires_at: x.expires_at,
+        }))
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<OAuthToken> {
+        info!(
+            "Refreshing Pipedrive OAuth token for tenant {} client {}",
+            self.tenant, self.config.client_id
+        );
+        let resp = self
+            .client
+            .post(&self.config.token_url)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ])
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            bail!(
+                "Failed to refresh Pipedrive OAuth token: {} {}",
+                status,
+                body
+            );
+        }
+        let resp: TokenResponse = resp.json().await?;
+        let token = OAuthToken {
+            access_token: resp.access_token,
+            refresh_token: resp.refresh_token,
+            expires_at: get_time_milliseconds() + (resp.expires_in * 1000) as i64,
+        };
+        self.db
+            .fun_user_save_pipedrive_oauth_token(FunUserSavePipedriveOauthTokenReq {
+                tenant: self.tenant.clone(),
+                client_id: self.config.client_id.clone(),
+                access_token: token.access_token.clone(),
+                refresh_token: token.refresh_token.clone(),
+                expires_at: token.expires_at,
+            })
+            .await?;
+        Ok(token)
+    }
+}
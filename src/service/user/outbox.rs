@@ -0,0 +1,373 @@
+use crate::pipedrive::{plain_text_to_html, LeadCustomFields, PipeDriveError};
+use crate::tenant::{Tenant, TenantRegistry, DEFAULT_TENANT};
+use eyre::*;
+use gen::database::*;
+use gen::model::{AddCrmLeadRequest, EnumErrorCode, EnumLeadStatus, ErrorUnknownFormKey};
+use lib::toolbox::CustomError;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeadDeliveryConfig {
+    /// How often the worker looks for leads that are due for delivery
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// How many leads are claimed by a single worker run
+    #[serde(default = "default_batch_size")]
+    pub batch_size: i32,
+    /// How long a claimed lead is hidden from other workers, renewed while it is being delivered
+    /// since Pipedrive calls may be retried for longer than that
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+    /// Delay before the first retry, doubled after every failed attempt
+    #[serde(default = "default_retry_base_secs")]
+    pub retry_base_secs: u64,
+    /// Upper bound of the retry delay
+    #[serde(default = "default_retry_max_secs")]
+    pub retry_max_secs: u64,
+    /// Failed attempts after which a lead is moved to the dead-letter state
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    /// Title of the Pipedrive lead, `{title}`, `{username}` and `{email}` are replaced with the
+    /// submitted values. The message itself is attached as a note. Tenants may override it.
+    #[serde(default = "default_title_template")]
+    pub title_template: String,
+}
+
+fn default_interval_secs() -> u64 {
+    2
+}
+fn default_batch_size() -> i32 {
+    16
+}
+fn default_lease_secs() -> u64 {
+    60
+}
+fn default_retry_base_secs() -> u64 {
+    10
+}
+fn default_retry_max_secs() -> u64 {
+    3600
+}
+fn default_max_attempts() -> i32 {
+    8
+}
+fn default_title_template() -> String {
+    "{title} ({username})".to_owned()
+}
+
+impl Default for LeadDeliveryConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_interval_secs(),
+            batch_size: default_batch_size(),
+            lease_secs: default_lease_secs(),
+            retry_base_secs: default_retry_base_secs(),
+            retry_max_secs: default_retry_max_secs(),
+            max_attempts: default_max_attempts(),
+            title_template: default_title_template(),
+        }
+    }
+}
+
+impl LeadDeliveryConfig {
+    pub fn retry_delay(&self, attempts: i32) -> Duration {
+        let exp = attempts.clamp(0, 31) as u32;
+        let delay = self.retry_base_secs.saturating_mul(1u64 << exp);
+        Duration::from_secs(delay.min(self.retry_max_secs))
+    }
+    /// Substitutes `{title}`, `{username}` and `{email}` in one pass, so placeholders inside
+    /// the submitted values are left as they are. Unknown placeholders are kept verbatim.
+    pub fn render_title(&self, tenant: &Tenant, req: &AddCrmLeadRequest) -> String {
+        let mut rest = tenant
+            .config
+            .defaults
+            .title_template
+            .as_deref()
+            .unwrap_or(&self.title_template);
+        let mut title = String::with_capacity(rest.len() + req.title.len());
+        while let Some(start) = rest.find('{') {
+            title.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            match &rest[1..end] {
+                "title" => title.push_str(&req.title),
+                "username" => title.push_str(&req.username),
+                "email" => title.push_str(&req.email),
+                _ => title.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        title.push_str(rest);
+        title
+    }
+}
+
+/// Delivers leads stored in `tbl.lead` to Pipedrive. Leads are written by `AddCrmLeadHandler`
+/// and picked up here, so a slow or failing Pipedrive never loses a submission.
+pub struct LeadOutbox {
+    db: DbClient,
+    tenants: Arc<TenantRegistry>,
+    config: LeadDeliveryConfig,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl LeadOutbox {
+    pub fn new(db: DbClient, tenants: Arc<TenantRegistry>, config: LeadDeliveryConfig) -> Self {
+        Self {
+            db,
+            tenants,
+            config,
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs)
+    }
+    /// `origin` is the `Origin` header of the submission, checked against the tenant's allowlist
+    /// Tenant owning the form of a lead, requests without a form key go to the default one
+    pub fn resolve_tenant(&self, req: &AddCrmLeadRequest) -> Result<Arc<Tenant>> {
+        let tenant = match req.form_key.as_deref() {
+            Some(form_key) => self.tenants.by_form_key(form_key).ok_or_else(|| {
+                CustomError::new(
+                    EnumErrorCode::UnknownFormKey,
+                    ErrorUnknownFormKey {
+                        form_key: form_key.to_owned(),
+                    },
+                )
+            })?,
+            None => self.tenants.get(DEFAULT_TENANT).ok_or_else(|| {
+                CustomError::new(EnumErrorCode::InvalidArgument, "Missing form key")
+            })?,
+        };
+        Ok(tenant)
+    }
+
+    pub async fn enqueue(
+        &self,
+        tenant: &Tenant,
+        req: &AddCrmLeadRequest,
+        origin: Option<&str>,
+    ) -> Result<i64> {
+        if !tenant.config.allows_origin(origin) {
+            bail!(CustomError::new(
+                EnumErrorCode::UserForbidden,
+                format!(
+                    "Origin {} may not submit this form",
+                    origin.unwrap_or_default()
+                )
+            ));
+        }
+        // reject values that can never be delivered before accepting the lead
+        map_custom_fields(tenant, req)?;
+        let resp = self
+            .db
+            .fun_user_add_lead(FunUserAddLeadReq {
+                email: req.email.clone(),
+                username: req.username.clone(),
+                title: req.title.clone(),
+                payload: serde_json::to_string(req)?,
+                tenant: Some(tenant.name.clone()),
+            })
+            .await?;
+        let lead_id = resp
+            .rows
+            .into_iter()
+            .next()
+            .context("No lead id returned")?
+            .lead_id;
+        info!(
+            "Lead {} of tenant {} queued for delivery",
+            lead_id, tenant.name
+        );
+        Ok(lead_id)
+    }
+
+    pub async fn deliver_due_leads(&self) {
+        // the scheduler does not wait for the previous run, so skip if one is still in flight
+        let _guard = match self.running.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        let leads = match self
+            .db
+            .fun_user_claim_due_leads(FunUserClaimDueLeadsReq {
+                limit: self.config.batch_size,
+                lease_ms: (self.config.lease_secs * 1000) as _,
+            })
+            .await
+        {
+            Ok(leads) => leads.rows,
+            Err(err) => {
+                error!("Failed to claim due leads: {:?}", err);
+                return;
+            }
+        };
+        for lead in leads {
+            if let Err(err) = self.deliver_leased(lead).await {
+                error!("Failed to update lead delivery state: {:?}", err);
+            }
+        }
+    }
+
+    /// Extends the lease of a lead, `None` if another worker claimed it in the meantime
+    async fn renew_lease(&self, lead_id: i64, leased_until: i64) -> Result<Option<i64>> {
+        let resp = self
+            .db
+            .fun_user_renew_lead_lease(FunUserRenewLeadLeaseReq {
+                lead_id,
+                leased_until,
+                lease_ms: (self.config.lease_secs * 1000) as _,
+            })
+            .await?;
+        Ok(resp.rows.into_iter().next().map(|x| x.leased_until))
+    }
+
+    /// Delivers a claimed lead while keeping it leased. Leads later in the batch may have
+    /// outlived their lease waiting for the ones before, so the lease is renewed first.
+    async fn deliver_leased(&self, lead: FunUserClaimDueLeadsRespRow) -> Result<()> {
+        let lead_id = lead.lead_id;
+        let mut leased_until = match self.renew_lease(lead_id, lead.leased_until).await? {
+            Some(leased_until) => leased_until,
+            None => {
+                warn!("Lead {} was claimed by another worker, skipped", lead_id);
+                return Ok(());
+            }
+        };
+        let renew = async {
+            loop {
+                tokio::time::sleep(Duration::from_secs(self.config.lease_secs) / 3).await;
+                match self.renew_lease(lead_id, leased_until).await {
+                    Ok(Some(renewed)) => leased_until = renewed,
+                    Ok(None) => {
+                        warn!("Lead {} lease was lost during delivery", lead_id);
+                        return std::future::pending::<()>().await;
+                    }
+                    Err(err) => warn!("Failed to renew lease of lead {}: {:?}", lead_id, err),
+                }
+            }
+        };
+        tokio::select! {
+            result = self.deliver(lead) => result,
+            _ = renew => unreachable!("lease renewal never finishes"),
+        }
+    }
+
+    async fn deliver(&self, lead: FunUserClaimDueLeadsRespRow) -> Result<()> {
+        let result = async {
+            let req: AddCrmLeadRequest = serde_json::from_str(&lead.payload)?;
+            // leads queued before tenants existed belong to the default tenant
+            let tenant_name = lead.tenant.as_deref().unwrap_or(DEFAULT_TENANT);
+            let tenant = self
+                .tenants
+                .get(tenant_name)
+                .with_context(|| format!("Unknown tenant {}", tenant_name))?;
+            // a previous attempt may have created the lead but failed to attach the note
+            let pipedrive_lead_id = match &lead.pipedrive_lead_id {
+                Some(id) => id.clone(),
+                None => {
+                    let title = self.config.render_title(&tenant, &req);
+                    let custom_fields = map_custom_fields(&tenant, &req)?;
+                    let pipedrive_lead = tenant
+                        .sdk
+                        .create_deal(
+                            &req.email,
+                            &req.username,
+                            req.company.as_deref(),
+                            &title,
+                            &custom_fields,
+                        )
+                        .await?;
+                    self.db
+                        .fun_user_set_lead_pipedrive_lead_id(FunUserSetLeadPipedriveLeadIdReq {
+                            lead_id: lead.lead_id,
+                            pipedrive_lead_id: pipedrive_lead.id.clone(),
+                            pipedrive_person_id: pipedrive_lead.person_id,
+                        })
+                        .await?;
+                    pipedrive_lead.id
+                }
+            };
+            // recorded before the lead is marked delivered, so a retry does not post it twice
+            if !lead.note_created && !req.message.trim().is_empty() {
+                tenant
+                    .sdk
+                    .create_note(&pipedrive_lead_id, &plain_text_to_html(&req.message))
+                    .await?;
+                self.db
+                    .fun_user_set_lead_note_created(FunUserSetLeadNoteCreatedReq {
+                        lead_id: lead.lead_id,
+                    })
+                    .await?;
+            }
+            Ok::<_, Report>(pipedrive_lead_id)
+        }
+        .await;
+        match result {
+            Ok(pipedrive_lead_id) => {
+                info!(
+                    "Lead {} delivered as Pipedrive lead {}",
+                    lead.lead_id, pipedrive_lead_id
+                );
+                self.db
+                    .fun_user_mark_lead_delivered(FunUserMarkLeadDeliveredReq {
+                        lead_id: lead.lead_id,
+                        pipedrive_lead_id,
+                    })
+                    .await?;
+            }
+            Err(err) => {
+                let delay = self.config.retry_delay(lead.attempts);
+                let (error, error_info) = if let Some(err) = err.downcast_ref::<PipeDriveError>() {
+                    (err.error.clone(), err.error_info.clone())
+                } else if let Some(err) = err.downcast_ref::<CustomError>() {
+                    let info = match err.params.as_str() {
+                        Some(info) => info.to_owned(),
+                        None => err.params.to_string(),
+                    };
+                    (format!("Error code {}", err.code.to_u32()), Some(info))
+                } else {
+                    (err.to_string(), None)
+                };
+                let resp = self
+                    .db
+                    .fun_user_mark_lead_failed(FunUserMarkLeadFailedReq {
+                        lead_id: lead.lead_id,
+                        error,
+                        error_info,
+                        retry_delay_ms: delay.as_millis() as _,
+                        max_attempts: self.config.max_attempts,
+                    })
+                    .await?;
+                match resp.rows.into_iter().next().map(|x| x.status) {
+                    Some(EnumLeadStatus::DeadLetter) => error!(
+                        "Lead {} moved to dead letter after {} attempts: {:?}",
+                        lead.lead_id,
+                        lead.attempts + 1,
+                        err
+                    ),
+                    _ => warn!(
+                        "Lead {} delivery attempt {} failed, retrying in {:?}: {:?}",
+                        lead.lead_id,
+                        lead.attempts + 1,
+                        delay,
+                        err
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn map_custom_fields(tenant: &Tenant, req: &AddCrmLeadRequest) -> Result<LeadCustomFields> {
+    let form = req.form.as_deref();
+    tenant.mapper.map(
+        form.or(tenant.config.defaults.form.as_deref()),
+        req.fields.as_deref().unwrap_or_default(),
+    )
+}
@@ -2,5 +2,365 @@ use model::types::*;
 
 pub fn get_user_pg_func() -> Vec<ProceduralFunction> {
     vec![
+        ProceduralFunction::new(
+            "fun_user_add_lead",
+            vec![
+                Field::new("email", Type::String),
+                Field::new("username", Type::String),
+                Field::new("title", Type::String),
+                Field::new("payload", Type::String),
+                Field::new("tenant", Type::optional(Type::String)),
+            ],
+            vec![Field::new("lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY INSERT INTO tbl.lead (email, username, title, payload, tenant, status, attempts, next_attempt_at, created_at, updated_at)
+    VALUES ($email, $username, $title, $payload, $tenant, 'pending', 0,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+            (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_claim_due_leads",
+            vec![
+                Field::new("limit", Type::Int),
+                Field::new("lease_ms", Type::BigInt),
+            ],
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("payload", Type::String),
+                Field::new("attempts", Type::Int),
+                Field::new("pipedrive_lead_id", Type::optional(Type::String)),
+                Field::new("tenant", Type::optional(Type::String)),
+                Field::new("note_created", Type::Boolean),
+                Field::new("leased_until", Type::BigInt),
+            ],
+            r#"
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + $lease_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id IN (
+        SELECT d.pkey_id
+        FROM tbl.lead AS d
+        WHERE d.status = 'pending'
+          AND d.next_attempt_at <= (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+        ORDER BY d.next_attempt_at
+        LIMIT $limit
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING l.pkey_id, l.payload, l.attempts, l.pipedrive_lead_id, l.tenant, l.note_created, l.next_attempt_at;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_renew_lead_lease",
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("leased_until", Type::BigInt),
+                Field::new("lease_ms", Type::BigInt),
+            ],
+            vec![Field::new("leased_until", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + $lease_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = $lead_id
+      AND l.status = 'pending'
+      AND l.next_attempt_at = $leased_until
+    RETURNING l.next_attempt_at;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_set_lead_pipedrive_lead_id",
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("pipedrive_lead_id", Type::String),
+                Field::new("pipedrive_person_id", Type::optional(Type::BigInt)),
+            ],
+            vec![],
+            r#"
+BEGIN
+    UPDATE tbl.lead
+    SET pipedrive_lead_id = $pipedrive_lead_id,
+        pipedrive_person_id = $pipedrive_person_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = $lead_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_set_lead_note_created",
+            vec![Field::new("lead_id", Type::BigInt)],
+            vec![],
+            r#"
+BEGIN
+    UPDATE tbl.lead
+    SET note_created = TRUE,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = $lead_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_mark_lead_delivered",
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("pipedrive_lead_id", Type::String),
+            ],
+            vec![],
+            r#"
+BEGIN
+    UPDATE tbl.lead
+    SET status = 'delivered',
+        attempts = attempts + 1,
+        last_error = NULL,
+        pipedrive_lead_id = $pipedrive_lead_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE pkey_id = $lead_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_mark_lead_failed",
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("error", Type::String),
+                Field::new("retry_delay_ms", Type::BigInt),
+                Field::new("max_attempts", Type::Int),
+                Field::new("error_info", Type::optional(Type::String)),
+            ],
+            vec![Field::new("status", Type::enum_ref("lead_status"))],
+            r#"
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET attempts = l.attempts + 1,
+        status = CASE WHEN l.attempts + 1 >= $max_attempts THEN 'dead_letter'::enum_lead_status ELSE l.status END,
+        last_error = $error,
+        last_error_info = $error_info,
+        next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint + $retry_delay_ms,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = $lead_id
+    RETURNING l.status;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_list_dead_letter_leads",
+            vec![],
+            vec![
+                Field::new("lead_id", Type::BigInt),
+                Field::new("email", Type::String),
+                Field::new("username", Type::String),
+                Field::new("title", Type::String),
+                Field::new("attempts", Type::Int),
+                Field::new("error", Type::optional(Type::String)),
+                Field::new("error_info", Type::optional(Type::String)),
+                Field::new("tenant", Type::optional(Type::String)),
+                Field::new("created_at", Type::BigInt),
+                Field::new("updated_at", Type::BigInt),
+            ],
+            r#"
+BEGIN
+    RETURN QUERY SELECT l.pkey_id, l.email, l.username, l.title, l.attempts, l.last_error, l.last_error_info, l.tenant, l.created_at, l.updated_at
+    FROM tbl.lead AS l
+    WHERE l.status = 'dead_letter'
+    ORDER BY l.pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_replay_dead_letter_leads",
+            vec![Field::new("lead_id", Type::optional(Type::BigInt))],
+            vec![Field::new("lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET status = 'pending',
+        attempts = 0,
+        next_attempt_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.status = 'dead_letter'
+      AND ($lead_id IS NULL OR l.pkey_id = $lead_id)
+    RETURNING l.pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_discard_dead_letter_leads",
+            vec![Field::new("lead_id", Type::optional(Type::BigInt))],
+            vec![Field::new("lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET status = 'discarded',
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.status = 'dead_letter'
+      AND ($lead_id IS NULL OR l.pkey_id = $lead_id)
+    RETURNING l.pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_get_pipedrive_oauth_token",
+            vec![
+                Field::new("tenant", Type::String),
+                Field::new("client_id", Type::String),
+            ],
+            vec![
+                Field::new("access_token", Type::String),
+                Field::new("refresh_token", Type::String),
+                Field::new("expires_at", Type::BigInt),
+            ],
+            r#"
+BEGIN
+    RETURN QUERY SELECT t.access_token, t.refresh_token, t.expires_at
+    FROM tbl.pipedrive_oauth_token AS t
+    WHERE t.tenant = $tenant
+      AND t.client_id = $client_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_save_pipedrive_oauth_token",
+            vec![
+                Field::new("tenant", Type::String),
+                Field::new("client_id", Type::String),
+                Field::new("access_token", Type::String),
+                Field::new("refresh_token", Type::String),
+                Field::new("expires_at", Type::BigInt),
+            ],
+            vec![],
+            r#"
+BEGIN
+    INSERT INTO tbl.pipedrive_oauth_token (tenant, client_id, access_token, refresh_token, expires_at, updated_at)
+    VALUES ($tenant, $client_id, $access_token, $refresh_token, $expires_at, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    ON CONFLICT (tenant, client_id) DO UPDATE
+    SET access_token = EXCLUDED.access_token,
+        refresh_token = EXCLUDED.refresh_token,
+        expires_at = EXCLUDED.expires_at,
+        updated_at = EXCLUDED.updated_at;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_list_tenants",
+            vec![],
+            vec![
+                Field::new("name", Type::String),
+                Field::new("form_key", Type::String),
+                Field::new("config", Type::String),
+            ],
+            r#"
+BEGIN
+    RETURN QUERY SELECT t.name, t.form_key, t.config
+    FROM tbl.tenant AS t
+    ORDER BY t.name;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_add_pipedrive_webhook_event",
+            vec![
+                Field::new("tenant", Type::String),
+                Field::new("action", Type::String),
+                Field::new("object", Type::String),
+                Field::new("payload", Type::String),
+                Field::new("object_id", Type::optional(Type::String)),
+            ],
+            vec![Field::new("event_id", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY INSERT INTO tbl.pipedrive_webhook_event (tenant, action, object, object_id, payload, received_at)
+    VALUES ($tenant, $action, $object, $object_id, $payload, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_update_lead_from_pipedrive_lead",
+            vec![
+                Field::new("tenant", Type::String),
+                Field::new("pipedrive_lead_id", Type::String),
+                Field::new("pipedrive_status", Type::enum_ref("pipedrive_status")),
+                Field::new("pipedrive_deal_id", Type::optional(Type::BigInt)),
+            ],
+            vec![Field::new("lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    -- leads queued before tenants existed have no tenant and belong to the default one
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_status = $pipedrive_status,
+        pipedrive_deal_id = $pipedrive_deal_id,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pipedrive_lead_id = $pipedrive_lead_id
+      AND COALESCE(l.tenant, 'default') = $tenant
+      AND l.pipedrive_deal_id IS NULL
+    RETURNING l.pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_update_lead_from_pipedrive_deal",
+            vec![
+                Field::new("tenant", Type::String),
+                Field::new("pipedrive_person_id", Type::BigInt),
+                Field::new("pipedrive_deal_id", Type::BigInt),
+                Field::new("pipedrive_status", Type::enum_ref("pipedrive_status")),
+            ],
+            vec![Field::new("lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    -- leads already linked to the deal, by the conversion of the lead or an earlier deal event
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_status = $pipedrive_status,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pipedrive_deal_id = $pipedrive_deal_id
+      AND COALESCE(l.tenant, 'default') = $tenant
+    RETURNING l.pkey_id;
+    IF FOUND THEN
+        RETURN;
+    END IF;
+    -- deals do not reference the lead they were converted from, so a deal nothing is linked to
+    -- yet takes the most recent lead of the person that is not linked to a deal either
+    RETURN QUERY UPDATE tbl.lead AS l
+    SET pipedrive_deal_id = $pipedrive_deal_id,
+        pipedrive_status = $pipedrive_status,
+        updated_at = (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint
+    WHERE l.pkey_id = (
+        SELECT o.pkey_id
+        FROM tbl.lead AS o
+        WHERE o.pipedrive_person_id = $pipedrive_person_id
+          AND COALESCE(o.tenant, 'default') = $tenant
+          AND o.pipedrive_deal_id IS NULL
+        ORDER BY o.pkey_id DESC
+        LIMIT 1
+    )
+    RETURNING l.pkey_id;
+END
+        "#,
+        ),
+        ProceduralFunction::new(
+            "fun_user_quarantine_lead",
+            vec![
+                Field::new("email", Type::String),
+                Field::new("payload", Type::String),
+                Field::new("reason", Type::String),
+                Field::new("client_ip", Type::String),
+            ],
+            vec![Field::new("quarantined_lead_id", Type::BigInt)],
+            r#"
+BEGIN
+    RETURN QUERY INSERT INTO tbl.quarantined_lead (email, payload, reason, client_ip, created_at)
+    VALUES ($email, $payload, $reason, $client_ip, (EXTRACT(EPOCH FROM NOW()) * 1000)::bigint)
+    RETURNING pkey_id;
+END
+        "#,
+        ),
     ]
 }
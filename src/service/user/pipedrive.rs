@@ -1,13 +1,32 @@
-use std::collections::HashMap;
+use crate::oauth::OAuthTokenManager;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
 use eyre::*;
+use gen::model::EnumErrorCode;
+use lib::metrics::Metrics;
+use lib::toolbox::CustomError;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Method, StatusCode};
 use serde::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Instant;
 use tracing::*;
 
 #[derive(Clone)]
 pub struct PipeDriveSdk {
-    token: String,
-    company: String,
+    auth: PipeDriveAuth,
+    base_url: String,
     client: reqwest::Client,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+#[derive(Clone)]
+pub enum PipeDriveAuth {
+    /// Personal API token, sent as the `api_token` query parameter
+    ApiToken(String),
+    /// Access token of an OAuth app installation, sent as a bearer `Authorization` header
+    OAuth(Arc<OAuthTokenManager>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +43,36 @@ pub struct PipeDriveResponse<T> {
     pub error_info: Option<String>,
 }
 
+impl<T> PipeDriveResponse<T> {
+    pub fn into_error(self, action: &'static str) -> PipeDriveError {
+        PipeDriveError {
+            action,
+            error: self.error.unwrap_or_default(),
+            error_info: self.error_info,
+        }
+    }
+}
+
+/// Failure reported by Pipedrive itself through `error` and `error_info`
+#[derive(Debug, Clone)]
+pub struct PipeDriveError {
+    pub action: &'static str,
+    pub error: String,
+    pub error_info: Option<String>,
+}
+
+impl Display for PipeDriveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to {}: {}", self.action, self.error)?;
+        if let Some(error_info) = &self.error_info {
+            write!(f, " {}", error_info)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PipeDriveError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Picture {
     pub id: i64,
@@ -50,11 +99,26 @@ pub struct PipeDriveUser {
 pub struct Organization {
     pub id: i64,
     pub name: String,
+    #[serde(default)]
     pub people_count: i64,
-    pub owner_id: i64,
-    pub address: String,
+    /// Owner object, whose shape varies between endpoints, e.g. `pic_hash` may be null
+    #[serde(default)]
+    pub owner_id: Option<serde_json::Value>,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
     pub active_flag: bool,
-    pub cc_email: String,
+    #[serde(default)]
+    pub cc_email: Option<String>,
+}
+
+/// Organization of a person, `organization` in `persons/search` and `org_id` elsewhere
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonOrganization {
+    #[serde(alias = "value")]
+    pub id: i64,
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -149,6 +213,8 @@ pub struct PipeDrivePerson {
     // pub phone: Vec<String>,
     // pub emails: Vec<String>,
     pub primary_email: String,
+    #[serde(default, alias = "org_id")]
+    pub organization: Option<PersonOrganization>,
     // pub first_char: String,
     // pub update_time: String,
     // pub add_time: String,
@@ -168,26 +234,186 @@ pub struct PipeDrivePerson {
     // pub cc_email: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldOption {
+    pub id: i64,
+    pub label: String,
+}
+
+/// Definition of a person, organization or deal field as returned by `personFields` etc.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldDefinition {
+    pub id: i64,
+    pub key: String,
+    pub name: String,
+    pub field_type: String,
+    #[serde(default)]
+    pub options: Option<Vec<FieldOption>>,
+}
+
+/// Custom field values keyed by Pipedrive field key, merged into create/update bodies
+pub type CustomFieldValues = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Default)]
+pub struct LeadCustomFields {
+    pub person: CustomFieldValues,
+    pub organization: CustomFieldValues,
+    pub lead: CustomFieldValues,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    pub id: i64,
+    pub content: String,
+    #[serde(default)]
+    pub lead_id: Option<String>,
+    #[serde(default)]
+    pub person_id: Option<i64>,
+    #[serde(default)]
+    pub add_time: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipeDriveLead {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub person_id: Option<i64>,
+}
 
 impl PipeDriveSdk {
     pub fn new(token: impl Into<String>, company: impl Into<String>) -> Self {
+        Self::with_base_url(
+            token,
+            format!("https://{}.pipedrive.com/v1/", company.into()),
+        )
+    }
+    pub fn with_base_url(token: impl Into<String>, base_url: impl Into<String>) -> Self {
         Self {
-            token: token.into(),
-            company: company.into(),
+            auth: PipeDriveAuth::ApiToken(token.into()),
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
             client: reqwest::Client::new(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        }
+    }
+    pub fn with_auth(mut self, auth: PipeDriveAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+    pub fn with_rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limiter = Arc::new(RateLimiter::new(config));
+        self
+    }
+    /// Shares the connection pool of `client` with other SDK instances
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+    /// Sends a call through the rate limiter. Calls rejected with 429 were not processed by
+    /// Pipedrive and are always retried, other transient failures only for idempotent methods.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let request = request.build()?;
+        let idempotent = matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE
+        );
+        let mut attempt = 0;
+        let mut reauthenticated = false;
+        loop {
+            self.rate_limiter.acquire().await;
+            let mut retry = request
+                .try_clone()
+                .context("Pipedrive request can not be retried")?;
+            let access_token = match &self.auth {
+                PipeDriveAuth::ApiToken(_) => None,
+                PipeDriveAuth::OAuth(oauth) => {
+                    let access_token = oauth.access_token().await?;
+                    retry.headers_mut().insert(
+                        AUTHORIZATION,
+                        HeaderValue::from_str(&format!("Bearer {}", access_token))?,
+                    );
+                    Some(access_token)
+                }
+            };
+            let started = Instant::now();
+            let result = self.client.execute(retry).await;
+            record_call(&request, &result, started);
+            let (rate_limited, requested_wait) = match &result {
+                // the token may have been revoked or refreshed elsewhere, refresh once and resend
+                Ok(resp) if resp.status() == StatusCode::UNAUTHORIZED && !reauthenticated => {
+                    if let (PipeDriveAuth::OAuth(oauth), Some(access_token)) =
+                        (&self.auth, &access_token)
+                    {
+                        warn!("Pipedrive rejected the OAuth access token, refreshing");
+                        oauth.invalidate(access_token).await;
+                        reauthenticated = true;
+                        continue;
+                    }
+                    return Ok(result?);
+                }
+                Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    (true, self.rate_limiter.observe(resp.headers(), true))
+                }
+                Ok(resp) => {
+                    self.rate_limiter.observe(resp.headers(), false);
+                    if !(idempotent && resp.status().is_server_error()) {
+                        return Ok(result?);
+                    }
+                    (false, None)
+                }
+                Err(err) if err.is_connect() || (idempotent && err.is_timeout()) => (false, None),
+                Err(_) => return Ok(result?),
+            };
+            if attempt >= self.rate_limiter.max_retries() {
+                if rate_limited {
+                    bail!(CustomError::new(
+                        EnumErrorCode::PipedriveRateLimitExceeded,
+                        format!(
+                            "Pipedrive rate limit exceeded after {} retries: {} {}",
+                            attempt,
+                            request.method(),
+                            request.url().path()
+                        )
+                    ));
+                }
+                return Ok(result?);
+            }
+            let delay = self
+                .rate_limiter
+                .backoff(attempt)
+                .max(requested_wait.unwrap_or_default());
+            warn!(
+                "Pipedrive call {} {} failed with {}, retry {} in {:?}",
+                request.method(),
+                request.url().path(),
+                match &result {
+                    Ok(resp) => resp.status().to_string(),
+                    Err(err) => err.to_string(),
+                },
+                attempt + 1,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
+    /// Cheapest authenticated call, fails when Pipedrive is down or rejects the credentials
+    pub async fn ping(&self) -> Result<()> {
+        let url = self.get_url("users/me");
+        let response = self.send(self.client.get(url)).await?;
+        let status = response.status();
+        ensure!(status.is_success(), "Pipedrive answered {}", status);
+        Ok(())
+    }
     pub fn get_url(&self, path: &str) -> String {
+        let token = match &self.auth {
+            PipeDriveAuth::ApiToken(token) => token,
+            PipeDriveAuth::OAuth(_) => return format!("{}/{}", self.base_url, path),
+        };
         if path.contains("?") {
-            format!(
-                "https://{}.pipedrive.com/v1/{}&api_token={}",
-                self.company, path, self.token
-            )
+            format!("{}/{}&api_token={}", self.base_url, path, token)
         } else {
-            format!(
-                "https://{}.pipedrive.com/v1/{}?api_token={}",
-                self.company, path, self.token
-            )
+            format!("{}/{}?api_token={}", self.base_url, path, token)
         }
     }
     pub async fn create_user(&self, email: &str, username: &str) -> Result<PipeDriveUser> {
@@ -198,59 +424,88 @@ impl PipeDriveSdk {
             "name": username
         });
         let response: PipeDriveResponse<PipeDriveUser> = self
-            .client
-            .post(url)
-            .json(&body)
-            .send()
+            .send(self.client.post(url).json(&body))
             .await?
             .json()
             .await?;
         if response.success {
             Ok(response.data)
         } else {
-            Err(eyre!("Failed to create user: {}", response.error.unwrap_or_default()))
+            Err(response.into_error("create user").into())
         }
     }
-    pub async fn create_person(&self, email: &str, username: &str) -> Result<PipeDrivePerson> {
+    pub async fn create_person(
+        &self,
+        email: &str,
+        username: &str,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<PipeDrivePerson> {
         info!("Creating person {} with email {}", username, email);
-        let url = self.get_url(&format!("persons"));
-        let body = serde_json::json!({
-            "email": email,
-            "name": username
-        });
-        let response = self
-            .client
-            .post(url)
-            .json(&body)
-            .send()
-            .await?;
+        let url = self.get_url("persons");
+        let mut body = custom_fields.clone();
+        body.insert("email".to_owned(), email.into());
+        body.insert("name".to_owned(), username.into());
+        let response = self.send(self.client.post(url).json(&body)).await?;
         let response = response.text().await?;
         info!("Response: {}", response);
         let response: PipeDriveResponse<PipeDrivePerson> = serde_json::from_str(&response)?;
         if response.success {
             Ok(response.data)
         } else {
-            Err(eyre!("Failed to create person: {}", response.error.unwrap_or_default()))
+            Err(response.into_error("create person").into())
+        }
+    }
+    pub async fn update_person(
+        &self,
+        person_id: i64,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<PipeDrivePerson> {
+        info!("Updating person {}", person_id);
+        let url = self.get_url(&format!("persons/{}", person_id));
+        let response: PipeDriveResponse<PipeDrivePerson> = self
+            .send(self.client.put(url).json(custom_fields))
+            .await?
+            .json()
+            .await?;
+        if response.success {
+            Ok(response.data)
+        } else {
+            Err(response.into_error("update person").into())
         }
     }
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<PipeDriveUser>> {
         info!("Finding user with email {}", email);
-        let url = self.get_url(&format!("users/find?term={}&search_by_email=1", email));
-        let result: serde_json::Value = self.client.get(url).send().await?.json().await?;
+        let url = self.get_url("users/find");
+        let result: serde_json::Value = self
+            .send(
+                self.client
+                    .get(url)
+                    .query(&[("term", email), ("search_by_email", "1")]),
+            )
+            .await?
+            .json()
+            .await?;
         info!("find_user_by_email {:?}", result);
 
         let user: PipeDriveResponse<Option<Vec<PipeDriveUser>>> = serde_json::from_value(result)?;
         if user.success {
             Ok(user.data.unwrap_or_default().pop())
         } else {
-            Err(eyre!("Failed to find user: {}", user.error.unwrap_or_default()))
+            Err(user.into_error("find user").into())
         }
     }
     pub async fn find_person_by_email(&self, email: &str) -> Result<Option<PipeDrivePerson>> {
         info!("Finding user with email {}", email);
-        // TODO: this should be url encoded
-        let url = self.get_url(&format!("persons/search?term={}&fields=email", email));
-        let result = self.client.get(url).send().await?.text().await?;
+        let url = self.get_url("persons/search");
+        let result = self
+            .send(
+                self.client
+                    .get(url)
+                    .query(&[("term", email), ("fields", "email")]),
+            )
+            .await?
+            .text()
+            .await?;
         info!("find_user_by_email {}", result);
 
         #[derive(Debug, Serialize, Deserialize)]
@@ -260,13 +515,13 @@ impl PipeDriveSdk {
         }
         #[derive(Debug, Serialize, Deserialize)]
         struct Persons {
-            items: Vec<SearchResult>
+            items: Vec<SearchResult>,
         }
         let mut user: PipeDriveResponse<Persons> = serde_json::from_str(&result)?;
         if user.success {
             Ok(user.data.items.pop().map(|x| x.item))
         } else {
-            Err(eyre!("Failed to find user: {}", user.error.unwrap_or_default()))
+            Err(user.into_error("find person").into())
         }
     }
     pub async fn ensure_user(&self, email: &str, username: &str) -> Result<PipeDriveUser> {
@@ -275,44 +530,323 @@ impl PipeDriveSdk {
         }
         self.create_user(email, username).await
     }
-    pub async fn ensure_person(&self, email: &str, username: &str) -> Result<PipeDrivePerson> {
+    /// Finds or creates the person. An existing person is only linked to `organization_id`
+    /// when it does not belong to an organization yet.
+    pub async fn ensure_person(
+        &self,
+        email: &str,
+        username: &str,
+        organization_id: Option<i64>,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<PipeDrivePerson> {
+        let mut fields = custom_fields.clone();
         if let Some(user) = self.find_person_by_email(email).await? {
-            return Ok(user);
+            if let (None, Some(organization_id)) = (&user.organization, organization_id) {
+                fields.insert("org_id".to_owned(), organization_id.into());
+            }
+            if fields.is_empty() {
+                return Ok(user);
+            }
+            return self.update_person(user.id, &fields).await;
+        }
+        if let Some(organization_id) = organization_id {
+            fields.insert("org_id".to_owned(), organization_id.into());
+        }
+        self.create_person(email, username, &fields).await
+    }
+    pub async fn find_organization_by_name(&self, name: &str) -> Result<Option<Organization>> {
+        info!("Finding organization {}", name);
+        let url = self.get_url("organizations/search");
+        #[derive(Debug, Serialize, Deserialize)]
+        struct SearchResult {
+            result_score: f64,
+            item: Organization,
+        }
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Organizations {
+            items: Vec<SearchResult>,
+        }
+        let resp: PipeDriveResponse<Organizations> = self
+            .send(self.client.get(url).query(&[
+                ("term", name),
+                ("fields", "name"),
+                ("exact_match", "true"),
+            ]))
+            .await?
+            .json()
+            .await?;
+        if resp.success {
+            // exact_match is case insensitive, prefer the organization spelled the same way
+            let mut items = resp.data.items.into_iter().map(|x| x.item);
+            let first = items.next();
+            Ok(items.find(|x| x.name == name).or(first))
+        } else {
+            Err(resp.into_error("find organization").into())
+        }
+    }
+    pub async fn create_organization(
+        &self,
+        name: &str,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<Organization> {
+        info!("Creating organization {}", name);
+        let url = self.get_url("organizations");
+        let mut body = custom_fields.clone();
+        body.insert("name".to_owned(), name.into());
+        let resp: PipeDriveResponse<Option<Organization>> = self
+            .send(self.client.post(url).json(&body))
+            .await?
+            .json()
+            .await?;
+        match resp.data {
+            Some(organization) if resp.success => Ok(organization),
+            _ => Err(resp.into_error("create organization").into()),
+        }
+    }
+    pub async fn update_organization(
+        &self,
+        organization_id: i64,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<Organization> {
+        info!("Updating organization {}", organization_id);
+        let url = self.get_url(&format!("organizations/{}", organization_id));
+        let resp: PipeDriveResponse<Option<Organization>> = self
+            .send(self.client.put(url).json(custom_fields))
+            .await?
+            .json()
+            .await?;
+        match resp.data {
+            Some(organization) if resp.success => Ok(organization),
+            _ => Err(resp.into_error("update organization").into()),
+        }
+    }
+    pub async fn ensure_organization(
+        &self,
+        name: &str,
+        custom_fields: &CustomFieldValues,
+    ) -> Result<Organization> {
+        if let Some(organization) = self.find_organization_by_name(name).await? {
+            if custom_fields.is_empty() {
+                return Ok(organization);
+            }
+            return self
+                .update_organization(organization.id, custom_fields)
+                .await;
+        }
+        self.create_organization(name, custom_fields).await
+    }
+    async fn get_fields(&self, entity: &'static str) -> Result<Vec<FieldDefinition>> {
+        #[derive(Debug, Deserialize)]
+        struct PageInfo {
+            #[serde(default)]
+            more_items_in_collection: bool,
+            #[serde(default)]
+            next_start: Option<i64>,
+        }
+        #[derive(Debug, Deserialize)]
+        struct PageData {
+            pagination: Option<PageInfo>,
+        }
+        #[derive(Debug, Deserialize)]
+        struct Page {
+            success: bool,
+            #[serde(default)]
+            data: Option<Vec<FieldDefinition>>,
+            #[serde(default)]
+            additional_data: Option<PageData>,
+            #[serde(default)]
+            error: Option<String>,
+            #[serde(default)]
+            error_info: Option<String>,
+        }
+        let mut fields = vec![];
+        let mut start = 0;
+        loop {
+            let url = self.get_url(&format!("{}?start={}&limit=500", entity, start));
+            let page: Page = self.send(self.client.get(url)).await?.json().await?;
+            if !page.success {
+                return Err(PipeDriveError {
+                    action: "list fields",
+                    error: page.error.unwrap_or_default(),
+                    error_info: page.error_info,
+                }
+                .into());
+            }
+            fields.extend(page.data.unwrap_or_default());
+            match page.additional_data.and_then(|x| x.pagination) {
+                Some(PageInfo {
+                    more_items_in_collection: true,
+                    next_start: Some(next_start),
+                }) => start = next_start,
+                _ => break,
+            }
         }
-        self.create_person(email, username).await
+        Ok(fields)
+    }
+    pub async fn get_person_fields(&self) -> Result<Vec<FieldDefinition>> {
+        self.get_fields("personFields").await
+    }
+    pub async fn get_organization_fields(&self) -> Result<Vec<FieldDefinition>> {
+        self.get_fields("organizationFields").await
+    }
+    /// Leads share their custom fields with deals
+    pub async fn get_deal_fields(&self) -> Result<Vec<FieldDefinition>> {
+        self.get_fields("dealFields").await
     }
 
     pub async fn create_deal(
         &self,
         email: &str,
         username: &str,
+        company: Option<&str>,
         title: &str,
-        message: &str,
-    ) -> Result<serde_json::Value> {
-        let user = self.ensure_person(email, username).await?;
+        custom_fields: &LeadCustomFields,
+    ) -> Result<PipeDriveLead> {
+        let organization = match organization_name(company, email) {
+            Some(name) => Some(
+                self.ensure_organization(&name, &custom_fields.organization)
+                    .await?,
+            ),
+            None => {
+                if !custom_fields.organization.is_empty() {
+                    warn!(
+                        "Ignoring organization fields {:?} of lead {}: no organization is linked",
+                        custom_fields.organization.keys().collect::<Vec<_>>(),
+                        title
+                    );
+                }
+                None
+            }
+        };
+        let organization_id = organization.as_ref().map(|x| x.id);
+        let user = self
+            .ensure_person(email, username, organization_id, &custom_fields.person)
+            .await?;
         info!("Creating deal {} for user {:?}", title, user);
-        let url = self.get_url(&format!("leads"));
+        let url = self.get_url("leads");
+        let mut body = custom_fields.lead.clone();
+        body.insert("title".to_owned(), title.into());
+        body.insert("person_id".to_owned(), user.id.into());
+        if let Some(organization_id) = organization_id {
+            body.insert("organization_id".to_owned(), organization_id.into());
+        }
+        let resp: PipeDriveResponse<Option<PipeDriveLead>> = self
+            .send(
+                self.client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .json(&body),
+            )
+            .await?
+            .json()
+            .await?;
+        info!("create_deal {:?}", resp);
+        match resp.data {
+            Some(lead) if resp.success => Ok(lead),
+            _ => Err(resp.into_error("create deal").into()),
+        }
+    }
+    /// Attaches a note to a lead. `content` is HTML, use `plain_text_to_html` for user input
+    pub async fn create_note(&self, lead_id: &str, content: &str) -> Result<Note> {
+        info!("Creating note for lead {}", lead_id);
+        let url = self.get_url("notes");
         let body = serde_json::json!({
-            "title": format!("Lead of {} {} {} -- {}", username, email, title, message),
-            "person_id": user.id,
-            // "value": {
-            //     "content": message
-            // }
+            "content": content,
+            "lead_id": lead_id,
         });
-        let resp: PipeDriveResponse<serde_json::Value> = self
-            .client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
+        let resp: PipeDriveResponse<Option<Note>> = self
+            .send(self.client.post(url).json(&body))
             .await?
             .json()
             .await?;
-        info!("create_deal {:?}", resp);
-        if resp.success {
-            Ok(resp.data)
-        } else {
-            Err(eyre!("Failed to create deal {} {}", resp.error.unwrap_or_default(), resp.error_info.unwrap_or_default()))
+        match resp.data {
+            Some(note) if resp.success => Ok(note),
+            _ => Err(resp.into_error("create note").into()),
         }
     }
 }
+
+/// Counts every attempt, retries included, by the resource it touched
+fn record_call(
+    request: &reqwest::Request,
+    result: &reqwest::Result<reqwest::Response>,
+    started: Instant,
+) {
+    let method = request.method().as_str();
+    // `/api/v1/persons/42` is reported as `persons`, ids would blow up the series
+    let resource = request
+        .url()
+        .path_segments()
+        .and_then(|mut segments| {
+            segments.find(|x| {
+                !x.is_empty()
+                    && *x != "api"
+                    && !(x.len() > 1
+                        && x.starts_with('v')
+                        && x[1..].chars().all(|c| c.is_ascii_digit()))
+            })
+        })
+        .unwrap_or_default();
+    let status = match result {
+        Ok(resp) => resp.status().as_u16().to_string(),
+        Err(_) => "error".to_owned(),
+    };
+    let metrics = Metrics::global();
+    metrics.increment(
+        "pipedrive_requests_total",
+        "Calls to the Pipedrive API by resource and HTTP status",
+        &[
+            ("method", method),
+            ("resource", resource),
+            ("status", &status),
+        ],
+    );
+    metrics.observe(
+        "pipedrive_request_duration_seconds",
+        "Latency of the Pipedrive API by resource",
+        &[("method", method), ("resource", resource)],
+        started.elapsed(),
+    );
+}
+
+/// Escapes user supplied text for Pipedrive's HTML note content, keeping line breaks
+pub fn plain_text_to_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\r' => {}
+            '\n' => html.push_str("<br>"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+const FREE_MAIL_DOMAINS: &str = include_str!("free_mail_domains.txt");
+
+/// Whether the domain belongs to a public mailbox provider rather than a company
+pub fn is_free_mail_domain(domain: &str) -> bool {
+    let domain = domain.trim().to_lowercase();
+    FREE_MAIL_DOMAINS
+        .lines()
+        .map(|x| x.trim())
+        .any(|x| !x.starts_with('#') && x == domain)
+}
+
+/// Organization a lead belongs to: the submitted company, otherwise the domain of a
+/// corporate email address
+pub fn organization_name(company: Option<&str>, email: &str) -> Option<String> {
+    if let Some(company) = company.map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        return Some(company.to_owned());
+    }
+    let domain = email.rsplit_once('@')?.1.trim().to_lowercase();
+    if domain.is_empty() || is_free_mail_domain(&domain) {
+        return None;
+    }
+    Some(domain)
+}
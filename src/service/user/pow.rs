@@ -0,0 +1,265 @@
+use crate::spam::SpamReason;
+use eyre::*;
+use gen::model::EnumErrorCode;
+use hmac::{Hmac, Mac};
+use lib::toolbox::CustomError;
+use lib::utils::get_time_milliseconds;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Spent challenges and submission counters are pruned once this many keys are tracked
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofOfWorkConfig {
+    /// Key signing the challenges
+    pub secret: String,
+    /// Leading zero bits the hash of a solution needs while submissions are rare
+    #[serde(default = "default_base_difficulty")]
+    pub base_difficulty: u32,
+    /// Each bit doubles the work, 24 bits take a browser several seconds
+    #[serde(default = "default_max_difficulty")]
+    pub max_difficulty: u32,
+    /// Submissions of a client IP within `window_secs` that add a bit of difficulty
+    #[serde(default = "default_ip_submissions_per_bit")]
+    pub ip_submissions_per_bit: u32,
+    /// Submissions to a `form_key` within `window_secs` that add a bit of difficulty
+    #[serde(default = "default_form_submissions_per_bit")]
+    pub form_submissions_per_bit: u32,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// How long a challenge may be solved and submitted
+    #[serde(default = "default_challenge_ttl_secs")]
+    pub challenge_ttl_secs: u64,
+}
+
+fn default_base_difficulty() -> u32 {
+    16
+}
+fn default_max_difficulty() -> u32 {
+    24
+}
+fn default_ip_submissions_per_bit() -> u32 {
+    5
+}
+fn default_form_submissions_per_bit() -> u32 {
+    50
+}
+fn default_window_secs() -> u64 {
+    600
+}
+fn default_challenge_ttl_secs() -> u64 {
+    300
+}
+
+/// Timestamps of recent submissions per client IP or form
+struct RecentSubmissions {
+    window_ms: i64,
+    hits: Mutex<HashMap<String, VecDeque<i64>>>,
+}
+
+impl RecentSubmissions {
+    /// Keeps at most `limit` timestamps per key, more would not raise the difficulty any further
+    fn record(&self, key: String, now: i64, limit: usize) {
+        let mut hits = self.hits.lock().unwrap();
+        if hits.len() > MAX_TRACKED_KEYS {
+            let since = now - self.window_ms;
+            hits.retain(|_, x| x.back().map_or(false, |&at| at > since));
+        }
+        let times = hits.entry(key).or_default();
+        self.expire(times, now);
+        while times.len() >= limit.max(1) {
+            times.pop_front();
+        }
+        times.push_back(now);
+    }
+    fn count(&self, key: &str, now: i64) -> u32 {
+        let mut hits = self.hits.lock().unwrap();
+        let times = match hits.get_mut(key) {
+            Some(times) => times,
+            None => return 0,
+        };
+        self.expire(times, now);
+        times.len() as u32
+    }
+    fn expire(&self, times: &mut VecDeque<i64>, now: i64) {
+        while times
+            .front()
+            .map_or(false, |&at| at <= now - self.window_ms)
+        {
+            times.pop_front();
+        }
+    }
+}
+
+/// Makes every submission cost the client some hashing, without a third party like a
+/// CAPTCHA provider. Challenges are signed, so issuing them keeps no state; only solved
+/// ones are remembered until they expire, so each is accepted once.
+pub struct ProofOfWork {
+    config: ProofOfWorkConfig,
+    submissions: RecentSubmissions,
+    /// Nonces of solved challenges and when they expire
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+impl ProofOfWork {
+    pub fn new(config: ProofOfWorkConfig) -> Self {
+        Self {
+            submissions: RecentSubmissions {
+                window_ms: config.window_secs as i64 * 1000,
+                hits: Mutex::new(HashMap::new()),
+            },
+            spent: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    fn sign(
+        &self,
+        expires_at: i64,
+        difficulty: u32,
+        nonce: &str,
+        form_key: Option<&str>,
+    ) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.secret.as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(
+            format!(
+                "{}.{}.{}.{}",
+                expires_at,
+                difficulty,
+                nonce,
+                form_key.unwrap_or_default()
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+
+    /// Grows by a bit for every `*_submissions_per_bit` recent submissions of the IP or form,
+    /// whichever is busier
+    pub fn difficulty(&self, client_ip: IpAddr, form_key: Option<&str>) -> u32 {
+        let now = get_time_milliseconds();
+        let by_ip = self.submissions.count(&format!("ip:{}", client_ip), now)
+            / self.config.ip_submissions_per_bit.max(1);
+        let by_form = self
+            .submissions
+            .count(&format!("form:{}", form_key.unwrap_or_default()), now)
+            / self.config.form_submissions_per_bit.max(1);
+        (self.config.base_difficulty + by_ip.max(by_form)).min(self.config.max_difficulty)
+    }
+
+    /// Challenge in the format `{expires_at}.{difficulty}.{nonce}.{signature}`, its
+    /// difficulty and when it expires
+    pub fn issue(&self, client_ip: IpAddr, form_key: Option<&str>) -> Result<(String, u32, i64)> {
+        if self.config.secret.is_empty() {
+            bail!(CustomError::new(
+                EnumErrorCode::InvalidState,
+                "Proof of work secret is not configured"
+            ));
+        }
+        let difficulty = self.difficulty(client_ip, form_key);
+        let expires_at = get_time_milliseconds() + self.config.challenge_ttl_secs as i64 * 1000;
+        let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let signature = self
+            .sign(expires_at, difficulty, &nonce, form_key)
+            .finalize()
+            .into_bytes();
+        let challenge = format!(
+            "{}.{}.{}.{}",
+            expires_at,
+            difficulty,
+            nonce,
+            hex::encode(signature)
+        );
+        Ok((challenge, difficulty, expires_at))
+    }
+
+    /// Counts a submission towards the difficulty of later challenges. `form_key` must
+    /// resolve to a tenant, so made up keys cannot grow the counters.
+    pub fn record_submission(&self, client_ip: IpAddr, form_key: Option<&str>) {
+        let now = get_time_milliseconds();
+        let extra_bits = self
+            .config
+            .max_difficulty
+            .saturating_sub(self.config.base_difficulty) as usize;
+        self.submissions.record(
+            format!("ip:{}", client_ip),
+            now,
+            extra_bits * self.config.ip_submissions_per_bit.max(1) as usize,
+        );
+        self.submissions.record(
+            format!("form:{}", form_key.unwrap_or_default()),
+            now,
+            extra_bits * self.config.form_submissions_per_bit.max(1) as usize,
+        );
+    }
+
+    /// A solution is accepted when SHA-256 of `{challenge}:{solution}` starts with as many
+    /// zero bits as the challenge demands
+    pub fn verify(
+        &self,
+        challenge: Option<&str>,
+        solution: Option<&str>,
+        form_key: Option<&str>,
+    ) -> Option<SpamReason> {
+        let (challenge, solution) = match (challenge, solution) {
+            (Some(challenge), Some(solution)) => (challenge, solution),
+            _ => return Some(SpamReason::MissingProofOfWork),
+        };
+        let mut parts = challenge.splitn(4, '.');
+        let (expires_at, difficulty, nonce, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(expires_at), Some(difficulty), Some(nonce), Some(signature)) => {
+                    (expires_at, difficulty, nonce, signature)
+                }
+                _ => return Some(SpamReason::InvalidProofOfWork),
+            };
+        let (expires_at, difficulty) = match (expires_at.parse::<i64>(), difficulty.parse::<u32>())
+        {
+            (Ok(expires_at), Ok(difficulty)) => (expires_at, difficulty),
+            _ => return Some(SpamReason::InvalidProofOfWork),
+        };
+        let valid = hex::decode(signature)
+            .map(|signature| {
+                self.sign(expires_at, difficulty, nonce, form_key)
+                    .verify_slice(&signature)
+                    .is_ok()
+            })
+            .unwrap_or_default();
+        if !valid {
+            return Some(SpamReason::InvalidProofOfWork);
+        }
+        let now = get_time_milliseconds();
+        if now > expires_at {
+            return Some(SpamReason::ExpiredProofOfWork);
+        }
+        let hash = Sha256::digest(format!("{}:{}", challenge, solution).as_bytes());
+        if leading_zero_bits(&hash) < difficulty {
+            return Some(SpamReason::InvalidProofOfWork);
+        }
+        let mut spent = self.spent.lock().unwrap();
+        if spent.len() > MAX_TRACKED_KEYS {
+            spent.retain(|_, &mut expires_at| expires_at >= now);
+        }
+        if spent.insert(nonce.to_owned(), expires_at).is_some() {
+            return Some(SpamReason::ReplayedProofOfWork);
+        }
+        None
+    }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
@@ -0,0 +1,118 @@
+use governor::{DefaultDirectRateLimiter, Quota};
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained rate of the client side limiter shared by all Pipedrive calls
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: u32,
+    /// Calls that may be sent at once before the sustained rate applies
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// Retries of a rate limited call, or of an idempotent call that failed transiently
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_base_ms")]
+    pub retry_base_ms: u64,
+    #[serde(default = "default_retry_max_ms")]
+    pub retry_max_ms: u64,
+}
+
+fn default_requests_per_second() -> u32 {
+    8
+}
+fn default_burst() -> u32 {
+    16
+}
+fn default_max_retries() -> u32 {
+    5
+}
+fn default_retry_base_ms() -> u64 {
+    500
+}
+fn default_retry_max_ms() -> u64 {
+    30_000
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: default_requests_per_second(),
+            burst: default_burst(),
+            max_retries: default_max_retries(),
+            retry_base_ms: default_retry_base_ms(),
+            retry_max_ms: default_retry_max_ms(),
+        }
+    }
+}
+
+/// Client side view of the Pipedrive rate limit. Calls are queued through a token bucket and
+/// held back entirely while Pipedrive reports the current window as used up.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    limiter: DefaultDirectRateLimiter,
+    blocked_until: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let rate = NonZeroU32::new(config.requests_per_second).unwrap_or(NonZeroU32::MIN);
+        let burst = NonZeroU32::new(config.burst).unwrap_or(rate);
+        Self {
+            limiter: DefaultDirectRateLimiter::direct(Quota::per_second(rate).allow_burst(burst)),
+            config,
+            blocked_until: Mutex::new(None),
+        }
+    }
+    pub fn max_retries(&self) -> u32 {
+        self.config.max_retries
+    }
+
+    /// Waits until a call may be sent
+    pub async fn acquire(&self) {
+        let blocked_until = *self.blocked_until.lock().unwrap();
+        if let Some(blocked_until) = blocked_until {
+            tokio::time::sleep_until(blocked_until.into()).await;
+        }
+        self.limiter.until_ready().await;
+    }
+
+    /// Records `x-ratelimit-remaining`/`x-ratelimit-reset` and `retry-after` of a response.
+    /// Returns how long Pipedrive asked us to wait, if at all.
+    pub fn observe(&self, headers: &HeaderMap, rate_limited: bool) -> Option<Duration> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse::<u64>().ok())
+        };
+        let reset = header("x-ratelimit-reset").map(Duration::from_secs);
+        let wait = match header("x-ratelimit-remaining") {
+            _ if rate_limited => header("retry-after").map(Duration::from_secs).or(reset),
+            Some(0) => reset,
+            _ => None,
+        }?;
+        let until = Instant::now() + wait;
+        let mut blocked_until = self.blocked_until.lock().unwrap();
+        if blocked_until.map(|x| x < until).unwrap_or(true) {
+            *blocked_until = Some(until);
+        }
+        Some(wait)
+    }
+
+    /// Exponential backoff with equal jitter for the given retry, at least half of the
+    /// exponential delay so retries never bunch up right after a failure
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let max = self
+            .config
+            .retry_base_ms
+            .saturating_mul(1u64 << attempt.min(31))
+            .min(self.config.retry_max_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+    }
+}
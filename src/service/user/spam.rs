@@ -0,0 +1,382 @@
+use crate::captcha::{CaptchaConfig, CaptchaVerifier};
+use crate::pow::{ProofOfWork, ProofOfWorkConfig};
+use eyre::*;
+use gen::database::*;
+use gen::model::{
+    AddCrmLeadRequest, EnumErrorCode, ErrorCaptchaFailed, IssueFormTokenRequest,
+    IssueFormTokenResponse, IssuePowChallengeRequest, IssuePowChallengeResponse,
+};
+use hmac::{Hmac, Mac};
+use lib::handler::RequestHandler;
+use lib::metrics::Metrics;
+use lib::toolbox::{CustomError, RequestContext, Toolbox};
+use lib::utils::get_time_milliseconds;
+use lib::ws::Connection;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tracing::*;
+
+/// At most this many spent form tokens are remembered, the oldest make room for new ones
+const MAX_SPENT_TOKENS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpamAction {
+    /// Answer as if the lead was accepted and forget it
+    #[default]
+    Drop,
+    /// Answer as if the lead was accepted and keep it in `tbl.quarantined_lead` for review
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpamProtectionConfig {
+    /// Key signing the form tokens, `AddCrmLead` requires no token while it is empty
+    #[serde(default)]
+    pub form_token_secret: String,
+    /// How long a form may stay open before its token expires
+    #[serde(default = "default_form_token_ttl_secs")]
+    pub form_token_ttl_secs: u64,
+    /// People take longer than this to fill in a form after it was shown
+    #[serde(default = "default_min_fill_secs")]
+    pub min_fill_secs: u64,
+    /// What happens to submissions that look automated
+    #[serde(default)]
+    pub action: SpamAction,
+    /// Require `captcha_token` to be solved, checked after the silent checks
+    #[serde(default)]
+    pub captcha: Option<CaptchaConfig>,
+    /// Require a solved `IssuePowChallenge` challenge, for sites that do without a CAPTCHA
+    #[serde(default)]
+    pub proof_of_work: Option<ProofOfWorkConfig>,
+}
+
+fn default_form_token_ttl_secs() -> u64 {
+    1800
+}
+fn default_min_fill_secs() -> u64 {
+    3
+}
+
+impl Default for SpamProtectionConfig {
+    fn default() -> Self {
+        Self {
+            form_token_secret: String::new(),
+            form_token_ttl_secs: default_form_token_ttl_secs(),
+            min_fill_secs: default_min_fill_secs(),
+            action: SpamAction::default(),
+            captcha: None,
+            proof_of_work: None,
+        }
+    }
+}
+
+/// Why a submission was taken for a bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpamReason {
+    Honeypot,
+    MissingToken,
+    InvalidToken,
+    ExpiredToken,
+    ReplayedToken,
+    TooFast,
+    MissingProofOfWork,
+    InvalidProofOfWork,
+    ExpiredProofOfWork,
+    ReplayedProofOfWork,
+}
+
+impl Display for SpamReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Honeypot => "honeypot",
+            Self::MissingToken => "missing_token",
+            Self::InvalidToken => "invalid_token",
+            Self::ExpiredToken => "expired_token",
+            Self::ReplayedToken => "replayed_token",
+            Self::TooFast => "too_fast",
+            Self::MissingProofOfWork => "missing_pow",
+            Self::InvalidProofOfWork => "invalid_pow",
+            Self::ExpiredProofOfWork => "expired_pow",
+            Self::ReplayedProofOfWork => "replayed_pow",
+        })
+    }
+}
+
+/// Nonces of accepted form tokens, in the order they were accepted
+#[derive(Default)]
+struct SpentTokens {
+    expires_at: HashMap<String, i64>,
+    order: VecDeque<(i64, String)>,
+}
+
+impl SpentTokens {
+    /// False when the nonce was spent already. Expired nonces are forgotten first, and
+    /// past `MAX_SPENT_TOKENS` the oldest ones too.
+    fn spend(&mut self, nonce: &str, expires_at: i64, now: i64) -> bool {
+        while let Some((at, oldest)) = self.order.front() {
+            if *at >= now && self.order.len() < MAX_SPENT_TOKENS {
+                break;
+            }
+            self.expires_at.remove(oldest);
+            self.order.pop_front();
+        }
+        if self.expires_at.contains_key(nonce) {
+            return false;
+        }
+        self.expires_at.insert(nonce.to_owned(), expires_at);
+        self.order.push_back((expires_at, nonce.to_owned()));
+        true
+    }
+}
+
+/// Keeps automated form submissions away from Pipedrive. Forms fetch a token signed with
+/// `form_token_secret` when they are shown, which tells when the form was opened. Issuing
+/// tokens keeps no state; the nonces of accepted tokens are kept in memory until they
+/// expire, up to `MAX_SPENT_TOKENS` of them, so each token is good for one submission.
+pub struct SpamFilter {
+    db: DbClient,
+    config: SpamProtectionConfig,
+    captcha: Option<Arc<dyn CaptchaVerifier>>,
+    pow: Option<ProofOfWork>,
+    spent_tokens: Mutex<SpentTokens>,
+}
+
+impl SpamFilter {
+    pub fn new(db: DbClient, config: SpamProtectionConfig) -> Self {
+        Self {
+            db,
+            captcha: config.captcha.as_ref().map(|x| x.verifier()),
+            pow: config.proof_of_work.clone().map(ProofOfWork::new),
+            spent_tokens: Mutex::new(SpentTokens::default()),
+            config,
+        }
+    }
+
+    fn sign(&self, issued_at: i64, nonce: &str, form_key: Option<&str>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.form_token_secret.as_bytes())
+            .expect("HMAC takes keys of any size");
+        // the form key is signed too, so a token of one tenant's form is useless on another
+        mac.update(format!("{}.{}.{}", issued_at, nonce, form_key.unwrap_or_default()).as_bytes());
+        mac
+    }
+
+    /// Token in the format `{issued_at}.{nonce}.{signature}`, and when it expires
+    pub fn issue_token(&self, form_key: Option<&str>) -> Result<(String, i64)> {
+        if self.config.form_token_secret.is_empty() {
+            bail!(CustomError::new(
+                EnumErrorCode::InvalidState,
+                "Form tokens are not enabled"
+            ));
+        }
+        let issued_at = get_time_milliseconds();
+        let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let signature = self
+            .sign(issued_at, &nonce, form_key)
+            .finalize()
+            .into_bytes();
+        let token = format!("{}.{}.{}", issued_at, nonce, hex::encode(signature));
+        let expires_at = issued_at + self.config.form_token_ttl_secs as i64 * 1000;
+        Ok((token, expires_at))
+    }
+
+    fn check_token(&self, token: Option<&str>, form_key: Option<&str>) -> Option<SpamReason> {
+        let token = match token {
+            Some(token) => token,
+            None => return Some(SpamReason::MissingToken),
+        };
+        let mut parts = token.splitn(3, '.');
+        let (issued_at, nonce, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(issued_at), Some(nonce), Some(signature)) => (issued_at, nonce, signature),
+            _ => return Some(SpamReason::InvalidToken),
+        };
+        let issued_at = match issued_at.parse::<i64>() {
+            Ok(issued_at) => issued_at,
+            Err(_) => return Some(SpamReason::InvalidToken),
+        };
+        let valid = hex::decode(signature)
+            .map(|signature| {
+                self.sign(issued_at, nonce, form_key)
+                    .verify_slice(&signature)
+                    .is_ok()
+            })
+            .unwrap_or_default();
+        if !valid {
+            return Some(SpamReason::InvalidToken);
+        }
+        let now = get_time_milliseconds();
+        let expires_at = issued_at + self.config.form_token_ttl_secs as i64 * 1000;
+        if now > expires_at {
+            return Some(SpamReason::ExpiredToken);
+        }
+        if now - issued_at < self.config.min_fill_secs as i64 * 1000 {
+            return Some(SpamReason::TooFast);
+        }
+        if !self
+            .spent_tokens
+            .lock()
+            .unwrap()
+            .spend(nonce, expires_at, now)
+        {
+            return Some(SpamReason::ReplayedToken);
+        }
+        None
+    }
+
+    /// Challenge for `IssuePowChallenge`, its difficulty and when it expires
+    pub fn issue_pow_challenge(
+        &self,
+        client_ip: IpAddr,
+        form_key: Option<&str>,
+    ) -> Result<(String, u32, i64)> {
+        match &self.pow {
+            Some(pow) => pow.issue(client_ip, form_key),
+            None => bail!(CustomError::new(
+                EnumErrorCode::InvalidState,
+                "Proof of work is not enabled"
+            )),
+        }
+    }
+
+    /// Reason the submission looks automated, if it does. The form key of `req` must have
+    /// been resolved to a tenant already.
+    pub fn check(&self, req: &AddCrmLeadRequest, client_ip: IpAddr) -> Option<SpamReason> {
+        let form_key = req.form_key.as_deref();
+        if let Some(pow) = &self.pow {
+            // every attempt counts, so bots make their own challenges harder
+            pow.record_submission(client_ip, form_key);
+        }
+        if req
+            .website
+            .as_deref()
+            .map_or(false, |x| !x.trim().is_empty())
+        {
+            return Some(SpamReason::Honeypot);
+        }
+        if !self.config.form_token_secret.is_empty() {
+            if let Some(reason) = self.check_token(req.form_token.as_deref(), form_key) {
+                return Some(reason);
+            }
+        }
+        self.pow.as_ref().and_then(|pow| {
+            pow.verify(
+                req.pow_challenge.as_deref(),
+                req.pow_solution.as_deref(),
+                form_key,
+            )
+        })
+    }
+
+    /// Unlike the silent checks a failed CAPTCHA is reported, people can solve it again
+    pub async fn verify_captcha(&self, token: Option<&str>, client_ip: IpAddr) -> Result<()> {
+        let verifier = match &self.captcha {
+            Some(verifier) => verifier.clone(),
+            None => return Ok(()),
+        };
+        let solved = match token.filter(|x| !x.is_empty()) {
+            Some(token) => verifier
+                .verify(token.to_owned(), client_ip)
+                .await
+                .map_err(|err| {
+                    warn!("Failed to verify CAPTCHA of {}: {:?}", client_ip, err);
+                    CustomError::new(
+                        EnumErrorCode::TemporarilyUnavailable,
+                        "CAPTCHA provider unavailable",
+                    )
+                })?,
+            None => false,
+        };
+        if !solved {
+            bail!(CustomError::new(
+                EnumErrorCode::CaptchaFailed,
+                ErrorCaptchaFailed {}
+            ));
+        }
+        Ok(())
+    }
+
+    /// Drops or quarantines a submission that failed `check`
+    pub async fn reject(
+        &self,
+        req: &AddCrmLeadRequest,
+        reason: SpamReason,
+        client_ip: IpAddr,
+    ) -> Result<()> {
+        let reason = reason.to_string();
+        Metrics::global().increment(
+            "spam_submissions_total",
+            "Lead submissions taken for bots by reason",
+            &[("reason", &reason)],
+        );
+        info!(
+            "Rejected lead of {} from {} as spam: {} ({:?})",
+            req.email, client_ip, reason, self.config.action
+        );
+        if self.config.action == SpamAction::Quarantine {
+            self.db
+                .fun_user_quarantine_lead(FunUserQuarantineLeadReq {
+                    email: req.email.clone(),
+                    payload: serde_json::to_string(req)?,
+                    reason,
+                    client_ip: client_ip.to_string(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct IssueFormTokenHandler {
+    pub spam: Arc<SpamFilter>,
+}
+impl RequestHandler for IssueFormTokenHandler {
+    type Request = IssueFormTokenRequest;
+    type Response = IssueFormTokenResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        _conn: Arc<Connection>,
+        req: Self::Request,
+    ) {
+        let spam = self.spam.clone();
+        toolbox.spawn_response(ctx, async move {
+            let (form_token, expires_at) = spam.issue_token(req.form_key.as_deref())?;
+            Ok(IssueFormTokenResponse {
+                form_token,
+                expires_at,
+            })
+        })
+    }
+}
+
+pub struct IssuePowChallengeHandler {
+    pub spam: Arc<SpamFilter>,
+}
+impl RequestHandler for IssuePowChallengeHandler {
+    type Request = IssuePowChallengeRequest;
+    type Response = IssuePowChallengeResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        conn: Arc<Connection>,
+        req: Self::Request,
+    ) {
+        let spam = self.spam.clone();
+        toolbox.spawn_response(ctx, async move {
+            let (challenge, difficulty, expires_at) =
+                spam.issue_pow_challenge(conn.client_ip, req.form_key.as_deref())?;
+            Ok(IssuePowChallengeResponse {
+                challenge,
+                difficulty: difficulty as _,
+                expires_at,
+            })
+        })
+    }
+}
@@ -0,0 +1,299 @@
+use crate::custom_fields::{CustomFieldMapper, FormConfig};
+use crate::oauth::{OAuthConfig, OAuthTokenManager};
+use crate::pipedrive::{PipeDriveAuth, PipeDriveSdk};
+use crate::rate_limit::RateLimitConfig;
+use crate::webhook::WebhookConfig;
+use eyre::*;
+use gen::database::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::*;
+
+/// Tenant built from the top level `pipedrive_*` settings, used by requests without a form key
+pub const DEFAULT_TENANT: &str = "default";
+
+/// A client company with its own Pipedrive account
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TenantConfig {
+    /// Public key embedded in the forms of the tenant, sent as `formKey` of `AddCrmLead`
+    #[serde(default)]
+    pub form_key: String,
+    #[serde(default)]
+    pub pipedrive_company: String,
+    #[serde(default)]
+    pub pipedrive_api_token: String,
+    #[serde(default)]
+    pub pipedrive_base_url: Option<String>,
+    #[serde(default)]
+    pub pipedrive_oauth: Option<OAuthConfig>,
+    /// Overrides the top level `pipedrive_rate_limit`, Pipedrive limits each company separately
+    #[serde(default)]
+    pub pipedrive_rate_limit: Option<RateLimitConfig>,
+    /// Credentials of the webhooks set up in the tenant's Pipedrive account, they also tell
+    /// which tenant an incoming webhook belongs to
+    #[serde(default)]
+    pub pipedrive_webhook: Option<WebhookConfig>,
+    /// Origins whose pages may submit the forms of the tenant, any origin when empty.
+    /// Browsers only get through when the origin is also listed in `cors.allowed_origins`.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub defaults: TenantDefaults,
+    #[serde(default)]
+    pub forms: HashMap<String, FormConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TenantDefaults {
+    /// Overrides `lead_delivery.title_template`
+    #[serde(default)]
+    pub title_template: Option<String>,
+    /// Form used when a request does not name one
+    #[serde(default)]
+    pub form: Option<String>,
+}
+
+impl TenantConfig {
+    /// Requests without an `Origin` header do not come from a browser and are not restricted
+    pub fn allows_origin(&self, origin: Option<&str>) -> bool {
+        match origin {
+            Some(origin) if !self.allowed_origins.is_empty() => self
+                .allowed_origins
+                .iter()
+                .any(|x| x.trim_end_matches('/').eq_ignore_ascii_case(origin)),
+            _ => true,
+        }
+    }
+}
+
+pub struct Tenant {
+    pub name: String,
+    pub config: TenantConfig,
+    pub sdk: PipeDriveSdk,
+    pub mapper: CustomFieldMapper,
+}
+
+/// Pool of the `PipeDriveSdk` of every tenant. Tenants come from the config and from
+/// `tbl.tenant`; `reload` rebuilds a tenant only when its definition changed.
+pub struct TenantRegistry {
+    db: DbClient,
+    static_tenants: HashMap<String, TenantConfig>,
+    rate_limit: RateLimitConfig,
+    client: reqwest::Client,
+    reload_interval: Duration,
+    tenants: RwLock<HashMap<String, Arc<Tenant>>>,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl TenantRegistry {
+    /// Fails if a tenant defined in the config cannot be built, tenants stored in Postgres are
+    /// skipped with an error instead so one broken row does not stop the gateway
+    pub async fn new(
+        db: DbClient,
+        static_tenants: HashMap<String, TenantConfig>,
+        rate_limit: RateLimitConfig,
+        reload_interval: Duration,
+    ) -> Result<Self> {
+        let this = Self {
+            db,
+            static_tenants,
+            rate_limit,
+            client: reqwest::Client::new(),
+            reload_interval,
+            tenants: RwLock::new(HashMap::new()),
+            running: tokio::sync::Mutex::new(()),
+        };
+        let mut form_keys = HashMap::new();
+        for (name, config) in &this.static_tenants {
+            check_form_key(&mut form_keys, name, config)?;
+        }
+        let mut tenants = HashMap::new();
+        for (name, config) in &this.static_tenants {
+            let tenant = this
+                .build(name, config.clone())
+                .await
+                .with_context(|| format!("Failed to set up tenant {}", name))?;
+            tenants.insert(name.clone(), Arc::new(tenant));
+        }
+        *this.tenants.write().unwrap() = tenants;
+        this.reload().await?;
+        Ok(this)
+    }
+    pub fn interval(&self) -> Duration {
+        self.reload_interval
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Tenant>> {
+        self.tenants.read().unwrap().get(name).cloned()
+    }
+    pub fn by_form_key(&self, form_key: &str) -> Option<Arc<Tenant>> {
+        if form_key.is_empty() {
+            return None;
+        }
+        self.tenants
+            .read()
+            .unwrap()
+            .values()
+            .find(|x| x.config.form_key == form_key)
+            .cloned()
+    }
+    pub fn by_webhook_credentials(&self, username: &str, password: &str) -> Option<Arc<Tenant>> {
+        self.tenants
+            .read()
+            .unwrap()
+            .values()
+            .find(|x| {
+                x.config
+                    .pipedrive_webhook
+                    .as_ref()
+                    .map(|x| x.matches(username, password))
+                    .unwrap_or_default()
+            })
+            .cloned()
+    }
+
+    /// Succeeds when Pipedrive answers for any tenant, the credentials of one tenant being
+    /// revoked must not take the gateway out of rotation for all of them
+    pub async fn ping(&self) -> Result<()> {
+        let tenants: Vec<_> = self.tenants.read().unwrap().values().cloned().collect();
+        if tenants.is_empty() {
+            return Ok(());
+        }
+        let results =
+            futures::future::join_all(tenants.iter().map(|tenant| tenant.sdk.ping())).await;
+        let mut errors = vec![];
+        for (tenant, result) in tenants.iter().zip(results) {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) => errors.push(format!("{}: {:#}", tenant.name, err)),
+            }
+        }
+        bail!(
+            "Pipedrive is unreachable for every tenant: {}",
+            errors.join("; ")
+        )
+    }
+
+    /// Picks up tenants added, changed or removed in `tbl.tenant`
+    pub async fn reload(&self) -> Result<()> {
+        // the scheduler does not wait for the previous run, so skip if one is still in flight
+        let _guard = match self.running.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return Ok(()),
+        };
+        let mut configs = self.static_tenants.clone();
+        let mut form_keys = HashMap::new();
+        for (name, config) in &configs {
+            check_form_key(&mut form_keys, name, config)?;
+        }
+        let rows = self
+            .db
+            .fun_user_list_tenants(FunUserListTenantsReq {})
+            .await?
+            .rows;
+        for row in rows {
+            if self.static_tenants.contains_key(&row.name) {
+                warn!(
+                    "Tenant {} is defined in the config, ignoring tbl.tenant",
+                    row.name
+                );
+                continue;
+            }
+            let config = match parse_tenant_row(&row) {
+                Ok(config) => config,
+                Err(err) => {
+                    error!("Invalid config of tenant {}: {:?}", row.name, err);
+                    continue;
+                }
+            };
+            // a form key taken twice would send the leads of one client to another's account
+            if let Err(err) = check_form_key(&mut form_keys, &row.name, &config) {
+                error!("Ignoring tenant {}: {:?}", row.name, err);
+                continue;
+            }
+            configs.insert(row.name, config);
+        }
+
+        let current = self.tenants.read().unwrap().clone();
+        let mut tenants = HashMap::new();
+        for (name, config) in configs {
+            match current.get(&name) {
+                Some(tenant) if tenant.config == config => {
+                    tenants.insert(name, tenant.clone());
+                }
+                existing => match self.build(&name, config).await {
+                    Ok(tenant) => {
+                        info!("Tenant {} loaded", name);
+                        tenants.insert(name, Arc::new(tenant));
+                    }
+                    Err(err) => {
+                        // keep serving with the previous credentials until the tenant is fixed
+                        error!("Failed to set up tenant {}: {:?}", name, err);
+                        if let Some(tenant) = existing {
+                            tenants.insert(name, tenant.clone());
+                        }
+                    }
+                },
+            }
+        }
+        for name in current.keys().filter(|x| !tenants.contains_key(*x)) {
+            info!("Tenant {} removed", name);
+        }
+        *self.tenants.write().unwrap() = tenants;
+        Ok(())
+    }
+
+    async fn build(&self, name: &str, config: TenantConfig) -> Result<Tenant> {
+        let mut sdk = match &config.pipedrive_base_url {
+            Some(base_url) => PipeDriveSdk::with_base_url(&config.pipedrive_api_token, base_url),
+            None => PipeDriveSdk::new(&config.pipedrive_api_token, &config.pipedrive_company),
+        }
+        .with_client(self.client.clone())
+        .with_rate_limit(
+            config
+                .pipedrive_rate_limit
+                .clone()
+                .unwrap_or_else(|| self.rate_limit.clone()),
+        );
+        if let Some(oauth) = config.pipedrive_oauth.clone() {
+            let oauth = OAuthTokenManager::new(self.db.clone(), name.to_owned(), oauth).await?;
+            sdk = sdk.with_auth(PipeDriveAuth::OAuth(Arc::new(oauth)));
+        }
+        let mapper = CustomFieldMapper::load(&sdk, &config.forms).await?;
+        Ok(Tenant {
+            name: name.to_owned(),
+            config,
+            sdk,
+            mapper,
+        })
+    }
+}
+
+/// Only the default tenant may go without a form key, and no two tenants may share one
+fn check_form_key(
+    form_keys: &mut HashMap<String, String>,
+    name: &str,
+    config: &TenantConfig,
+) -> Result<()> {
+    if config.form_key.is_empty() && name != DEFAULT_TENANT {
+        bail!("Tenant {} has no form_key", name);
+    }
+    if let Some(other) = form_keys.get(&config.form_key) {
+        bail!("Tenants {} and {} share a form_key", other, name);
+    }
+    form_keys.insert(config.form_key.clone(), name.to_owned());
+    Ok(())
+}
+
+/// `config` of a `tbl.tenant` row is a `TenantConfig` without the form key
+fn parse_tenant_row(row: &FunUserListTenantsRespRow) -> Result<TenantConfig> {
+    let mut config: Value = serde_json::from_str(&row.config)?;
+    config
+        .as_object_mut()
+        .context("Tenant config is not an object")?
+        .insert("form_key".to_owned(), Value::from(row.form_key.clone()));
+    Ok(serde_json::from_value(config)?)
+}
@@ -0,0 +1,332 @@
+use crate::method::{ensure_admin_token, secure_eq};
+use crate::tenant::{Tenant, TenantRegistry};
+use base64::Engine;
+use eyre::*;
+use gen::database::*;
+use gen::model::{
+    EnumErrorCode, EnumPipedriveStatus, SubscribePipedriveEventsRequest,
+    SubscribePipedriveEventsResponse,
+};
+use lib::handler::{RequestHandler, RequestHandlerErased};
+use lib::toolbox::{CustomError, RequestContext, Toolbox};
+use lib::ws::{Connection, WsResponse, WsStreamResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::*;
+
+/// HTTP basic-auth credentials Pipedrive sends with the webhooks of a tenant
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub username: String,
+    pub password: String,
+}
+
+impl WebhookConfig {
+    pub fn matches(&self, username: &str, password: &str) -> bool {
+        secure_eq(&self.username, username) & secure_eq(&self.password, password)
+    }
+}
+
+/// A webhook event after it has been stored and applied to `tbl.lead`
+#[derive(Debug, Clone, Serialize)]
+pub struct PipedriveEvent {
+    pub event_id: i64,
+    pub tenant: String,
+    /// `added`, `updated` or `deleted`
+    pub action: String,
+    /// `lead`, `person`, `deal`, ...
+    pub object: String,
+    pub object_id: Option<String>,
+    /// Local leads whose Pipedrive state changed with this event
+    pub lead_ids: Vec<i64>,
+    pub current: Value,
+    pub previous: Value,
+}
+
+/// Pipedrive webhook payload, both the v1 (`current`, `meta.object`) and the
+/// v2 (`data`, `meta.entity`) format are accepted
+#[derive(Debug, Deserialize)]
+struct WebhookPayload {
+    meta: WebhookMeta,
+    #[serde(default, alias = "data")]
+    current: Value,
+    #[serde(default)]
+    previous: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookMeta {
+    action: String,
+    #[serde(alias = "entity")]
+    object: String,
+    #[serde(default, alias = "entity_id")]
+    id: Value,
+}
+
+impl WebhookPayload {
+    /// v2 uses `create`/`change`/`delete`, v1 `added`/`updated`/`deleted`
+    fn action(&self) -> &str {
+        match self.meta.action.as_str() {
+            "create" | "added" => "added",
+            "change" | "updated" => "updated",
+            "delete" | "deleted" => "deleted",
+            action => action,
+        }
+    }
+    /// Falls back to the `id` of the object, `meta.id` is not set for every object type
+    fn object_id(&self) -> Option<String> {
+        [&self.meta.id, &self.state()["id"]]
+            .into_iter()
+            .find_map(|id| match id {
+                Value::String(id) => Some(id.clone()),
+                Value::Number(id) => Some(id.to_string()),
+                _ => None,
+            })
+    }
+    /// The current state, or the last known one for deletions
+    fn state(&self) -> &Value {
+        if self.current.is_object() {
+            &self.current
+        } else {
+            &self.previous
+        }
+    }
+}
+
+/// Stores Pipedrive webhook events, applies them to the leads we forwarded and broadcasts
+/// them to the rest of the gateway
+pub struct WebhookReceiver {
+    db: DbClient,
+    tenants: Arc<TenantRegistry>,
+    events: broadcast::Sender<Arc<PipedriveEvent>>,
+}
+
+impl WebhookReceiver {
+    pub fn new(db: DbClient, tenants: Arc<TenantRegistry>) -> Self {
+        Self {
+            db,
+            tenants,
+            events: broadcast::channel(1024).0,
+        }
+    }
+    /// Events received after subscribing. Slow receivers miss events rather than block delivery.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<PipedriveEvent>> {
+        self.events.subscribe()
+    }
+
+    fn authenticate(&self, authorization: Option<&str>) -> Result<Arc<Tenant>> {
+        let unauthorized =
+            || CustomError::new(EnumErrorCode::Unauthorized, "Invalid webhook credentials");
+        let credentials = authorization
+            .and_then(|x| x.strip_prefix("Basic "))
+            .and_then(|x| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(x.trim())
+                    .ok()
+            })
+            .and_then(|x| String::from_utf8(x).ok())
+            .ok_or_else(unauthorized)?;
+        let (username, password) = credentials.split_once(':').ok_or_else(unauthorized)?;
+        Ok(self
+            .tenants
+            .by_webhook_credentials(username, password)
+            .ok_or_else(unauthorized)?)
+    }
+
+    pub async fn receive(&self, authorization: Option<&str>, body: Value) -> Result<i64> {
+        let tenant = self.authenticate(authorization)?;
+        let payload: WebhookPayload = serde_json::from_value(body.clone()).map_err(|err| {
+            CustomError::new(
+                EnumErrorCode::InvalidArgument,
+                format!("Invalid webhook payload: {}", err),
+            )
+        })?;
+        let action = payload.action().to_owned();
+        let object_id = payload.object_id();
+        let event_id = self
+            .db
+            .fun_user_add_pipedrive_webhook_event(FunUserAddPipedriveWebhookEventReq {
+                tenant: tenant.name.clone(),
+                action: action.clone(),
+                object: payload.meta.object.clone(),
+                payload: body.to_string(),
+                object_id: object_id.clone(),
+            })
+            .await?
+            .rows
+            .into_iter()
+            .next()
+            .context("No event id returned")?
+            .event_id;
+        let lead_ids = self.apply(&tenant, &payload).await?;
+        info!(
+            "Pipedrive webhook {} {} {:?} of tenant {} updated leads {:?}",
+            action, payload.meta.object, object_id, tenant.name, lead_ids
+        );
+        // nobody listening is not an error
+        let _ = self.events.send(Arc::new(PipedriveEvent {
+            event_id,
+            tenant: tenant.name.clone(),
+            action,
+            object: payload.meta.object,
+            object_id,
+            lead_ids,
+            current: payload.current,
+            previous: payload.previous,
+        }));
+        Ok(event_id)
+    }
+
+    /// Updates the Pipedrive state of the local leads an event refers to
+    async fn apply(&self, tenant: &Tenant, payload: &WebhookPayload) -> Result<Vec<i64>> {
+        let deleted = payload.action() == "deleted";
+        let state = payload.state();
+        let lead_ids = match payload.meta.object.as_str() {
+            "lead" => {
+                let Some(pipedrive_lead_id) = payload.object_id() else {
+                    return Ok(vec![]);
+                };
+                // a lead converted to a deal is archived or deleted with the id of the deal
+                let deal_id = state["deal_id"].as_i64();
+                let status = if deal_id.is_some() {
+                    EnumPipedriveStatus::DealOpen
+                } else if deleted {
+                    EnumPipedriveStatus::LeadDeleted
+                } else if state["is_archived"].as_bool() == Some(true) {
+                    EnumPipedriveStatus::LeadArchived
+                } else {
+                    EnumPipedriveStatus::LeadOpen
+                };
+                self.db
+                    .fun_user_update_lead_from_pipedrive_lead(
+                        FunUserUpdateLeadFromPipedriveLeadReq {
+                            tenant: tenant.name.clone(),
+                            pipedrive_lead_id,
+                            pipedrive_status: status,
+                            pipedrive_deal_id: deal_id,
+                        },
+                    )
+                    .await?
+                    .rows
+                    .into_iter()
+                    .map(|x| x.lead_id)
+                    .collect()
+            }
+            "deal" => {
+                let deal_id = payload.object_id().and_then(|x| x.parse().ok());
+                // `person_id` is a plain id in webhooks and `{value: id}` in some payloads
+                let person_id = state["person_id"]
+                    .as_i64()
+                    .or_else(|| state["person_id"]["value"].as_i64());
+                let (Some(deal_id), Some(person_id)) = (deal_id, person_id) else {
+                    return Ok(vec![]);
+                };
+                let status = match state["status"].as_str() {
+                    _ if deleted => EnumPipedriveStatus::DealDeleted,
+                    Some("won") => EnumPipedriveStatus::DealWon,
+                    Some("lost") => EnumPipedriveStatus::DealLost,
+                    Some("deleted") => EnumPipedriveStatus::DealDeleted,
+                    _ => EnumPipedriveStatus::DealOpen,
+                };
+                self.db
+                    .fun_user_update_lead_from_pipedrive_deal(
+                        FunUserUpdateLeadFromPipedriveDealReq {
+                            tenant: tenant.name.clone(),
+                            pipedrive_person_id: person_id,
+                            pipedrive_deal_id: deal_id,
+                            pipedrive_status: status,
+                        },
+                    )
+                    .await?
+                    .rows
+                    .into_iter()
+                    .map(|x| x.lead_id)
+                    .collect()
+            }
+            _ => vec![],
+        };
+        Ok(lead_ids)
+    }
+}
+
+/// Receives the raw webhook body, whose shape is defined by Pipedrive rather than by an
+/// endpoint schema, so it is registered with `add_handler_erased`
+pub struct PipedriveWebhookHandler {
+    pub receiver: Arc<WebhookReceiver>,
+}
+impl RequestHandlerErased for PipedriveWebhookHandler {
+    fn handle(&self, toolbox: &Toolbox, ctx: RequestContext, conn: Arc<Connection>, req: Value) {
+        let receiver = self.receiver.clone();
+        toolbox.spawn_response(ctx, async move {
+            receiver.receive(conn.header("authorization"), req).await?;
+            Ok(serde_json::json!({}))
+        })
+    }
+}
+
+/// Forwards webhook events to the connection until `limit` is reached or the subscriber is gone.
+/// A client that leaves is noticed when the next event cannot be delivered.
+pub struct SubscribePipedriveEventsHandler {
+    pub admin_token: String,
+    pub receiver: Arc<WebhookReceiver>,
+}
+impl RequestHandler for SubscribePipedriveEventsHandler {
+    type Request = SubscribePipedriveEventsRequest;
+    type Response = SubscribePipedriveEventsResponse;
+    fn handle(
+        &self,
+        toolbox: &Toolbox,
+        ctx: RequestContext,
+        _conn: Arc<Connection>,
+        req: Self::Request,
+    ) {
+        let admin_token = self.admin_token.clone();
+        let mut events = self.receiver.subscribe();
+        let stream = toolbox.clone();
+        toolbox.spawn_response(ctx, async move {
+            ensure_admin_token(&admin_token, &req.admin_token)?;
+            let limit = req.limit.map(|x| x.max(0) as u32);
+            let mut stream_seq = 0;
+            while limit.map_or(true, |limit| stream_seq < limit) {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    // the gateway is shutting down, end the stream like an exhausted limit
+                    _ = stream.tasks.closed() => break,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Subscriber missed {} Pipedrive events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let data = serde_json::json!({
+                    "eventId": event.event_id,
+                    "tenant": event.tenant,
+                    "action": event.action,
+                    "object": event.object,
+                    "objectId": event.object_id,
+                    "leadIds": event.lead_ids,
+                });
+                let sent = stream.send(
+                    ctx.connection_id,
+                    WsResponse::Stream(WsStreamResponse {
+                        method: ctx.method,
+                        stream_seq,
+                        resource: event.tenant.clone(),
+                        data,
+                    }),
+                );
+                if !sent {
+                    break;
+                }
+                stream_seq += 1;
+            }
+            Ok(SubscribePipedriveEventsResponse {})
+        })
+    }
+}
@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+
+pub mod pipedrive_mock;
+
+use eyre::*;
+use serde_json::{json, Value};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+pub use pipedrive_mock::*;
+
+pub const PIPEDRIVE_API_TOKEN: &str = "test-api-token";
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Database the `user` binary connects to. Defaults match `etc/config_template.json`
+/// and the CI postgres service; override with the usual `PG*` environment variables.
+pub fn app_db_config() -> Value {
+    let env = |key: &str, default: &str| std::env::var(key).unwrap_or_else(|_| default.to_owned());
+    json!({
+        "host": env("PGHOST", "localhost"),
+        "port": env("PGPORT", "5432").parse::<u16>().unwrap_or(5432),
+        "user": env("PGUSER", "postgres"),
+        "password": env("PGPASSWORD", "123456"),
+        "dbname": env("PGDATABASE", "gw"),
+    })
+}
+
+/// Connects to the database of the `user` binary, e.g. to manage `tbl.tenant`
+pub async fn connect_app_db() -> Result<lib::database::SimpleDbClient> {
+    let config = serde_json::from_value(app_db_config())?;
+    lib::database::connect_to_database(config).await
+}
+
+/// All servers share one database, so a worker of one test could claim the leads of another.
+/// Tests therefore hold this lock for as long as their server is running.
+static SERVER_LOCK: Mutex<()> = Mutex::new(());
+
+fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// A running `user` binary, killed on drop
+pub struct UserServer {
+    pub addr: SocketAddr,
+    child: Child,
+    _dir: TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl UserServer {
+    /// Starts the `user` binary with its Pipedrive base URL pointing at `mock`.
+    /// `extra` is merged into the `user` section of the generated config.
+    pub async fn start(mock: &PipeDriveMock, extra: Value) -> Result<Self> {
+        let lock = SERVER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let port = free_port()?;
+        let mut user = json!({
+            "pipedrive_company": "",
+            "pipedrive_api_token": mock.api_token,
+            "pipedrive_base_url": mock.base_url(),
+            "admin_token": ADMIN_TOKEN,
+            "lead_delivery": {
+                "interval_secs": 1,
+                "retry_base_secs": 1,
+                "retry_max_secs": 1,
+            },
+            "host": "127.0.0.1",
+            "port": port,
+            "log_level": "info",
+        });
+        if let (Some(user), Value::Object(extra)) = (user.as_object_mut(), extra) {
+            user.extend(extra);
+        }
+        let config = json!({
+            "app_db": app_db_config(),
+            "auth_db": app_db_config(),
+            "user": user,
+        });
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.json");
+        std::fs::write(&config_path, serde_json::to_string_pretty(&config)?)?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_user"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .spawn()?;
+        let mut server = Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            child,
+            _dir: dir,
+            _lock: lock,
+        };
+        server.wait_until_listening(Duration::from_secs(30)).await?;
+        Ok(server)
+    }
+
+    async fn wait_until_listening(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                bail!("user server exited early: {}", status);
+            }
+            if tokio::net::TcpStream::connect(self.addr).await.is_ok() {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        bail!("user server did not start listening on {}", self.addr)
+    }
+
+    /// Sends SIGTERM like systemd does on stop and waits for the server to exit
+    pub async fn terminate(&mut self, timeout: Duration) -> Result<std::process::ExitStatus> {
+        let status = Command::new("kill")
+            .arg("-TERM")
+            .arg(self.child.id().to_string())
+            .status()?;
+        ensure!(status.success(), "Failed to send SIGTERM: {}", status);
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        bail!("user server did not exit within {:?}", timeout)
+    }
+
+    pub fn url(&self, endpoint: &str) -> String {
+        format!("http://{}/{}", self.addr, endpoint)
+    }
+
+    /// Posts a JSON request to an endpoint and returns the status code and parsed body
+    pub async fn call(&self, endpoint: &str, req: Value) -> Result<(u16, Value)> {
+        self.call_with_headers(endpoint, &[], req).await
+    }
+
+    /// Like `call`, with extra request headers such as the `Origin` of a browser form
+    pub async fn call_with_headers(
+        &self,
+        endpoint: &str,
+        headers: &[(&str, &str)],
+        req: Value,
+    ) -> Result<(u16, Value)> {
+        let mut request = reqwest::Client::new().post(self.url(endpoint)).json(&req);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let resp = request.send().await?;
+        let status = resp.status().as_u16();
+        let text = resp.text().await?;
+        let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+        Ok((status, body))
+    }
+}
+
+impl Drop for UserServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Polls `f` until it returns `Some` or the timeout expires
+pub async fn wait_for<T>(timeout: Duration, mut f: impl FnMut() -> Option<T>) -> Result<T> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = f() {
+            return Ok(value);
+        }
+        if Instant::now() > deadline {
+            bail!("condition not met within {:?}", timeout);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
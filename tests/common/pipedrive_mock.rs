@@ -0,0 +1,481 @@
+use base64::Engine;
+use eyre::*;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+pub const COMPANY_SIZE_KEY: &str = "6f1d0a3c9b2e4f5a8c7d6e5f4a3b2c1d0e9f8a7b";
+pub const BUDGET_KEY: &str = "0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b";
+pub const INDUSTRY_KEY: &str = "1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c";
+pub const PRODUCT_INTEREST_KEY: &str = "9f8e7d6c5b4a39281706f5e4d3c2b1a09f8e7d6c";
+
+/// In-memory stand-in for the parts of the Pipedrive v1 API used by `PipeDriveSdk`
+#[derive(Default)]
+pub struct MockState {
+    pub next_id: i64,
+    pub persons: Vec<Value>,
+    pub organizations: Vec<Value>,
+    pub users: Vec<Value>,
+    pub leads: Vec<Value>,
+    pub notes: Vec<Value>,
+    pub person_fields: Vec<Value>,
+    pub organization_fields: Vec<Value>,
+    pub deal_fields: Vec<Value>,
+    /// Fail this many `POST leads` calls before accepting them again
+    pub failing_leads: usize,
+    /// Fail this many `POST notes` calls before accepting them again
+    pub failing_notes: usize,
+    /// Answer this many calls of any kind with 429 Too Many Requests
+    pub rate_limited: usize,
+    /// Calls answered with 429 so far
+    pub rejected: usize,
+    /// `(client_id, client_secret)` of the OAuth app, once enabled
+    pub oauth_client: Option<(String, String)>,
+    pub oauth_expires_in: u64,
+    pub refresh_tokens: HashSet<String>,
+    pub access_tokens: HashSet<String>,
+    pub token_refreshes: usize,
+    pub api_token_calls: usize,
+    pub bearer_calls: usize,
+}
+
+impl MockState {
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+    /// Stores `org_id` the way Pipedrive returns it, as an object with `value` and `name`
+    fn link_organization(&self, person: &mut Value) {
+        if let Some(org_id) = person["org_id"].as_i64() {
+            let name = self
+                .organizations
+                .iter()
+                .find(|o| o["id"] == json!(org_id))
+                .map(|o| o["name"].clone())
+                .unwrap_or_default();
+            person["org_id"] = json!({ "value": org_id, "name": name });
+        }
+    }
+}
+
+pub struct PipeDriveMock {
+    pub addr: SocketAddr,
+    pub api_token: String,
+    pub state: Arc<Mutex<MockState>>,
+}
+
+impl PipeDriveMock {
+    pub async fn start(api_token: impl Into<String>) -> Result<Self> {
+        let api_token = api_token.into();
+        let state = Arc::new(Mutex::new(MockState {
+            person_fields: vec![
+                json!({ "id": 1, "key": "name", "name": "Name", "field_type": "varchar" }),
+                json!({ "id": 2, "key": "email", "name": "Email", "field_type": "varchar" }),
+                json!({
+                    "id": 3,
+                    "key": COMPANY_SIZE_KEY,
+                    "name": "Company size",
+                    "field_type": "enum",
+                    "options": [
+                        { "id": 11, "label": "1-10" },
+                        { "id": 12, "label": "11-50" },
+                        { "id": 13, "label": "51+" },
+                    ],
+                }),
+                json!({ "id": 4, "key": "owner_id", "name": "Owner", "field_type": "user" }),
+            ],
+            organization_fields: vec![
+                json!({ "id": 5, "key": "name", "name": "Name", "field_type": "varchar" }),
+                json!({ "id": 8, "key": INDUSTRY_KEY, "name": "Industry", "field_type": "varchar" }),
+            ],
+            deal_fields: vec![
+                json!({ "id": 6, "key": BUDGET_KEY, "name": "Budget", "field_type": "double" }),
+                json!({
+                    "id": 7,
+                    "key": PRODUCT_INTEREST_KEY,
+                    "name": "Product interest",
+                    "field_type": "set",
+                    "options": [
+                        { "id": 21, "label": "Gateway" },
+                        { "id": 22, "label": "Analytics" },
+                    ],
+                }),
+            ],
+            ..Default::default()
+        }));
+
+        let make_svc = {
+            let state = state.clone();
+            let api_token = api_token.clone();
+            make_service_fn(move |_| {
+                let state = state.clone();
+                let api_token = api_token.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        handle(state.clone(), api_token.clone(), req)
+                    }))
+                }
+            })
+        };
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Ok(Self {
+            addr,
+            api_token,
+            state,
+        })
+    }
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1/", self.addr)
+    }
+    pub fn leads(&self) -> Vec<Value> {
+        self.state.lock().unwrap().leads.clone()
+    }
+    pub fn notes(&self) -> Vec<Value> {
+        self.state.lock().unwrap().notes.clone()
+    }
+    pub fn organizations(&self) -> Vec<Value> {
+        self.state.lock().unwrap().organizations.clone()
+    }
+    pub fn persons(&self) -> Vec<Value> {
+        self.state.lock().unwrap().persons.clone()
+    }
+    pub fn fail_leads(&self, times: usize) {
+        self.state.lock().unwrap().failing_leads = times;
+    }
+    pub fn fail_notes(&self, times: usize) {
+        self.state.lock().unwrap().failing_notes = times;
+    }
+    pub fn rate_limit(&self, times: usize) {
+        self.state.lock().unwrap().rate_limited = times;
+    }
+    pub fn rejected(&self) -> usize {
+        self.state.lock().unwrap().rejected
+    }
+    /// Accepts bearer tokens issued to this OAuth client and returns an initial refresh token
+    pub fn enable_oauth(&self, client_id: &str, client_secret: &str, expires_in: u64) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.oauth_client = Some((client_id.to_owned(), client_secret.to_owned()));
+        state.oauth_expires_in = expires_in;
+        let refresh_token = uuid::Uuid::new_v4().to_string();
+        state.refresh_tokens.insert(refresh_token.clone());
+        refresh_token
+    }
+    pub fn token_url(&self) -> String {
+        format!("http://{}/oauth/token", self.addr)
+    }
+    pub fn revoke_access_tokens(&self) {
+        self.state.lock().unwrap().access_tokens.clear();
+    }
+    pub fn token_refreshes(&self) -> usize {
+        self.state.lock().unwrap().token_refreshes
+    }
+    /// Number of calls authenticated with the `api_token` query parameter and with a bearer token
+    pub fn auth_calls(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.api_token_calls, state.bearer_calls)
+    }
+}
+
+fn parse_query(query: Option<&str>) -> HashMap<String, String> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| {
+            let v = v.replace('+', " ");
+            let v = urlencoding::decode(&v).map(|x| x.into_owned()).unwrap_or(v);
+            (k.to_owned(), v)
+        })
+        .collect()
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn success(data: Value) -> Response<Body> {
+    reply(StatusCode::OK, json!({ "success": true, "data": data }))
+}
+
+fn failure(status: StatusCode, error: &str, error_info: &str) -> Response<Body> {
+    reply(
+        status,
+        json!({ "success": false, "error": error, "error_info": error_info }),
+    )
+}
+
+async fn handle(
+    state: Arc<Mutex<MockState>>,
+    api_token: String,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().trim_start_matches("/v1/").to_owned();
+    let query = parse_query(req.uri().query());
+    let authorization = req
+        .headers()
+        .get("authorization")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let raw_body = hyper::body::to_bytes(req.into_body())
+        .await
+        .unwrap_or_default();
+    let body: Value = serde_json::from_slice(&raw_body).unwrap_or(Value::Null);
+
+    let mut state = state.lock().unwrap();
+    if path == "/oauth/token" {
+        return Ok(issue_token(
+            &mut state,
+            &authorization,
+            &String::from_utf8_lossy(&raw_body),
+        ));
+    }
+    let bearer = authorization.strip_prefix("Bearer ").unwrap_or_default();
+    if !bearer.is_empty() && state.access_tokens.contains(bearer) {
+        state.bearer_calls += 1;
+    } else if !api_token.is_empty() && query.get("api_token") == Some(&api_token) {
+        state.api_token_calls += 1;
+    } else {
+        return Ok(failure(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized access",
+            "Please check your api_token",
+        ));
+    }
+    if state.rate_limited > 0 {
+        state.rate_limited -= 1;
+        state.rejected += 1;
+        let mut resp = failure(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Request over limit",
+            "Rate limit exceeded",
+        );
+        let headers = resp.headers_mut();
+        headers.insert("x-ratelimit-limit", 80.into());
+        headers.insert("x-ratelimit-remaining", 0.into());
+        headers.insert("x-ratelimit-reset", 1.into());
+        return Ok(resp);
+    }
+    let resp = match (method, path.as_str()) {
+        (Method::GET, "persons/search") => {
+            let term = query.get("term").cloned().unwrap_or_default();
+            let items: Vec<Value> = state
+                .persons
+                .iter()
+                .filter(|p| p["primary_email"].as_str() == Some(term.as_str()))
+                .map(|p| {
+                    let organization = match p["org_id"]["value"].as_i64() {
+                        Some(id) => json!({ "id": id, "name": p["org_id"]["name"] }),
+                        None => Value::Null,
+                    };
+                    let item = json!({
+                        "id": p["id"],
+                        "name": p["name"],
+                        "primary_email": p["primary_email"],
+                        "organization": organization,
+                    });
+                    json!({ "result_score": 1.0, "item": item })
+                })
+                .collect();
+            success(json!({ "items": items }))
+        }
+        (Method::POST, "persons") => {
+            let mut person = body.clone();
+            person["id"] = json!(state.next_id());
+            person["primary_email"] = body["email"].clone();
+            state.link_organization(&mut person);
+            state.persons.push(person.clone());
+            success(person)
+        }
+        (Method::PUT, path) if path.starts_with("persons/") => {
+            let id: i64 = path["persons/".len()..].parse().unwrap_or_default();
+            let mut update = body.clone();
+            state.link_organization(&mut update);
+            match state.persons.iter_mut().find(|p| p["id"] == json!(id)) {
+                Some(person) => {
+                    if let (Some(person), Some(update)) =
+                        (person.as_object_mut(), update.as_object())
+                    {
+                        person.extend(update.clone());
+                    }
+                    success(person.clone())
+                }
+                None => failure(StatusCode::NOT_FOUND, "Person not found", ""),
+            }
+        }
+        (Method::GET, "organizations/search") => {
+            let term = query.get("term").cloned().unwrap_or_default();
+            let items: Vec<Value> = state
+                .organizations
+                .iter()
+                .filter(|o| {
+                    o["name"].as_str().map(|x| x.to_lowercase()) == Some(term.to_lowercase())
+                })
+                .map(|o| json!({ "result_score": 1.0, "item": { "id": o["id"], "name": o["name"] } }))
+                .collect();
+            success(json!({ "items": items }))
+        }
+        (Method::POST, "organizations") => {
+            let mut organization = body.clone();
+            organization["id"] = json!(state.next_id());
+            organization["people_count"] = json!(0);
+            organization["active_flag"] = json!(true);
+            organization["address"] = Value::Null;
+            // like Pipedrive's, the owner has no picture hash unless a picture was uploaded
+            organization["owner_id"] = json!({
+                "id": 1,
+                "name": "Mock Owner",
+                "email": "owner@mock.pipedrive.com",
+                "has_pic": 0,
+                "pic_hash": null,
+                "active_flag": true,
+                "value": 1,
+            });
+            state.organizations.push(organization.clone());
+            success(organization)
+        }
+        (Method::PUT, path) if path.starts_with("organizations/") => {
+            let id: i64 = path["organizations/".len()..].parse().unwrap_or_default();
+            match state
+                .organizations
+                .iter_mut()
+                .find(|o| o["id"] == json!(id))
+            {
+                Some(organization) => {
+                    if let (Some(organization), Some(body)) =
+                        (organization.as_object_mut(), body.as_object())
+                    {
+                        organization.extend(body.clone());
+                    }
+                    success(organization.clone())
+                }
+                None => failure(StatusCode::NOT_FOUND, "Organization not found", ""),
+            }
+        }
+        (Method::GET, "personFields") => success(json!(state.person_fields)),
+        (Method::GET, "organizationFields") => success(json!(state.organization_fields)),
+        (Method::GET, "dealFields") => success(json!(state.deal_fields)),
+        (Method::GET, "users/find") => {
+            let term = query.get("term").cloned().unwrap_or_default();
+            let users: Vec<Value> = state
+                .users
+                .iter()
+                .filter(|u| u["email"].as_str() == Some(term.as_str()))
+                .cloned()
+                .collect();
+            if users.is_empty() {
+                success(Value::Null)
+            } else {
+                success(Value::Array(users))
+            }
+        }
+        (Method::GET, "users/me") => success(json!({
+            "id": 1,
+            "name": "Gateway",
+            "email": "gateway@example.com",
+            "active_flag": true,
+        })),
+        (Method::POST, "users") => {
+            let user = json!({
+                "id": state.next_id(),
+                "name": body["name"],
+                "email": body["email"],
+                "has_pic": 0,
+                "pic_hash": "",
+                "active_flag": true,
+            });
+            state.users.push(user.clone());
+            success(user)
+        }
+        (Method::POST, "leads") if state.failing_leads > 0 => {
+            state.failing_leads -= 1;
+            failure(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service unavailable",
+                "The mock was told to fail this request",
+            )
+        }
+        (Method::POST, "leads") => {
+            let mut lead = body.clone();
+            lead["id"] = json!(uuid::Uuid::new_v4().to_string());
+            state.leads.push(lead.clone());
+            success(lead)
+        }
+        (Method::POST, "notes") if state.failing_notes > 0 => {
+            state.failing_notes -= 1;
+            failure(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service unavailable",
+                "The mock was told to fail this request",
+            )
+        }
+        (Method::POST, "notes") => {
+            let exists = state.leads.iter().any(|l| l["id"] == body["lead_id"]);
+            if body["content"].as_str().unwrap_or_default().is_empty() {
+                failure(StatusCode::BAD_REQUEST, "Content is required", "")
+            } else if !exists {
+                failure(StatusCode::BAD_REQUEST, "Lead not found", "")
+            } else {
+                let note = json!({
+                    "id": state.next_id(),
+                    "content": body["content"],
+                    "lead_id": body["lead_id"],
+                });
+                state.notes.push(note.clone());
+                success(note)
+            }
+        }
+        _ => failure(StatusCode::NOT_FOUND, "Unknown method", &path),
+    };
+    Ok(resp)
+}
+
+fn issue_token(state: &mut MockState, authorization: &str, form: &str) -> Response<Body> {
+    let client_ok = match &state.oauth_client {
+        Some((id, secret)) => {
+            let credentials = format!("{}:{}", id, secret);
+            let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+            authorization == format!("Basic {}", encoded)
+        }
+        None => false,
+    };
+    let form = parse_query(Some(form));
+    let refresh_token = form.get("refresh_token").cloned().unwrap_or_default();
+    if !client_ok
+        || form.get("grant_type").map(|x| x.as_str()) != Some("refresh_token")
+        || !state.refresh_tokens.remove(&refresh_token)
+    {
+        return reply(
+            StatusCode::UNAUTHORIZED,
+            json!({ "success": false, "message": "Invalid grant", "error": "unauthorized" }),
+        );
+    }
+    // refresh tokens rotate like Pipedrive's, the used one is no longer valid
+    let access_token = uuid::Uuid::new_v4().to_string();
+    let refresh_token = uuid::Uuid::new_v4().to_string();
+    state.access_tokens.insert(access_token.clone());
+    state.refresh_tokens.insert(refresh_token.clone());
+    state.token_refreshes += 1;
+    reply(
+        StatusCode::OK,
+        json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "refresh_token": refresh_token,
+            "scope": "base,leads:full,contacts:full",
+            "expires_in": state.oauth_expires_in,
+            "api_domain": "https://mock.pipedrive.com",
+        }),
+    )
+}
@@ -0,0 +1,2064 @@
+mod common;
+
+use common::*;
+use eyre::*;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(20);
+
+fn unique_email() -> String {
+    format!("lead-{}@example.com", uuid::Uuid::new_v4())
+}
+
+fn add_crm_lead(email: &str) -> Value {
+    json!({
+        "email": email,
+        "username": "Jane Doe",
+        "title": "Enterprise plan",
+        "message": "Please get in touch",
+    })
+}
+
+fn person_id_of(mock: &PipeDriveMock, email: &str) -> Option<i64> {
+    mock.persons()
+        .into_iter()
+        .find(|p| p["primary_email"].as_str() == Some(email))
+        .and_then(|p| p["id"].as_i64())
+}
+
+fn leads_of(mock: &PipeDriveMock, email: &str) -> Vec<Value> {
+    let person_id = match person_id_of(mock, email) {
+        Some(id) => id,
+        None => return vec![],
+    };
+    mock.leads()
+        .into_iter()
+        .filter(|l| l["person_id"].as_i64() == Some(person_id))
+        .collect()
+}
+
+#[tokio::test]
+async fn add_crm_lead_is_delivered_to_pipedrive() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = unique_email();
+
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body, json!({}));
+
+    let leads = wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| !x.is_empty())
+    })
+    .await?;
+    assert_eq!(leads.len(), 1);
+    assert_eq!(leads[0]["title"], json!("Enterprise plan (Jane Doe)"));
+
+    let notes = wait_for(DELIVERY_TIMEOUT, || {
+        let notes: Vec<Value> = mock
+            .notes()
+            .into_iter()
+            .filter(|n| n["lead_id"] == leads[0]["id"])
+            .collect();
+        Some(notes).filter(|x| !x.is_empty())
+    })
+    .await?;
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0]["content"], json!("Please get in touch"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn message_is_escaped_and_title_uses_template() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "lead_delivery": {
+                "interval_secs": 1,
+                "title_template": "Website: {title} from {email}",
+            }
+        }),
+    )
+    .await?;
+    let email = unique_email();
+    let mut req = add_crm_lead(&email);
+    req["message"] = json!("<b>Hi</b> & \"bye\"\nsecond line");
+    // placeholders in submitted values are not expanded
+    req["title"] = json!("Enterprise plan {username}");
+
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let leads = wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| !x.is_empty())
+    })
+    .await?;
+    assert_eq!(
+        leads[0]["title"],
+        json!(format!(
+            "Website: Enterprise plan {{username}} from {}",
+            email
+        ))
+    );
+    let note = wait_for(DELIVERY_TIMEOUT, || {
+        mock.notes()
+            .into_iter()
+            .find(|n| n["lead_id"] == leads[0]["id"])
+    })
+    .await?;
+    assert_eq!(
+        note["content"],
+        json!("&lt;b&gt;Hi&lt;/b&gt; &amp; &quot;bye&quot;<br>second line")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn note_failure_does_not_duplicate_lead() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    mock.fail_notes(1);
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = unique_email();
+
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    wait_for(DELIVERY_TIMEOUT, || {
+        mock.notes()
+            .into_iter()
+            .find(|n| n["lead_id"] == lead["id"])
+    })
+    .await?;
+    assert_eq!(leads_of(&mock, &email).len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn existing_person_is_reused() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = unique_email();
+
+    for _ in 0..2 {
+        let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+        assert_eq!(status, 200, "{}", body);
+    }
+    wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| x.len() == 2)
+    })
+    .await?;
+    let persons = mock
+        .persons()
+        .into_iter()
+        .filter(|p| p["primary_email"].as_str() == Some(email.as_str()))
+        .count();
+    assert_eq!(persons, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_delivery_is_retried() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    mock.fail_leads(2);
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = unique_email();
+
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| x.len() == 1)
+    })
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_endpoints_reject_invalid_token() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+
+    for endpoint in [
+        "ListDeadLetterLeads",
+        "ReplayDeadLetterLeads",
+        "DiscardDeadLetterLeads",
+    ] {
+        let (status, body) = server
+            .call(endpoint, json!({ "adminToken": "wrong" }))
+            .await?;
+        assert_eq!(status, 403, "{}", endpoint);
+        assert_eq!(body["code"], json!(101403));
+        assert_eq!(body["symbol"], json!("UserForbidden"));
+        assert_eq!(body["message"], json!("Insufficient role for user"));
+        assert!(body["log_id"].as_str().map_or(false, |x| !x.is_empty()));
+    }
+
+    let (status, body) = server
+        .call("ListDeadLetterLeads", json!({ "adminToken": ADMIN_TOKEN }))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body["leads"].is_array());
+    Ok(())
+}
+
+/// Dead-lettered lead submitted with `email`, if any
+async fn dead_letter_of(server: &UserServer, email: &str) -> Result<Option<Value>> {
+    let (status, body) = server
+        .call("ListDeadLetterLeads", json!({ "adminToken": ADMIN_TOKEN }))
+        .await?;
+    ensure!(status == 200, "{}", body);
+    Ok(body["leads"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|x| x["email"].as_str() == Some(email))
+        .cloned())
+}
+
+async fn wait_for_dead_letter(server: &UserServer, email: &str) -> Result<Value> {
+    let deadline = std::time::Instant::now() + DELIVERY_TIMEOUT;
+    loop {
+        match dead_letter_of(server, email).await? {
+            Some(lead) => return Ok(lead),
+            None if std::time::Instant::now() > deadline => bail!("lead was not dead-lettered"),
+            None => tokio::time::sleep(Duration::from_millis(200)).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn dead_letter_leads_are_replayed_or_discarded() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({ "lead_delivery": { "interval_secs": 1, "max_attempts": 1 } }),
+    )
+    .await?;
+
+    mock.fail_leads(1);
+    let replayed = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&replayed)).await?;
+    assert_eq!(status, 200, "{}", body);
+    let lead = wait_for_dead_letter(&server, &replayed).await?;
+    let (status, body) = server
+        .call(
+            "ReplayDeadLetterLeads",
+            json!({ "adminToken": ADMIN_TOKEN, "leadId": lead["leadId"] }),
+        )
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["leadIds"], json!([lead["leadId"]]));
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &replayed).pop()).await?;
+    assert!(dead_letter_of(&server, &replayed).await?.is_none());
+
+    mock.fail_leads(1);
+    let discarded = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&discarded)).await?;
+    assert_eq!(status, 200, "{}", body);
+    let lead = wait_for_dead_letter(&server, &discarded).await?;
+    let (status, body) = server
+        .call(
+            "DiscardDeadLetterLeads",
+            json!({ "adminToken": ADMIN_TOKEN, "leadId": lead["leadId"] }),
+        )
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["leadIds"], json!([lead["leadId"]]));
+    assert!(dead_letter_of(&server, &discarded).await?.is_none());
+    // a discarded lead is never delivered
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(leads_of(&mock, &discarded).is_empty());
+    Ok(())
+}
+
+fn contact_form() -> Value {
+    json!({
+        "forms": {
+            "contact": {
+                "person_fields": { "company_size": COMPANY_SIZE_KEY },
+                "lead_fields": {
+                    "budget": BUDGET_KEY,
+                    "product_interest": PRODUCT_INTEREST_KEY,
+                },
+            }
+        }
+    })
+}
+
+#[tokio::test]
+async fn form_fields_are_mapped_to_custom_fields() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, contact_form()).await?;
+    let email = unique_email();
+    let mut req = add_crm_lead(&email);
+    req["form"] = json!("contact");
+    req["fields"] = json!([
+        { "name": "company_size", "value": "11-50" },
+        { "name": "budget", "value": "1500" },
+        { "name": "product_interest", "value": "gateway, Analytics" },
+        { "name": "newsletter", "value": "yes" },
+    ]);
+
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert_eq!(lead[BUDGET_KEY], json!(1500.0));
+    assert_eq!(lead[PRODUCT_INTEREST_KEY], json!("21,22"));
+    assert!(lead.get("newsletter").is_none());
+    let person = mock
+        .persons()
+        .into_iter()
+        .find(|p| p["primary_email"].as_str() == Some(email.as_str()))
+        .unwrap();
+    assert_eq!(person[COMPANY_SIZE_KEY], json!(12));
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_option_label_is_rejected() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, contact_form()).await?;
+    let mut req = add_crm_lead(&unique_email());
+    req["form"] = json!("contact");
+    req["fields"] = json!([{ "name": "company_size", "value": "a lot" }]);
+
+    let (status, _) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 400);
+
+    let mut req = add_crm_lead(&unique_email());
+    req["form"] = json!("unknown");
+    let (status, _) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_custom_field_key_fails_startup() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let config = json!({
+        "forms": { "contact": { "person_fields": { "company_size": "does-not-exist" } } }
+    });
+    assert!(UserServer::start(&mock, config).await.is_err());
+    Ok(())
+}
+
+fn organization_named(mock: &PipeDriveMock, name: &str) -> Vec<Value> {
+    mock.organizations()
+        .into_iter()
+        .filter(|o| o["name"].as_str() == Some(name))
+        .collect()
+}
+
+#[tokio::test]
+async fn lead_is_linked_to_submitted_company() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "forms": {
+                "default": { "organization_fields": { "industry": INDUSTRY_KEY } }
+            }
+        }),
+    )
+    .await?;
+    let company = format!("Acme {}", uuid::Uuid::new_v4());
+    for _ in 0..2 {
+        let email = format!("lead-{}@gmail.com", uuid::Uuid::new_v4());
+        let mut req = add_crm_lead(&email);
+        req["company"] = json!(company);
+        req["fields"] = json!([{ "name": "industry", "value": "Robotics" }]);
+        let (status, body) = server.call("AddCrmLead", req).await?;
+        assert_eq!(status, 200, "{}", body);
+
+        let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+        let organizations = organization_named(&mock, &company);
+        assert_eq!(organizations.len(), 1);
+        assert_eq!(organizations[0][INDUSTRY_KEY], json!("Robotics"));
+        assert_eq!(lead["organization_id"], organizations[0]["id"]);
+        let person = mock
+            .persons()
+            .into_iter()
+            .find(|p| p["primary_email"].as_str() == Some(email.as_str()))
+            .unwrap();
+        assert_eq!(person["org_id"]["value"], organizations[0]["id"]);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn corporate_email_domain_is_used_as_organization() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    let organizations = organization_named(&mock, "example.com");
+    assert_eq!(organizations.len(), 1);
+    assert_eq!(lead["organization_id"], organizations[0]["id"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn free_mail_lead_has_no_organization() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let email = format!("lead-{}@GMail.com", uuid::Uuid::new_v4());
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    // addresses reach Pipedrive in lower case
+    let email = email.to_lowercase();
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert!(lead.get("organization_id").is_none());
+    assert!(mock.organizations().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn rate_limited_calls_are_retried() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    mock.rate_limit(3);
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert!(mock.rejected() >= 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn exhausted_rate_limit_retries_report_error_code() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "pipedrive_rate_limit": { "max_retries": 1, "retry_base_ms": 10 },
+            "lead_delivery": { "interval_secs": 1, "max_attempts": 1 },
+        }),
+    )
+    .await?;
+    mock.rate_limit(usize::MAX);
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let lead = wait_for_dead_letter(&server, &email).await?;
+    assert_eq!(lead["error"], json!("Error code 101429"));
+    assert!(mock.rejected() >= 2);
+    Ok(())
+}
+
+fn oauth_config(mock: &PipeDriveMock, expires_in: u64) -> Value {
+    // tokens are stored per client id, so every test uses its own OAuth client
+    let client_id = uuid::Uuid::new_v4().to_string();
+    let refresh_token = mock.enable_oauth(&client_id, "secret", expires_in);
+    json!({
+        "pipedrive_api_token": "",
+        "pipedrive_oauth": {
+            "client_id": client_id,
+            "client_secret": "secret",
+            "refresh_token": refresh_token,
+            "token_url": mock.token_url(),
+        }
+    })
+}
+
+#[tokio::test]
+async fn oauth_bearer_token_is_used_instead_of_api_token() -> Result<()> {
+    let mock = PipeDriveMock::start("").await?;
+    let server = UserServer::start(&mock, oauth_config(&mock, 3600)).await?;
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    let (api_token_calls, bearer_calls) = mock.auth_calls();
+    assert_eq!(api_token_calls, 0);
+    assert!(bearer_calls > 0);
+    assert_eq!(mock.token_refreshes(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn oauth_token_is_refreshed_before_expiry_and_when_rejected() -> Result<()> {
+    let mock = PipeDriveMock::start("").await?;
+    // tokens expire within the refresh window, so every call refreshes first
+    let server = UserServer::start(&mock, oauth_config(&mock, 30)).await?;
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert!(mock.token_refreshes() > 2);
+    drop(server);
+
+    let refreshes = mock.token_refreshes();
+    let mock_config = oauth_config(&mock, 3600);
+    let server = UserServer::start(&mock, mock_config).await?;
+    mock.revoke_access_tokens();
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert_eq!(mock.token_refreshes(), refreshes + 2);
+    Ok(())
+}
+
+fn tenant_config(mock: &PipeDriveMock, form_key: &str) -> Value {
+    json!({
+        "form_key": form_key,
+        "pipedrive_api_token": mock.api_token,
+        "pipedrive_base_url": mock.base_url(),
+        "allowed_origins": ["https://www.example.com"],
+        "defaults": { "title_template": "{title} via {email}" },
+    })
+}
+
+fn tenant_lead(email: &str, form_key: &str) -> Value {
+    let mut req = add_crm_lead(email);
+    req["formKey"] = json!(form_key);
+    req
+}
+
+#[tokio::test]
+async fn form_key_routes_lead_to_tenant() -> Result<()> {
+    let default_mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let acme_mock = PipeDriveMock::start("acme-token").await?;
+    let globex_mock = PipeDriveMock::start("globex-token").await?;
+    let server = UserServer::start(
+        &default_mock,
+        json!({
+            "tenants": {
+                "acme": tenant_config(&acme_mock, "acme-form"),
+                "globex": tenant_config(&globex_mock, "globex-form"),
+            }
+        }),
+    )
+    .await?;
+
+    let acme_email = unique_email();
+    let (status, body) = server
+        .call("AddCrmLead", tenant_lead(&acme_email, "acme-form"))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    let globex_email = unique_email();
+    let (status, body) = server
+        .call("AddCrmLead", tenant_lead(&globex_email, "globex-form"))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&acme_mock, &acme_email).pop()).await?;
+    assert_eq!(
+        lead["title"],
+        json!(format!("Enterprise plan via {}", acme_email))
+    );
+    wait_for(DELIVERY_TIMEOUT, || {
+        leads_of(&globex_mock, &globex_email).pop()
+    })
+    .await?;
+    assert!(leads_of(&acme_mock, &globex_email).is_empty());
+    assert!(leads_of(&globex_mock, &acme_email).is_empty());
+    assert!(default_mock.leads().is_empty());
+
+    let (status, _) = server
+        .call("AddCrmLead", tenant_lead(&unique_email(), "unknown-form"))
+        .await?;
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_rejects_disallowed_origin() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({ "tenants": { "acme": tenant_config(&mock, "acme-form") } }),
+    )
+    .await?;
+
+    let (status, _) = server
+        .call_with_headers(
+            "AddCrmLead",
+            &[("Origin", "https://evil.example.net")],
+            tenant_lead(&unique_email(), "acme-form"),
+        )
+        .await?;
+    assert_eq!(status, 403);
+
+    let email = unique_email();
+    let (status, body) = server
+        .call_with_headers(
+            "AddCrmLead",
+            &[("Origin", "https://www.example.com")],
+            tenant_lead(&email, "acme-form"),
+        )
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_from_database_is_rebuilt_when_credentials_change() -> Result<()> {
+    let default_mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let mock = PipeDriveMock::start("db-tenant-token").await?;
+    let server = UserServer::start(&default_mock, json!({ "tenant_reload_secs": 1 })).await?;
+    let db = connect_app_db().await?;
+    let name = format!("tenant-{}", uuid::Uuid::new_v4());
+    let form_key = format!("form-{}", uuid::Uuid::new_v4());
+    let save_config = |api_token: &str| {
+        json!({
+            "pipedrive_api_token": api_token,
+            "pipedrive_base_url": mock.base_url(),
+        })
+        .to_string()
+    };
+    db.query(
+        "INSERT INTO tbl.tenant (name, form_key, config, updated_at) VALUES ($1, $2, $3, 0)",
+        &[&name, &form_key, &save_config("stale-token")],
+    )
+    .await?;
+
+    let email = unique_email();
+    let result = async {
+        // the tenant is picked up by the next reload
+        let deadline = std::time::Instant::now() + DELIVERY_TIMEOUT;
+        loop {
+            let (status, body) = server
+                .call("AddCrmLead", tenant_lead(&email, &form_key))
+                .await?;
+            if status == 200 {
+                break;
+            }
+            ensure!(std::time::Instant::now() < deadline, "{}", body);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        // deliveries fail until the credentials are fixed
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(leads_of(&mock, &email).is_empty());
+        db.query(
+            "UPDATE tbl.tenant SET config = $2 WHERE name = $1",
+            &[&name, &save_config("db-tenant-token")],
+        )
+        .await?;
+        wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+        Ok::<_, Report>(())
+    }
+    .await;
+    db.query("DELETE FROM tbl.tenant WHERE name = $1", &[&name])
+        .await?;
+    result
+}
+
+#[tokio::test]
+async fn tenant_from_database_cannot_reuse_a_form_key() -> Result<()> {
+    let default_mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let acme_mock = PipeDriveMock::start("acme-token").await?;
+    let db_mock = PipeDriveMock::start("db-tenant-token").await?;
+    let acme_form_key = format!("form-{}", uuid::Uuid::new_v4());
+    let server = UserServer::start(
+        &default_mock,
+        json!({
+            "tenant_reload_secs": 1,
+            "tenants": { "acme": tenant_config(&acme_mock, &acme_form_key) },
+        }),
+    )
+    .await?;
+    let db = connect_app_db().await?;
+    let config = json!({
+        "pipedrive_api_token": db_mock.api_token,
+        "pipedrive_base_url": db_mock.base_url(),
+    })
+    .to_string();
+    let thief = format!("tenant-{}", uuid::Uuid::new_v4());
+    let name = format!("tenant-{}", uuid::Uuid::new_v4());
+    let form_key = format!("form-{}", uuid::Uuid::new_v4());
+    for (name, form_key) in [(&thief, &acme_form_key), (&name, &form_key)] {
+        db.query(
+            "INSERT INTO tbl.tenant (name, form_key, config, updated_at) VALUES ($1, $2, $3, 0)",
+            &[name, form_key, &config],
+        )
+        .await?;
+    }
+
+    let result = async {
+        // once the valid tenant is routed the conflicting one has been seen as well
+        let deadline = std::time::Instant::now() + DELIVERY_TIMEOUT;
+        loop {
+            let (status, body) = server
+                .call("AddCrmLead", tenant_lead(&unique_email(), &form_key))
+                .await?;
+            if status == 200 {
+                break;
+            }
+            ensure!(std::time::Instant::now() < deadline, "{}", body);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        for _ in 0..5 {
+            let email = unique_email();
+            let (status, body) = server
+                .call("AddCrmLead", tenant_lead(&email, &acme_form_key))
+                .await?;
+            assert_eq!(status, 200, "{}", body);
+            wait_for(DELIVERY_TIMEOUT, || leads_of(&acme_mock, &email).pop()).await?;
+            assert!(leads_of(&db_mock, &email).is_empty());
+        }
+        Ok::<_, Report>(())
+    }
+    .await;
+    for name in [&thief, &name] {
+        db.query("DELETE FROM tbl.tenant WHERE name = $1", &[name])
+            .await?;
+    }
+    result
+}
+
+const WEBHOOK_USERNAME: &str = "pipedrive";
+const WEBHOOK_PASSWORD: &str = "webhook-secret";
+
+fn basic_auth(username: &str, password: &str) -> String {
+    use base64::Engine;
+    let credentials = format!("{}:{}", username, password);
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+}
+
+/// Pipedrive state of the local lead submitted with `email`
+async fn pipedrive_state_of(
+    db: &lib::database::SimpleDbClient,
+    email: &str,
+) -> Result<(Option<String>, Option<i64>)> {
+    let rows = db
+        .query(
+            "SELECT pipedrive_status::varchar, pipedrive_deal_id FROM tbl.lead WHERE email = $1",
+            &[&email],
+        )
+        .await?;
+    let row = rows.first().context("lead not found")?;
+    Ok((row.get(0), row.get(1)))
+}
+
+/// Deals of every run land in the default tenant of the shared database
+fn unique_deal_id() -> i64 {
+    (uuid::Uuid::new_v4().as_u128() % 1_000_000_000) as i64
+}
+
+#[tokio::test]
+async fn pipedrive_webhooks_update_lead_state() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "pipedrive_webhook": {
+                "username": WEBHOOK_USERNAME,
+                "password": WEBHOOK_PASSWORD,
+            }
+        }),
+    )
+    .await?;
+    let db = connect_app_db().await?;
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    wait_for(DELIVERY_TIMEOUT, || {
+        mock.notes()
+            .into_iter()
+            .find(|n| n["lead_id"] == lead["id"])
+    })
+    .await?;
+    let person_id = lead["person_id"].as_i64().unwrap();
+
+    let archived = json!({
+        "v": 1,
+        "event": "updated.lead",
+        "meta": { "action": "updated", "object": "lead", "id": lead["id"] },
+        "current": { "id": lead["id"], "is_archived": true },
+        "previous": { "id": lead["id"], "is_archived": false },
+    });
+    let resp = reqwest::Client::new()
+        .post(server.url("PipedriveWebhook"))
+        .json(&archived)
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 401);
+    assert_eq!(
+        resp.headers()
+            .get("www-authenticate")
+            .and_then(|x| x.to_str().ok()),
+        Some("Basic")
+    );
+    let body: Value = resp.json().await?;
+    assert_eq!(body["code"], json!(101401));
+    assert_eq!(body["symbol"], json!("Unauthorized"));
+    let wrong_auth = basic_auth(WEBHOOK_USERNAME, "wrong");
+    let (status, body) = server
+        .call_with_headers(
+            "PipedriveWebhook",
+            &[("Authorization", &wrong_auth)],
+            archived.clone(),
+        )
+        .await?;
+    assert_eq!(status, 401);
+    assert_eq!(body["code"], json!(101401));
+
+    let auth = basic_auth(WEBHOOK_USERNAME, WEBHOOK_PASSWORD);
+    let headers = [("Authorization", auth.as_str())];
+    let webhook = |payload: Value| server.call_with_headers("PipedriveWebhook", &headers, payload);
+    let (status, body) = webhook(archived).await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        pipedrive_state_of(&db, &email).await?,
+        (Some("lead_archived".to_owned()), None)
+    );
+
+    // v2 payload of the deal the lead was converted to
+    let deal_id = unique_deal_id();
+    let (status, body) = webhook(json!({
+        "meta": { "action": "create", "entity": "deal", "entity_id": deal_id.to_string(), "version": "2.0" },
+        "data": { "id": deal_id, "person_id": person_id, "status": "open" },
+        "previous": null,
+    }))
+    .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        pipedrive_state_of(&db, &email).await?,
+        (Some("deal_open".to_owned()), Some(deal_id))
+    );
+
+    let (status, body) = webhook(json!({
+        "v": 1,
+        "event": "updated.deal",
+        "meta": { "action": "updated", "object": "deal", "id": deal_id },
+        "current": { "id": deal_id, "person_id": person_id, "status": "won" },
+        "previous": { "id": deal_id, "person_id": person_id, "status": "open" },
+    }))
+    .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        pipedrive_state_of(&db, &email).await?,
+        (Some("deal_won".to_owned()), Some(deal_id))
+    );
+
+    let events = db
+        .query(
+            "SELECT action, object FROM tbl.pipedrive_webhook_event WHERE payload LIKE $1 ORDER BY pkey_id",
+            &[&format!("%{}%", lead["id"].as_str().unwrap())],
+        )
+        .await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].get::<_, String>(0), "updated");
+    assert_eq!(events[0].get::<_, String>(1), "lead");
+    Ok(())
+}
+
+#[tokio::test]
+async fn deal_is_linked_to_one_lead_of_the_person() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "pipedrive_webhook": {
+                "username": WEBHOOK_USERNAME,
+                "password": WEBHOOK_PASSWORD,
+            }
+        }),
+    )
+    .await?;
+    let db = connect_app_db().await?;
+    let email = unique_email();
+    let mut leads = vec![];
+    for _ in 0..2 {
+        let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+        assert_eq!(status, 200, "{}", body);
+        let count = leads.len();
+        leads = wait_for(DELIVERY_TIMEOUT, || {
+            Some(leads_of(&mock, &email)).filter(|x| x.len() > count)
+        })
+        .await?;
+    }
+    assert_eq!(leads[0]["person_id"], leads[1]["person_id"]);
+    let person_id = leads[0]["person_id"].as_i64().unwrap();
+    let states = || async {
+        let rows = db
+            .query(
+                "SELECT pipedrive_lead_id, pipedrive_status::varchar, pipedrive_deal_id \
+                 FROM tbl.lead WHERE email = $1 ORDER BY pkey_id",
+                &[&email],
+            )
+            .await?;
+        Ok::<_, Error>(
+            rows.iter()
+                .map(|x| {
+                    (
+                        x.get::<_, Option<String>>(0),
+                        x.get::<_, Option<String>>(1),
+                        x.get::<_, Option<i64>>(2),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+    // the outbox records the Pipedrive ids once the lead is fully delivered
+    let deadline = std::time::Instant::now() + DELIVERY_TIMEOUT;
+    let lead_ids = loop {
+        let states = states().await?;
+        if states.iter().all(|x| x.0.is_some()) {
+            break states;
+        }
+        ensure!(std::time::Instant::now() < deadline, "{:?}", states);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    let (older, newer) = (lead_ids[0].0.clone(), lead_ids[1].0.clone());
+
+    let auth = basic_auth(WEBHOOK_USERNAME, WEBHOOK_PASSWORD);
+    let headers = [("Authorization", auth.as_str())];
+    let webhook = |payload: Value| server.call_with_headers("PipedriveWebhook", &headers, payload);
+    // a deal nothing refers to takes only the most recent lead of the person
+    let deal_id = unique_deal_id();
+    let (status, body) = webhook(json!({
+        "v": 1,
+        "event": "added.deal",
+        "meta": { "action": "added", "object": "deal", "id": deal_id },
+        "current": { "id": deal_id, "person_id": person_id, "status": "open" },
+        "previous": null,
+    }))
+    .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        states().await?,
+        vec![
+            (older.clone(), None, None),
+            (newer.clone(), Some("deal_open".to_owned()), Some(deal_id)),
+        ]
+    );
+
+    // the conversion of a lead names its deal, which later deal events then update
+    let converted_id = unique_deal_id();
+    let (status, body) = webhook(json!({
+        "v": 1,
+        "event": "deleted.lead",
+        "meta": { "action": "deleted", "object": "lead", "id": older },
+        "current": null,
+        "previous": { "id": older, "person_id": person_id, "deal_id": converted_id },
+    }))
+    .await?;
+    assert_eq!(status, 200, "{}", body);
+    let (status, body) = webhook(json!({
+        "v": 1,
+        "event": "updated.deal",
+        "meta": { "action": "updated", "object": "deal", "id": converted_id },
+        "current": { "id": converted_id, "person_id": person_id, "status": "lost" },
+        "previous": { "id": converted_id, "person_id": person_id, "status": "open" },
+    }))
+    .await?;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        states().await?,
+        vec![
+            (older, Some("deal_lost".to_owned()), Some(converted_id)),
+            (newer, Some("deal_open".to_owned()), Some(deal_id)),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn cors_preflight_and_origin_allowlist() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "cors": {
+                "allowed_origins": ["https://www.example.com"],
+                "max_age_secs": 120,
+            }
+        }),
+    )
+    .await?;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .request(reqwest::Method::OPTIONS, server.url("AddCrmLead"))
+        .header("Origin", "https://www.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 204);
+    let header = |resp: &reqwest::Response, name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_owned())
+    };
+    assert_eq!(
+        header(&resp, "access-control-allow-origin").as_deref(),
+        Some("https://www.example.com")
+    );
+    assert_eq!(
+        header(&resp, "access-control-allow-methods").as_deref(),
+        Some("POST, OPTIONS")
+    );
+    assert_eq!(
+        header(&resp, "access-control-max-age").as_deref(),
+        Some("120")
+    );
+
+    let resp = client
+        .request(reqwest::Method::OPTIONS, server.url("AddCrmLead"))
+        .header("Origin", "https://evil.example.net")
+        .header("Access-Control-Request-Method", "POST")
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 403);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+    assert_eq!(header(&resp, "vary").as_deref(), Some("Origin"));
+    let body: Value = resp.json().await?;
+    assert_eq!(body["code"], json!(100403));
+    assert_eq!(body["symbol"], json!("OriginNotAllowed"));
+    assert_eq!(
+        body["message"],
+        json!("Origin https://evil.example.net is not allowed")
+    );
+
+    let email = unique_email();
+    let resp = client
+        .post(server.url("AddCrmLead"))
+        .header("Origin", "https://evil.example.net")
+        .json(&add_crm_lead(&email))
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let resp = client
+        .post(server.url("AddCrmLead"))
+        .header("Origin", "https://www.example.com")
+        .json(&add_crm_lead(&email))
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        header(&resp, "access-control-allow-origin").as_deref(),
+        Some("https://www.example.com")
+    );
+    assert_eq!(header(&resp, "vary").as_deref(), Some("Origin"));
+
+    // server to server calls carry no Origin
+    let resp = client
+        .post(server.url("AddCrmLead"))
+        .json(&add_crm_lead(&unique_email()))
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(header(&resp, "access-control-allow-origin"), None);
+    assert_eq!(header(&resp, "vary").as_deref(), Some("Origin"));
+
+    // the rejected submission never reached the outbox
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(leads_of(&mock, &email).len(), 1);
+    Ok(())
+}
+
+fn form_post_config() -> Value {
+    json!({
+        "form_redirects": {
+            "AddCrmLead": {
+                "success_url": "https://www.example.com/thanks",
+                "error_url": "https://www.example.com/oops?form=contact",
+            }
+        }
+    })
+}
+
+fn no_redirect_client() -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?)
+}
+
+fn location(resp: &reqwest::Response) -> String {
+    resp.headers()
+        .get("location")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn urlencoded_form_post_redirects_to_thank_you_page() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, form_post_config()).await?;
+    let client = no_redirect_client()?;
+    let email = unique_email();
+
+    let resp = client
+        .post(server.url("AddCrmLead"))
+        .form(&[
+            ("email", email.as_str()),
+            ("username", "Jane Doe"),
+            ("title", "Enterprise plan"),
+            ("message", "Please get in touch"),
+            ("company", ""),
+        ])
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(location(&resp), "https://www.example.com/thanks");
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+
+    let resp = client
+        .post(server.url("AddCrmLead"))
+        .form(&[("email", unique_email().as_str()), ("formKey", "unknown")])
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 303);
+    assert!(
+        location(&resp)
+            .starts_with("https://www.example.com/oops?form=contact&code=100400&log_id="),
+        "{}",
+        location(&resp)
+    );
+
+    // JSON clients are not redirected
+    let (status, body) = server
+        .call("AddCrmLead", add_crm_lead(&unique_email()))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn multipart_form_post_fills_custom_fields() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, contact_form()).await?;
+    let email = unique_email();
+    let boundary = "gateway-test-boundary";
+    let parts = [
+        ("email", email.as_str()),
+        ("username", "Jane Doe"),
+        ("title", "Enterprise plan"),
+        ("message", "Please get in touch"),
+        ("form", "contact"),
+        ("fields[0][name]", "company_size"),
+        ("fields[0][value]", "11-50"),
+        ("fields[1][name]", "budget"),
+        ("fields[1][value]", "1200.5"),
+    ];
+    let mut body = String::new();
+    for (name, value) in parts {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary, name, value
+        ));
+    }
+    body.push_str(&format!("--{}--\r\n", boundary));
+
+    let resp = no_redirect_client()?
+        .post(server.url("AddCrmLead"))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body)
+        .send()
+        .await?;
+    // no redirect configured, the JSON response is returned as usual
+    assert_eq!(resp.status().as_u16(), 200, "{}", resp.text().await?);
+
+    let lead = wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    assert_eq!(lead[BUDGET_KEY], json!(1200.5));
+    let person = mock
+        .persons()
+        .into_iter()
+        .find(|p| p["primary_email"].as_str() == Some(email.as_str()))
+        .unwrap();
+    assert_eq!(person[COMPANY_SIZE_KEY], json!(12));
+    Ok(())
+}
+
+#[tokio::test]
+async fn errors_use_http_status_and_json_envelope() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+
+    let (status, body) = server
+        .call("AddCrmLead", json!({ "email": unique_email() }))
+        .await?;
+    assert_eq!(status, 400);
+    assert_eq!(body["code"], json!(100400));
+    assert_eq!(body["symbol"], json!("BadRequest"));
+    assert!(body["params"].is_string(), "{}", body);
+
+    let mut req = add_crm_lead(&unique_email());
+    req["formKey"] = json!("unknown");
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 400);
+    assert_eq!(body["symbol"], json!("UnknownFormKey"));
+    assert_eq!(body["params"], json!({ "formKey": "unknown" }));
+    assert_eq!(body["message"], json!("Unknown form key unknown"));
+
+    let (status, body) = server.call("NoSuchEndpoint", json!({})).await?;
+    assert_eq!(status, 404);
+    assert_eq!(body["symbol"], json!("NotFound"));
+    assert_eq!(body["params"], json!("Endpoint NoSuchEndpoint not found"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn error_messages_follow_accept_language() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let mut req = add_crm_lead(&unique_email());
+    req["formKey"] = json!("unknown");
+
+    let (status, body) = server
+        .call_with_headers(
+            "AddCrmLead",
+            &[("Accept-Language", "fr-CH, de-AT;q=0.9, es;q=0.8")],
+            req.clone(),
+        )
+        .await?;
+    assert_eq!(status, 400);
+    assert_eq!(
+        body["message"],
+        json!("Unbekannter Formularschlüssel unknown")
+    );
+
+    let (_, body) = server
+        .call_with_headers(
+            "AddCrmLead?lang=es",
+            &[("Accept-Language", "de")],
+            req.clone(),
+        )
+        .await?;
+    assert_eq!(
+        body["message"],
+        json!("Clave de formulario desconocida unknown")
+    );
+
+    // languages without a catalog fall back to English
+    let (_, body) = server
+        .call_with_headers("AddCrmLead", &[("Accept-Language", "fr, *;q=0.1")], req)
+        .await?;
+    assert_eq!(body["message"], json!("Unknown form key unknown"));
+    assert_eq!(body["symbol"], json!("UnknownFormKey"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn handlers_time_out_with_temporarily_unavailable() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            // no handler touching the database can finish in time
+            "handler_timeout_secs": 0,
+            "pipedrive_webhook": {
+                "username": WEBHOOK_USERNAME,
+                "password": WEBHOOK_PASSWORD,
+            }
+        }),
+    )
+    .await?;
+
+    let (status, body) = server
+        .call("ListDeadLetterLeads", json!({ "adminToken": ADMIN_TOKEN }))
+        .await?;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(body["symbol"], json!("TemporarilyUnavailable"));
+    assert!(body["log_id"].as_str().map_or(false, |x| !x.is_empty()));
+
+    // the webhook endpoint sets its own deadline
+    let auth = basic_auth(WEBHOOK_USERNAME, WEBHOOK_PASSWORD);
+    let (status, body) = server
+        .call_with_headers(
+            "PipedriveWebhook",
+            &[("Authorization", &auth)],
+            json!({
+                "meta": { "action": "updated", "object": "lead", "id": uuid::Uuid::new_v4() },
+                "current": { "is_archived": false },
+            }),
+        )
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn pipedrive_events_are_streamed_as_server_sent_events() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "pipedrive_webhook": {
+                "username": WEBHOOK_USERNAME,
+                "password": WEBHOOK_PASSWORD,
+            }
+        }),
+    )
+    .await?;
+    let subscribe = json!({ "adminToken": ADMIN_TOKEN, "limit": 1 });
+
+    // without an event stream the events would have nowhere to go
+    let (status, body) = server
+        .call("SubscribePipedriveEvents", subscribe.clone())
+        .await?;
+    assert_eq!(status, 400, "{}", body);
+
+    let mut resp = reqwest::Client::new()
+        .post(server.url("SubscribePipedriveEvents"))
+        .header("Accept", "text/event-stream")
+        .json(&subscribe)
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.headers()
+            .get("content-type")
+            .and_then(|x| x.to_str().ok()),
+        Some("text/event-stream")
+    );
+
+    let lead_id = uuid::Uuid::new_v4().to_string();
+    let auth = basic_auth(WEBHOOK_USERNAME, WEBHOOK_PASSWORD);
+    let (status, body) = server
+        .call_with_headers(
+            "PipedriveWebhook",
+            &[("Authorization", &auth)],
+            json!({
+                "meta": { "action": "updated", "object": "lead", "id": lead_id },
+                "current": { "is_archived": true },
+            }),
+        )
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+
+    // the stream ends by itself after `limit` events
+    let mut events = String::new();
+    tokio::time::timeout(DELIVERY_TIMEOUT, async {
+        while let Some(chunk) = resp.chunk().await? {
+            events.push_str(std::str::from_utf8(&chunk)?);
+        }
+        Ok::<_, Report>(())
+    })
+    .await??;
+    let blocks: Vec<&str> = events.split_terminator("\n\n").collect();
+    assert_eq!(blocks.len(), 2, "{}", events);
+    let event = blocks[0]
+        .strip_prefix("id: 0\nevent: stream\ndata: ")
+        .context(events.clone())?;
+    let event: Value = serde_json::from_str(event)?;
+    assert_eq!(event["resource"], json!("default"));
+    assert_eq!(event["data"]["object"], json!("lead"));
+    assert_eq!(event["data"]["objectId"], json!(lead_id));
+    assert_eq!(event["data"]["action"], json!("updated"));
+    assert_eq!(blocks[1], "event: response\ndata: {}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn pipedrive_events_are_streamed_to_websocket_clients() -> Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "pipedrive_webhook": {
+                "username": WEBHOOK_USERNAME,
+                "password": WEBHOOK_PASSWORD,
+            }
+        }),
+    )
+    .await?;
+    let url = server.url("").replacen("http://", "ws://", 1);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await?;
+    let req = json!({
+        "method": 20665, // SubscribePipedriveEvents
+        "seq": 1,
+        "params": { "adminToken": ADMIN_TOKEN, "limit": 1 },
+    });
+    ws.send(Message::Text(req.to_string())).await?;
+
+    // the subscription is in place once the event reaches the stream, so keep sending
+    let lead_id = uuid::Uuid::new_v4().to_string();
+    let auth = basic_auth(WEBHOOK_USERNAME, WEBHOOK_PASSWORD);
+    let mut messages = vec![];
+    tokio::time::timeout(DELIVERY_TIMEOUT, async {
+        loop {
+            let (status, body) = server
+                .call_with_headers(
+                    "PipedriveWebhook",
+                    &[("Authorization", &auth)],
+                    json!({
+                        "meta": { "action": "updated", "object": "lead", "id": lead_id },
+                        "current": { "is_archived": true },
+                    }),
+                )
+                .await?;
+            assert_eq!(status, 200, "{}", body);
+            let msg = tokio::time::timeout(Duration::from_millis(500), ws.next()).await;
+            if let Ok(msg) = msg {
+                if let Message::Text(text) = msg.context("websocket closed")?? {
+                    messages.push(serde_json::from_str::<Value>(&text)?);
+                    break;
+                }
+            }
+        }
+        // the response follows the last event of the stream
+        while messages.len() < 2 {
+            if let Message::Text(text) = ws.next().await.context("websocket closed")?? {
+                messages.push(serde_json::from_str::<Value>(&text)?);
+            }
+        }
+        Ok::<_, Report>(())
+    })
+    .await??;
+    assert_eq!(messages[0]["type"], json!("Stream"), "{}", messages[0]);
+    assert_eq!(messages[0]["resource"], json!("default"));
+    assert_eq!(messages[0]["data"]["objectId"], json!(lead_id));
+    assert_eq!(messages[1]["type"], json!("Immediate"), "{}", messages[1]);
+    assert_eq!(messages[1]["seq"], json!(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn health_readiness_and_metrics_are_served() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({ "pipedrive_readiness_probe": true })).await?;
+    let client = reqwest::Client::new();
+
+    let resp = client.get(server.url("healthz")).send().await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await?, "ok");
+
+    let resp = client.get(server.url("readyz")).send().await?;
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await?;
+    assert_eq!(body["ready"], json!(true), "{}", body);
+    assert_eq!(body["checks"]["database_0"], json!("ok"));
+    assert_eq!(body["checks"]["pipedrive"], json!("ok"));
+
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| !x.is_empty())
+    })
+    .await?;
+    let (status, _) = server.call("NoSuchEndpoint", json!({})).await?;
+    assert_eq!(status, 404);
+
+    let resp = client.get(server.url("metrics")).send().await?;
+    assert_eq!(resp.status(), 200);
+    let metrics = resp.text().await?;
+    assert!(
+        metrics.contains(r#"http_requests_total{endpoint="AddCrmLead",status="200"} 1"#),
+        "{}",
+        metrics
+    );
+    assert!(metrics.contains(r#"http_request_duration_seconds_count{endpoint="AddCrmLead"} 1"#));
+    assert!(metrics.contains(r#"http_errors_total{code="100404",symbol="NotFound"} 1"#));
+    assert!(metrics
+        .contains(r#"pipedrive_requests_total{method="POST",resource="leads",status="200"}"#));
+    assert!(metrics.contains(
+        r#"pipedrive_request_duration_seconds_bucket{method="GET",resource="users",le="+Inf"}"#
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_clients_share_the_http_port_and_endpoints() -> Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(&mock, json!({})).await?;
+    let url = server.url("").replacen("http://", "ws://", 1);
+    let (mut ws, resp) = tokio_tungstenite::connect_async(url).await?;
+    assert_eq!(resp.status(), 101);
+
+    for (seq, admin_token) in [(1, "wrong"), (2, ADMIN_TOKEN)] {
+        let req = json!({
+            "method": 20661, // ListDeadLetterLeads
+            "seq": seq,
+            "params": { "adminToken": admin_token },
+        });
+        ws.send(Message::Text(req.to_string())).await?;
+    }
+    let mut responses = vec![];
+    while responses.len() < 2 {
+        let msg = tokio::time::timeout(Duration::from_secs(10), ws.next())
+            .await?
+            .context("websocket closed")??;
+        if let Message::Text(text) = msg {
+            responses.push(serde_json::from_str::<Value>(&text)?);
+        }
+    }
+    responses.sort_by_key(|x| x["seq"].as_u64());
+    assert_eq!(responses[0]["type"], json!("Error"), "{}", responses[0]);
+    assert_eq!(responses[0]["code"], json!(101403));
+    assert_eq!(responses[0]["message"], json!("Insufficient role for user"));
+    assert_eq!(responses[1]["type"], json!("Immediate"), "{}", responses[1]);
+    assert!(responses[1]["params"]["leads"].is_array());
+
+    // plain requests on the same port still reach the endpoints by name
+    let (status, body) = server
+        .call("ListDeadLetterLeads", json!({ "adminToken": ADMIN_TOKEN }))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+    Ok(())
+}
+
+#[test]
+fn client_ip_is_only_taken_from_trusted_proxies() -> Result<()> {
+    use lib::http::client_ip;
+    use std::net::IpAddr;
+
+    let config: lib::config::AppConfig<Value> = serde_json::from_value(json!({
+        "trusted_proxies": ["10.0.0.0/8", "2001:db8::/32"],
+    }))?;
+    let trusted = &config.trusted_proxies;
+    let ip = |x: &str| x.parse::<IpAddr>().unwrap();
+    let headers = |pairs: &[(&'static str, &str)]| {
+        let mut headers = hyper::HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    };
+
+    // anyone else may claim any address
+    let spoofed = headers(&[("x-forwarded-for", "198.51.100.7")]);
+    assert_eq!(
+        client_ip(ip("203.0.113.9"), &spoofed, trusted),
+        ip("203.0.113.9")
+    );
+    assert_eq!(
+        client_ip(ip("10.0.0.2"), &spoofed, trusted),
+        ip("198.51.100.7")
+    );
+
+    // hops prepended by the client are skipped, the chain is followed through proxies only
+    let chain = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.1.2.3")]);
+    assert_eq!(
+        client_ip(ip("10.0.0.2"), &chain, trusted),
+        ip("198.51.100.7")
+    );
+    let repeated = headers(&[
+        ("x-forwarded-for", "1.1.1.1"),
+        ("x-forwarded-for", "198.51.100.7:5000"),
+    ]);
+    assert_eq!(
+        client_ip(ip("10.0.0.2"), &repeated, trusted),
+        ip("198.51.100.7")
+    );
+
+    let forwarded = headers(&[(
+        "forwarded",
+        r#"for=1.1.1.1, for="[2001:db8:cafe::17]:4711";proto=https, For=2001:db8::1"#,
+    )]);
+    assert_eq!(
+        client_ip(ip("10.0.0.2"), &forwarded, trusted),
+        ip("1.1.1.1")
+    );
+    let hidden = headers(&[("forwarded", "for=198.51.100.7, for=_hidden, for=10.1.2.3")]);
+    assert_eq!(client_ip(ip("10.0.0.2"), &hidden, trusted), ip("10.1.2.3"));
+
+    // without forwarding headers the proxy itself is the client
+    assert_eq!(
+        client_ip(ip("10.0.0.2"), &headers(&[]), trusted),
+        ip("10.0.0.2")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn proxy_protocol_headers_are_decoded() -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "proxy_protocol": true,
+            "rate_limits": { "AddCrmLead": { "requests_per_minute": 1, "burst": 1 } },
+        }),
+    )
+    .await?;
+    let addr = server.addr;
+    // every lead is sent through a local connection, only the PROXY header tells them apart
+    let send = |header: Vec<u8>| async move {
+        let body = add_crm_lead(&unique_email()).to_string();
+        let request = format!(
+            "POST /AddCrmLead HTTP/1.1\r\nHost: gateway\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.write_all(&header).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            stream.read_to_string(&mut response),
+        )
+        .await??;
+        Ok::<_, Error>(response)
+    };
+
+    let v1 = b"PROXY TCP4 198.51.100.7 10.0.0.1 51000 443\r\n".to_vec();
+    let response = send(v1).await?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // the budget of 198.51.100.7 is spent, whichever header version names it
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    v2.extend_from_slice(&[198, 51, 100, 7, 10, 0, 0, 1, 0xc7, 0x38, 0x01, 0xbb]);
+    let response = send(v2).await?;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+    let other = b"PROXY TCP4 198.51.100.8 10.0.0.1 51000 443\r\n".to_vec();
+    let response = send(other).await?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // a connection that bypassed the load balancer is dropped, possibly with a reset
+    let response = send(vec![]).await.unwrap_or_default();
+    assert_eq!(response, "");
+    Ok(())
+}
+
+#[tokio::test]
+async fn sigterm_drains_connections_and_flushes_queued_leads() -> Result<()> {
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let mut server = UserServer::start(
+        &mock,
+        json!({
+            // leads are only delivered by the last run of the outbox on shutdown
+            "lead_delivery": { "interval_secs": 3600, "batch_size": 1000 },
+            "shutdown_grace_secs": 10,
+        }),
+    )
+    .await?;
+    let url = server.url("").replacen("http://", "ws://", 1);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await?;
+
+    let email = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&email)).await?;
+    assert_eq!(status, 200, "{}", body);
+    assert!(leads_of(&mock, &email).is_empty());
+
+    let status = server.terminate(Duration::from_secs(30)).await?;
+    assert!(status.success(), "{}", status);
+    assert_eq!(leads_of(&mock, &email).len(), 1);
+
+    // websocket clients are told the server is going away
+    let msg = tokio::time::timeout(Duration::from_secs(5), ws.next()).await?;
+    assert!(
+        matches!(msg, Some(Ok(Message::Close(_))) | None),
+        "{:?}",
+        msg
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_are_rate_limited_per_client_and_form_key() -> Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let form_key = format!("form-{}", uuid::Uuid::new_v4());
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "rate_limits": { "AddCrmLead": { "requests_per_minute": 1, "burst": 2 } },
+            "tenants": { "acme": tenant_config(&mock, &form_key) },
+        }),
+    )
+    .await?;
+
+    for _ in 0..2 {
+        let (status, body) = server
+            .call("AddCrmLead", add_crm_lead(&unique_email()))
+            .await?;
+        assert_eq!(status, 200, "{}", body);
+    }
+    let resp = reqwest::Client::new()
+        .post(server.url("AddCrmLead"))
+        .json(&add_crm_lead(&unique_email()))
+        .send()
+        .await?;
+    assert_eq!(resp.status().as_u16(), 503);
+    let retry_after: u64 = resp.headers()["retry-after"].to_str()?.parse()?;
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    let body: Value = resp.json().await?;
+    assert_eq!(body["code"], json!(45349645), "{}", body);
+    assert_eq!(body["symbol"], json!("BackPressureIncreased"));
+
+    // made up form keys draw from the budget of the client IP
+    let (status, body) = server
+        .call(
+            "AddCrmLead",
+            tenant_lead(&unique_email(), &uuid::Uuid::new_v4().to_string()),
+        )
+        .await?;
+    assert_eq!(status, 503, "{}", body);
+
+    // each form key of a client has its own budget
+    let (status, body) = server
+        .call("AddCrmLead", tenant_lead(&unique_email(), &form_key))
+        .await?;
+    assert_eq!(status, 200, "{}", body);
+
+    // websocket requests draw from the same budget
+    let url = server.url("").replacen("http://", "ws://", 1);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await?;
+    let req = json!({
+        "method": 20660, // AddCrmLead
+        "seq": 1,
+        "params": add_crm_lead(&unique_email()),
+    });
+    ws.send(Message::Text(req.to_string())).await?;
+    let resp = loop {
+        let msg = tokio::time::timeout(Duration::from_secs(10), ws.next())
+            .await?
+            .context("websocket closed")??;
+        if let Message::Text(text) = msg {
+            break serde_json::from_str::<Value>(&text)?;
+        }
+    };
+    assert_eq!(resp["type"], json!("Error"), "{}", resp);
+    assert_eq!(resp["code"], json!(45349645));
+    assert!(
+        resp["params"]["retry_after_secs"].as_u64() >= Some(1),
+        "{}",
+        resp
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn spam_submissions_are_quarantined_instead_of_delivered() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "rate_limits": { "AddCrmLead": { "requests_per_minute": 0, "burst": 0 } },
+            "spam_protection": {
+                "form_token_secret": "test-form-token-secret",
+                "min_fill_secs": 1,
+                "action": "quarantine",
+            }
+        }),
+    )
+    .await?;
+    let db = connect_app_db().await?;
+    let issue_token = || async {
+        let resp = reqwest::get(server.url("IssueFormToken")).await?;
+        ensure!(
+            resp.status() == 200,
+            "IssueFormToken answered {}",
+            resp.status()
+        );
+        let body: Value = resp.json().await?;
+        Ok::<_, Error>(body["formToken"].as_str().unwrap_or_default().to_owned())
+    };
+
+    let token = issue_token().await?;
+    let honeypot = unique_email();
+    let mut req = add_crm_lead(&honeypot);
+    req["formToken"] = json!(token);
+    req["website"] = json!("https://spam.example.com");
+    let missing = unique_email();
+    let too_fast = unique_email();
+    let mut too_fast_req = add_crm_lead(&too_fast);
+    too_fast_req["formToken"] = json!(token);
+    let forged = unique_email();
+    let mut forged_req = add_crm_lead(&forged);
+    forged_req["formToken"] = json!(format!("{}0", &token[..token.len() - 1]));
+    for req in [req, add_crm_lead(&missing), too_fast_req, forged_req] {
+        // bots are answered like people
+        let (status, body) = server.call("AddCrmLead", req).await?;
+        assert_eq!(status, 200, "{}", body);
+    }
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let email = unique_email();
+    let mut req = add_crm_lead(&email);
+    req["formToken"] = json!(token);
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+
+    // a token is spent by the submission that was accepted with it
+    let replayed = unique_email();
+    let mut req = add_crm_lead(&replayed);
+    req["formToken"] = json!(token);
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    for (email, reason) in [
+        (&honeypot, "honeypot"),
+        (&missing, "missing_token"),
+        (&too_fast, "too_fast"),
+        (&forged, "invalid_token"),
+        (&replayed, "replayed_token"),
+    ] {
+        assert!(leads_of(&mock, email).is_empty(), "{}", reason);
+        let rows = db
+            .query(
+                "SELECT reason, client_ip FROM tbl.quarantined_lead WHERE email = $1",
+                &[email],
+            )
+            .await?;
+        assert_eq!(rows.len(), 1, "{}", reason);
+        assert_eq!(rows[0].get::<_, String>(0), reason);
+        assert_eq!(rows[0].get::<_, String>(1), "127.0.0.1");
+    }
+
+    // only endpoints meant for it answer GET requests
+    let resp = reqwest::get(server.url("AddCrmLead")).await?;
+    assert_eq!(resp.status().as_u16(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn captcha_must_be_solved_before_leads_are_accepted() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "spam_protection": {
+                "captcha": { "provider": "local", "secret": "test-captcha-solution" }
+            }
+        }),
+    )
+    .await?;
+
+    for token in [None, Some("wrong-solution")] {
+        let email = unique_email();
+        let mut req = add_crm_lead(&email);
+        if let Some(token) = token {
+            req["captchaToken"] = json!(token);
+        }
+        let (status, body) = server.call("AddCrmLead", req).await?;
+        assert_eq!(status, 422, "{}", body);
+        assert_eq!(body["code"], json!(101422));
+        assert_eq!(body["symbol"], json!("CaptchaFailed"));
+    }
+
+    let email = unique_email();
+    let mut req = add_crm_lead(&email);
+    req["captchaToken"] = json!("test-captcha-solution");
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+    drop(server);
+
+    // an unreachable provider is a temporary failure, not a failed CAPTCHA
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "spam_protection": {
+                "captcha": {
+                    "provider": "turnstile",
+                    "secret": "test-captcha-secret",
+                    "siteverify_url": "http://127.0.0.1:1/siteverify",
+                }
+            }
+        }),
+    )
+    .await?;
+    let mut req = add_crm_lead(&unique_email());
+    req["captchaToken"] = json!("test-captcha-solution");
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(body["symbol"], json!("TemporarilyUnavailable"));
+    Ok(())
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// First counter whose hash of `{challenge}:{counter}` does, or with `solve` false does not,
+/// start with `difficulty` zero bits
+fn pow_solution(challenge: &str, difficulty: u32, solve: bool) -> String {
+    use sha2::{Digest, Sha256};
+    (0u64..)
+        .map(|x| x.to_string())
+        .find(|x| {
+            let hash = Sha256::digest(format!("{}:{}", challenge, x).as_bytes());
+            (leading_zero_bits(&hash) >= difficulty) == solve
+        })
+        .unwrap()
+}
+
+#[tokio::test]
+async fn proof_of_work_gets_harder_with_recent_submissions() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "spam_protection": {
+                "action": "quarantine",
+                "proof_of_work": {
+                    "secret": "test-pow-secret",
+                    "base_difficulty": 4,
+                    "ip_submissions_per_bit": 2,
+                }
+            }
+        }),
+    )
+    .await?;
+    let db = connect_app_db().await?;
+    let issue_challenge = || async {
+        let resp = reqwest::get(server.url("IssuePowChallenge")).await?;
+        ensure!(
+            resp.status() == 200,
+            "IssuePowChallenge answered {}",
+            resp.status()
+        );
+        let body: Value = resp.json().await?;
+        let challenge = body["challenge"].as_str().unwrap_or_default().to_owned();
+        Ok::<_, Error>((
+            challenge,
+            body["difficulty"].as_u64().unwrap_or_default() as u32,
+        ))
+    };
+
+    let (_, difficulty) = issue_challenge().await?;
+    assert_eq!(difficulty, 4);
+    let missing = unique_email();
+    let (status, body) = server.call("AddCrmLead", add_crm_lead(&missing)).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    let (challenge, difficulty) = issue_challenge().await?;
+    assert_eq!(difficulty, 4);
+    let email = unique_email();
+    let mut req = add_crm_lead(&email);
+    req["powChallenge"] = json!(challenge);
+    req["powSolution"] = json!(pow_solution(&challenge, difficulty, true));
+    let (status, body) = server.call("AddCrmLead", req.clone()).await?;
+    assert_eq!(status, 200, "{}", body);
+    wait_for(DELIVERY_TIMEOUT, || leads_of(&mock, &email).pop()).await?;
+
+    // a solved challenge is accepted once
+    let replayed = unique_email();
+    req["email"] = json!(replayed);
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    // three submissions of this IP so far, a bit per two
+    let (challenge, difficulty) = issue_challenge().await?;
+    assert_eq!(difficulty, 5);
+    let unsolved = unique_email();
+    let mut req = add_crm_lead(&unsolved);
+    req["powChallenge"] = json!(challenge);
+    req["powSolution"] = json!(pow_solution(&challenge, difficulty, false));
+    let (status, body) = server.call("AddCrmLead", req).await?;
+    assert_eq!(status, 200, "{}", body);
+
+    for (email, reason) in [
+        (&missing, "missing_pow"),
+        (&replayed, "replayed_pow"),
+        (&unsolved, "invalid_pow"),
+    ] {
+        assert!(leads_of(&mock, email).is_empty(), "{}", reason);
+        let rows = db
+            .query(
+                "SELECT reason FROM tbl.quarantined_lead WHERE email = $1",
+                &[email],
+            )
+            .await?;
+        assert_eq!(rows.len(), 1, "{}", reason);
+        assert_eq!(rows[0].get::<_, String>(0), reason);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn lead_emails_are_validated_and_normalized() -> Result<()> {
+    let mock = PipeDriveMock::start(PIPEDRIVE_API_TOKEN).await?;
+    let dir = tempfile::tempdir()?;
+    let domains_file = dir.path().join("disposable_domains.txt");
+    std::fs::write(&domains_file, "# local additions\nblocked.example\n")?;
+    let server = UserServer::start(
+        &mock,
+        json!({
+            "rate_limits": { "AddCrmLead": { "requests_per_minute": 0, "burst": 0 } },
+            "email_validation": {
+                "disposable_domains_file": domains_file,
+                "reject_role_accounts": true,
+            }
+        }),
+    )
+    .await?;
+
+    for (email, reason) in [
+        ("not-an-email", "invalid_syntax"),
+        ("two..dots@example.com", "invalid_syntax"),
+        ("\"quoted\"@example.com", "invalid_syntax"),
+        ("lead@localhost", "invalid_domain"),
+        ("lead@[127.0.0.1]", "invalid_domain"),
+        ("lead@-example.com", "invalid_domain"),
+        ("lead@mailinator.com", "disposable_domain"),
+        ("lead@inbox.YOPmail.com", "disposable_domain"),
+        ("lead@blocked.example", "disposable_domain"),
+        ("Info+web@example.com", "role_account"),
+    ] {
+        let (status, body) = server.call("AddCrmLead", add_crm_lead(email)).await?;
+        assert_eq!(status, 400, "{}: {}", email, body);
+        assert_eq!(body["code"], json!(45349633), "{}", email);
+        assert_eq!(body["symbol"], json!("InvalidArgument"), "{}", email);
+        assert_eq!(
+            body["params"],
+            json!({ "errors": [{ "field": "email", "reason": reason }] }),
+            "{}",
+            email
+        );
+    }
+
+    // the plus of the tag has to survive the person search, or every lead creates a person
+    let id = uuid::Uuid::new_v4();
+    let submitted = format!(" Lead-{}+News@Bücher.Example ", id);
+    let email = format!("lead-{}+news@xn--bcher-kva.example", id);
+    for _ in 0..2 {
+        let (status, body) = server.call("AddCrmLead", add_crm_lead(&submitted)).await?;
+        assert_eq!(status, 200, "{}", body);
+    }
+    wait_for(DELIVERY_TIMEOUT, || {
+        Some(leads_of(&mock, &email)).filter(|x| x.len() == 2)
+    })
+    .await?;
+    let persons = mock
+        .persons()
+        .into_iter()
+        .filter(|p| p["primary_email"].as_str() == Some(email.as_str()))
+        .count();
+    assert_eq!(persons, 1);
+    Ok(())
+}